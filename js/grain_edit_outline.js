@@ -0,0 +1,150 @@
+console.log("grain_edit_outline.js");
+var image;
+var canvas;
+var scale = 1.0;
+var polygon = [];
+var stored_polygon = [];
+var axis;
+var dragged = -1;
+
+// Distance in canvas pixels at which a click hits a vertex.
+var vertex_radius = 6;
+
+window.addEventListener("load", function(){
+    image = document.getElementById("grain_image");
+    canvas = document.getElementById("grain_canvas");
+
+    if (image && canvas) {
+      canvas.width = image.naturalWidth;
+      canvas.height = image.naturalHeight;
+
+      // The page shows the medium preview, the outline is stored in pixels of the working image.
+      var working_width = parseInt(image.dataset.width);
+      if (working_width > 0 && image.naturalWidth > 0) {
+        scale = working_width / image.naturalWidth;
+      }
+
+      stored_polygon = JSON.parse(document.getElementsByName("coordinates")[0].value);
+      axis = JSON.parse(document.getElementsByName("axis")[0].value);
+      reset_polygon();
+
+      canvas.addEventListener("mousedown", start_drag, true);
+      canvas.addEventListener("mousemove", drag, true);
+      canvas.addEventListener("mouseup", stop_drag, true);
+      canvas.addEventListener("mouseleave", stop_drag, true);
+      canvas.addEventListener("dblclick", add_or_delete_vertex, true);
+    }
+});
+
+function reset_polygon() {
+  polygon = stored_polygon.map(function(point) {
+    return {x: point.x / scale, y: point.y / scale};
+  });
+
+  redraw_polygon();
+}
+
+function nearest_vertex(x, y) {
+  var nearest = -1;
+  var nearest_distance = vertex_radius;
+
+  for (var i = 0; i < polygon.length; i++) {
+    var distance = Math.hypot(polygon[i].x - x, polygon[i].y - y);
+    if (distance <= nearest_distance) {
+      nearest = i;
+      nearest_distance = distance;
+    }
+  }
+
+  return nearest;
+}
+
+// Index of the edge (from vertex i to vertex i + 1) that is closest to the point.
+function nearest_edge(x, y) {
+  var nearest = -1;
+  var nearest_distance = Infinity;
+
+  for (var i = 0; i < polygon.length; i++) {
+    var p1 = polygon[i];
+    var p2 = polygon[(i + 1) % polygon.length];
+    var dx = p2.x - p1.x;
+    var dy = p2.y - p1.y;
+    var length = dx * dx + dy * dy;
+    var t = length > 0 ? Math.max(0, Math.min(1, ((x - p1.x) * dx + (y - p1.y) * dy) / length)) : 0;
+    var distance = Math.hypot(p1.x + t * dx - x, p1.y + t * dy - y);
+
+    if (distance < nearest_distance) {
+      nearest = i;
+      nearest_distance = distance;
+    }
+  }
+
+  return nearest;
+}
+
+function start_drag(evt) {
+  dragged = nearest_vertex(evt.offsetX, evt.offsetY);
+}
+
+function drag(evt) {
+  if (dragged >= 0) {
+    polygon[dragged] = {x: evt.offsetX, y: evt.offsetY};
+    redraw_polygon();
+  }
+}
+
+function stop_drag(evt) {
+  dragged = -1;
+}
+
+function add_or_delete_vertex(evt) {
+  var vertex = nearest_vertex(evt.offsetX, evt.offsetY);
+
+  if (vertex >= 0) {
+    if (polygon.length > 3) {
+      polygon.splice(vertex, 1);
+    }
+  } else if (polygon.length < 3) {
+    polygon.push({x: evt.offsetX, y: evt.offsetY});
+  } else {
+    polygon.splice(nearest_edge(evt.offsetX, evt.offsetY) + 1, 0, {x: evt.offsetX, y: evt.offsetY});
+  }
+
+  redraw_polygon();
+}
+
+function redraw_polygon() {
+  var context = canvas.getContext("2d");
+  context.drawImage(image, 0, 0);
+
+  if (polygon.length > 0) {
+    context.strokeStyle = "#FF0000";
+    context.lineWidth = 1;
+    context.beginPath();
+    context.moveTo(polygon[0].x, polygon[0].y);
+    for (var i = 1; i < polygon.length; i++) {
+      context.lineTo(polygon[i].x, polygon[i].y);
+    }
+    context.closePath();
+    context.stroke();
+
+    context.fillStyle = "#FFFF00";
+    for (var i = 0; i < polygon.length; i++) {
+      context.fillRect(polygon[i].x - 2, polygon[i].y - 2, 4, 4);
+    }
+  }
+
+  if (axis && (axis.x1 != axis.x2 || axis.y1 != axis.y2)) {
+    context.strokeStyle = "#00FF00";
+    context.beginPath();
+    context.moveTo(axis.x1 / scale, axis.y1 / scale);
+    context.lineTo(axis.x2 / scale, axis.y2 / scale);
+    context.stroke();
+  }
+}
+
+function submit_polygon() {
+  document.getElementsByName("coordinates")[0].value = JSON.stringify(polygon.map(function(point) {
+    return {x: Math.max(0, point.x * scale), y: Math.max(0, point.y * scale)};
+  }));
+}
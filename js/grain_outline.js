@@ -1,8 +1,10 @@
 console.log("grain_outline.js");
 var images;
+var working_images;
 var canvases;
 var num_of_images;
 var bw_threshold;
+var threshold_method;
 var corner_points;
 var axis_points;
 var axis_mode;
@@ -17,10 +19,14 @@ window.addEventListener("load", function(){
       num_of_images = images.length;
       console.log("number of images: " + num_of_images);
 
+      // The server chooses the starting threshold (stored with the outline or from Otsu's method).
       if (!bw_threshold) {
         bw_threshold = [];
+        threshold_method = [];
         for (var i = 0; i < num_of_images; i++) {
-          bw_threshold.push(0.5);
+          var value = parseFloat(images[i].dataset.bwThreshold);
+          bw_threshold.push(isNaN(value) ? 0.5 : value);
+          threshold_method.push(images[i].dataset.thresholdMethod || "manual");
         }
       }
 
@@ -45,18 +51,37 @@ window.addEventListener("load", function(){
         }
       }
 
+      // The page shows previews, the outline is traced on the working image so that it has one row pair per pixel row.
+      // The canvas has the size of the working image and is shown with the size of the preview.
+      working_images = [];
       for (let i = 0; i < num_of_images; i++) {
-        canvases[i].width = images[i].width;
-        canvases[i].height = images[i].height;
+        var working_image = new Image();
+        working_images.push(working_image);
 
         canvases[i].addEventListener("mouseup", function(evt) { set_axis(evt, i) }, true);
 
-        redraw_image(i);
+        working_image.addEventListener("load", function() {
+          canvases[i].width = working_images[i].naturalWidth;
+          canvases[i].height = working_images[i].naturalHeight;
+          canvases[i].style.width = images[i].width + "px";
+
+          redraw_image(i);
+        });
+        working_image.src = images[i].dataset.workingSrc;
       }
       console.log("image processing finished");
     }
 });
 
+// Pixels of the working image per pixel shown on the page.
+function canvas_scale(image_index) {
+  if (canvases[image_index].clientWidth > 0) {
+    return canvases[image_index].width / canvases[image_index].clientWidth;
+  }
+
+  return 1.0;
+}
+
 function set_axis(evt, image_index) {
   /*
   console.log("offsetX: " + evt.offsetX);
@@ -65,13 +90,15 @@ function set_axis(evt, image_index) {
   console.log("mode: " + axis_mode[image_index]);
   */
 
+  var scale = canvas_scale(image_index);
+
   if (axis_mode[image_index] == 0) {
-    axis_points[image_index].x1 = evt.offsetX;
-    axis_points[image_index].y1 = evt.offsetY;
+    axis_points[image_index].x1 = Math.round(evt.offsetX * scale);
+    axis_points[image_index].y1 = Math.round(evt.offsetY * scale);
     axis_mode[image_index] = 1;
   } else if (axis_mode[image_index] == 1) {
-    axis_points[image_index].x2 = evt.offsetX;
-    axis_points[image_index].y2 = evt.offsetY;
+    axis_points[image_index].x2 = Math.round(evt.offsetX * scale);
+    axis_points[image_index].y2 = Math.round(evt.offsetY * scale);
     axis_mode[image_index] = 2;
   }
 
@@ -102,11 +129,18 @@ function laplace_image(pixel_data) {
 }
 
 function redraw_image(image_index) {
-  if (images && canvases && axis_points) {
+  if (working_images && canvases && axis_points) {
     if (image_index >= 0 && image_index < num_of_images) {
+      show_bw_threshold(image_index);
+
+      // Not loaded yet, it is drawn by its load handler.
+      if (!working_images[image_index].complete || working_images[image_index].naturalWidth == 0) {
+        return;
+      }
+
       var context = canvases[image_index].getContext("2d");
-      context.drawImage(images[image_index], 0, 0);
-      var pixel_data = context.getImageData(0, 0, images[image_index].width, images[image_index].height);
+      context.drawImage(working_images[image_index], 0, 0);
+      var pixel_data = context.getImageData(0, 0, canvases[image_index].width, canvases[image_index].height);
 
       gauss_blur(pixel_data);
       bw_image(pixel_data, image_index);
@@ -119,6 +153,7 @@ function redraw_image(image_index) {
       context.moveTo(axis_points[image_index].x1, axis_points[image_index].y1);
       context.lineTo(axis_points[image_index].x2, axis_points[image_index].y2);
       context.strokeStyle = "#ffff00";
+      context.lineWidth = canvas_scale(image_index);
       context.stroke();
     }
   }
@@ -241,9 +276,26 @@ function bw_image(pixel_data, image_index) {
   }
 }
 
+function show_bw_threshold(image_index) {
+  var labels = document.getElementsByName("bw_threshold_label");
+  var values = document.getElementsByName("bw_thresholds");
+  var methods = document.getElementsByName("threshold_methods");
+
+  if (labels[image_index]) {
+    labels[image_index].textContent = bw_threshold[image_index].toFixed(2) + " (" + threshold_method[image_index] + ")";
+  }
+  if (values[image_index]) {
+    values[image_index].value = bw_threshold[image_index];
+  }
+  if (methods[image_index]) {
+    methods[image_index].value = threshold_method[image_index];
+  }
+}
+
 function inc_bw_threshold(image_index) {
   if (images) {
     if (image_index >= 0 && image_index < num_of_images) {
+      threshold_method[image_index] = "manual";
       bw_threshold[image_index] += 0.05;
       if (bw_threshold[image_index] > 1.0) {
         bw_threshold[image_index] = 1.0
@@ -257,6 +309,7 @@ function inc_bw_threshold(image_index) {
 function dec_bw_threshold(image_index) {
   if (images) {
     if (image_index >= 0 && image_index < num_of_images) {
+      threshold_method[image_index] = "manual";
       bw_threshold[image_index] -= 0.05;
       if (bw_threshold[image_index] < 0.0) {
         bw_threshold[image_index] = 0.0
@@ -272,7 +325,8 @@ function submit_coordinates() {
   var axis = document.getElementsByName("axis");
   if (coordinates) {
     for (var i = 0; i < num_of_images; i++) {
-      coordinates[i].value = JSON.stringify(corner_points[i]);
+      var points = Array.isArray(corner_points[i]) ? corner_points[i] : [];
+      coordinates[i].value = JSON.stringify(points);
     }
   }
 
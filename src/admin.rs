@@ -0,0 +1,45 @@
+use rouille::{Response};
+use failure;
+
+use util::{self, UserRole};
+use programs::grain;
+
+pub fn usage_get(session_id: &str) -> Result<Response, failure::Error> {
+    debug!("admin.rs, usage_get()");
+    if util::logged_in(session_id)? {
+        let (user_name, user_id) = util::login_id(session_id)?;
+
+        if util::user_role(user_id)? == UserRole::Admin {
+            let allowed_programs = util::list_of_allowed_programs(user_id)?;
+            let mut users = Vec::new();
+
+            for (id, login_id, role) in util::list_of_users()? {
+                let usage = grain::storage_usage(id, &login_id)?;
+
+                users.push(json!({
+                    "login_id": login_id,
+                    "role": role.name(),
+                    "bytes": util::format_bytes(usage.bytes),
+                    "max_bytes": usage.quota.max_bytes.map(util::format_bytes),
+                    "images": usage.images,
+                    "max_images": usage.quota.max_images,
+                    "samples": usage.samples,
+                    "max_samples": usage.quota.max_samples,
+                }));
+            }
+
+            let context = json!({
+                "login_id": user_name,
+                "programs": util::build_program_menu(&allowed_programs),
+                "usage": util::build_usage(user_id, &user_name)?,
+                "users": users,
+            });
+
+            Ok(Response::html(util::render("admin_usage", &context)?))
+        } else {
+            Ok(Response::redirect_303("/web_gui/"))
+        }
+    } else {
+        Ok(Response::redirect_303("/web_gui/"))
+    }
+}
@@ -3,6 +3,7 @@ use std::process;
 use std::env;
 use std::fs;
 use std::{thread, time};
+use std::collections::HashMap;
 
 use toml;
 use failure;
@@ -17,17 +18,151 @@ lazy_static! {
             grain_db: "not_set".to_string(),
             matlab_exec: "not_set".to_string(),
             matlab_folder: "not_set".to_string(),
+            trash_retention_days: default_trash_retention_days(),
+            calculation_workers: default_calculation_workers(),
+            timeouts: HashMap::new(),
+            executors: HashMap::new(),
+            quotas: QuotaConfiguration::default(),
+            rescaling: Rescaling::default(),
         })
     };
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 struct Configuration {
     log_filename: String,
     user_db: String,
     grain_db: String,
+    /// Used by the default executor of 3D-He when none is configured for it.
+    #[serde(default)]
     matlab_exec: String,
+    #[serde(default)]
     matlab_folder: String,
+    #[serde(default = "default_trash_retention_days")]
+    trash_retention_days: u64,
+    /// Calculations that run at the same time, each one can hold a matlab licence.
+    #[serde(default = "default_calculation_workers")]
+    calculation_workers: usize,
+    /// Wall-clock limit (seconds) of a calculation by engine name ("3d-he", "monte-carlo"), unset means no limit.
+    #[serde(default)]
+    timeouts: HashMap<String, u64>,
+    /// How each calculation program is run, by engine name.
+    #[serde(default)]
+    executors: HashMap<String, Executor>,
+    #[serde(default)]
+    quotas: QuotaConfiguration,
+    #[serde(default)]
+    rescaling: Rescaling,
+}
+
+/// Runs a calculation program. The external ones read the input written to the folder of the sample
+/// and write result.txt next to it, the native ones are calculated in the web gui itself.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "backend", rename_all = "kebab-case")]
+pub enum Executor {
+    /// The script is run by matlab in the given folder.
+    Matlab {
+        exec: String,
+        folder: String,
+        #[serde(default = "default_script")]
+        script: String,
+    },
+    /// Same as matlab, with GNU Octave.
+    Octave {
+        exec: String,
+        folder: String,
+        #[serde(default = "default_script")]
+        script: String,
+    },
+    /// Run by "sh -c", {input_file}, {output_file} and {grain_folder} are replaced by the quoted paths.
+    Command {
+        command: String,
+    },
+    Native {
+        engine: NativeEngine,
+    },
+}
+
+/// Engines of the web gui that write result.txt without an external program.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NativeEngine {
+    MonteCarlo,
+    Analytical,
+}
+
+/// Storage limits, given per role ("Admin", "User") and per login id.
+/// A limit set for a login id overrides the one of the role.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize)]
+struct QuotaConfiguration {
+    #[serde(default)]
+    roles: HashMap<String, Quota>,
+    #[serde(default)]
+    users: HashMap<String, Quota>,
+}
+
+/// Unset fields mean no limit.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Quota {
+    pub max_bytes: Option<u64>,
+    pub max_images: Option<usize>,
+    pub max_samples: Option<usize>,
+}
+
+/// How uploaded grain images are resized to the working image used for the outline and the calculation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rescaling {
+    /// Pixel size of the working image in micrometer.
+    #[serde(default = "default_target_pixel_size")]
+    pub target_pixel_size: f64,
+    #[serde(default)]
+    pub filter: ResizeFilter,
+}
+
+impl Default for Rescaling {
+    fn default() -> Rescaling {
+        Rescaling {
+            target_pixel_size: default_target_pixel_size(),
+            filter: ResizeFilter::default(),
+        }
+    }
+}
+
+/// Resampling filters offered for resizing, from fastest to smoothest.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ResizeFilter {
+    #[default]
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+}
+
+impl ResizeFilter {
+    pub fn all() -> [ResizeFilter; 5] {
+        [ResizeFilter::Nearest, ResizeFilter::Triangle, ResizeFilter::CatmullRom, ResizeFilter::Gaussian, ResizeFilter::Lanczos3]
+    }
+
+    pub fn from_name(name: &str) -> Option<ResizeFilter> {
+        ResizeFilter::all().iter().find(|filter| format!("{:?}", filter).eq_ignore_ascii_case(name)).cloned()
+    }
+}
+
+fn default_target_pixel_size() -> f64 {
+    2.0
+}
+
+fn default_script() -> String {
+    "run_3DFt.m".to_string()
+}
+
+fn default_trash_retention_days() -> u64 {
+    30
+}
+
+fn default_calculation_workers() -> usize {
+    1
 }
 
 fn get_db_lock<'a>() -> MutexGuard<'a, Configuration> {
@@ -46,13 +181,16 @@ fn get_db_lock<'a>() -> MutexGuard<'a, Configuration> {
 
 
 
-pub fn load_configuration() {
+/// Loads the configuration file given on the command line and returns the
+/// optional admin command that follows it (ex.: "orphans").
+pub fn load_configuration() -> Option<String> {
     debug!("configuration.rs, load_configuration()");
     let input: Vec<String> = env::args().collect();
 
-    match load_configuration_helper(input) {
+    match load_configuration_helper(&input) {
         Ok(_) => {
             println!("Configuration loaded successfully");
+            input.get(2).cloned()
         }
         Err(e) => {
             println!("Error loading configuration file: {}", e);
@@ -61,9 +199,9 @@ pub fn load_configuration() {
     }
 }
 
-fn load_configuration_helper(input: Vec<String>) -> Result<(), failure::Error> {
+fn load_configuration_helper(input: &[String]) -> Result<(), failure::Error> {
     debug!("configuration.rs, load_configuration_helper()");
-    if input.len() == 2 {
+    if input.len() == 2 || input.len() == 3 {
         let filename = &input[1];
         println!("Try to open file '{}'", filename);
         let content = fs::read_to_string(filename)?;
@@ -73,7 +211,7 @@ fn load_configuration_helper(input: Vec<String>) -> Result<(), failure::Error> {
         *configuration = new_configuration;
         Ok(())
     } else {
-        println!("Usage: {} config_filename", input[0]);
+        println!("Usage: {} config_filename [orphans]", input[0]);
         Err(WebGuiError::InvalidCommandLineArguments.into())
     }
 }
@@ -96,14 +234,63 @@ pub fn grain_db() -> String {
     configuration.grain_db.clone()
 }
 
-pub fn matlab_exec() -> String {
-    debug!("configuration.rs, grain_db()");
+/// True if the config file sets an executor for the engine.
+pub fn executor_configured(engine: &str) -> bool {
+    debug!("configuration.rs, executor_configured()");
     let configuration = get_db_lock();
-    configuration.matlab_exec.clone()
+    configuration.executors.contains_key(engine)
 }
 
-pub fn matlab_folder() -> String {
-    debug!("configuration.rs, grain_db()");
+/// The configured executor of the engine. Without one 3D-He runs in matlab_exec and the other engines are native.
+pub fn executor(engine: &str) -> Executor {
+    debug!("configuration.rs, executor()");
     let configuration = get_db_lock();
-    configuration.matlab_folder.clone()
+
+    match configuration.executors.get(engine) {
+        Some(executor) => executor.clone(),
+        None if engine == "3d-he" => Executor::Matlab {
+            exec: configuration.matlab_exec.clone(),
+            folder: configuration.matlab_folder.clone(),
+            script: default_script(),
+        },
+        None if engine == "analytical" => Executor::Native { engine: NativeEngine::Analytical },
+        None => Executor::Native { engine: NativeEngine::MonteCarlo },
+    }
+}
+
+pub fn trash_retention_days() -> u64 {
+    debug!("configuration.rs, trash_retention_days()");
+    let configuration = get_db_lock();
+    configuration.trash_retention_days
+}
+
+pub fn calculation_workers() -> usize {
+    debug!("configuration.rs, calculation_workers()");
+    let configuration = get_db_lock();
+    configuration.calculation_workers
+}
+
+pub fn job_timeout(engine: &str) -> Option<u64> {
+    debug!("configuration.rs, job_timeout()");
+    let configuration = get_db_lock();
+    configuration.timeouts.get(engine).cloned()
+}
+
+pub fn rescaling() -> Rescaling {
+    debug!("configuration.rs, rescaling()");
+    let configuration = get_db_lock();
+    configuration.rescaling.clone()
+}
+
+pub fn quota(login_id: &str, role: &str) -> Quota {
+    debug!("configuration.rs, quota()");
+    let configuration = get_db_lock();
+    let role_quota = configuration.quotas.roles.get(role).cloned().unwrap_or_default();
+    let user_quota = configuration.quotas.users.get(login_id).cloned().unwrap_or_default();
+
+    Quota {
+        max_bytes: user_quota.max_bytes.or(role_quota.max_bytes),
+        max_images: user_quota.max_images.or(role_quota.max_images),
+        max_samples: user_quota.max_samples.or(role_quota.max_samples),
+    }
 }
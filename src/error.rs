@@ -18,8 +18,44 @@ pub enum WebGuiError {
     NoFilenameForGrainImage,
     #[fail(display = "Grain / sample image not found for user")]
     GrainImageNotFoundForUser,
+    #[fail(display = "Sample not found for user")]
+    SampleNotFoundForUser,
+    #[fail(display = "User is not allowed to change that sample")]
+    SampleNotEditableForUser,
+    #[fail(display = "Project not found for user")]
+    ProjectNotFoundForUser,
+    #[fail(display = "Preset not found for user")]
+    PresetNotFoundForUser,
+    #[fail(display = "Only the owner can change a preset")]
+    PresetNotEditableForUser,
+    #[fail(display = "Invalid workspace archive")]
+    InvalidArchive,
+    #[fail(display = "Unsupported image format")]
+    UnsupportedImageFormat,
+    #[fail(display = "Unknown outline method")]
+    UnknownOutlineMethod,
+    #[fail(display = "Unknown calculation engine")]
+    UnknownCalculationEngine,
+    #[fail(display = "Job not found or no log written for it")]
+    JobNotFoundForUser,
+    #[fail(display = "The list of running jobs is not available")]
+    JobRegistryPoisoned,
+    #[fail(display = "The Monte Carlo engine needs an outlined image with the c-axis parallel and one with the c-axis perpendicular to the field of view")]
+    MonteCarloViewsMissing,
+    #[fail(display = "Unknown threshold method or missing threshold")]
+    UnknownThresholdMethod,
+    #[fail(display = "Invalid outline")]
+    InvalidOutline,
+    #[fail(display = "Outline revision not found")]
+    OutlineRevisionNotFound,
+    #[fail(display = "Images can only be moved to samples of the same owner")]
+    ImageMovedToOtherOwner,
+    #[fail(display = "A file of the image already exists in the target sample")]
+    ImageFileExistsInSample,
     #[fail(display = "User is not allowed to use that program")]
     ProgramNotAllowedForUser,
     #[fail(display = "User in not logged in")]
     UserNotLoggedIn,
+    #[fail(display = "Unknown admin command")]
+    UnknownAdminCommand,
 }
@@ -12,11 +12,16 @@ extern crate log4rs;
 extern crate toml;
 extern crate image;
 extern crate itertools;
+extern crate chrono;
+extern crate zip;
+extern crate csv;
+extern crate sha2;
 
 // Request handler:
 mod menu;
 mod login;
 mod logout;
+mod admin;
 mod programs;
 
 // Helper / utils:
@@ -26,6 +31,7 @@ mod util;
 mod program_types;
 
 use std::fs::File;
+use std::process;
 
 use rouille::{Request, Response};
 
@@ -41,7 +47,7 @@ fn main() {
     return;
     */
 
-    configuration::load_configuration();
+    let admin_command = configuration::load_configuration();
 
     let file_logger = log4rs::append::file::FileAppender::builder()
         .encoder(Box::new(log4rs::encode::pattern::PatternEncoder::new("{d} {l} - {m}{n}")))
@@ -57,6 +63,18 @@ fn main() {
     util::load_db().unwrap();
     grain::load_db().unwrap();
 
+    if let Some(command) = admin_command {
+        process::exit(match run_admin_command(&command) {
+            Ok(_) => 0,
+            Err(e) => {
+                println!("Error running admin command '{}': {}", command, e);
+                1
+            }
+        });
+    }
+
+    grain::start_trash_purger();
+    grain::start_job_workers();
 
     let addr = "0.0.0.0:3030";
     println!("Now listening on {}", addr);
@@ -79,6 +97,22 @@ fn main() {
     });
 }
 
+fn run_admin_command(command: &str) -> Result<(), failure::Error> {
+    debug!("main.rs, run_admin_command()");
+
+    match command {
+        "orphans" => {
+            let orphans = grain::find_orphaned_files()?;
+            for file in orphans.iter() {
+                println!("{}", file);
+            }
+            println!("{} orphaned file(s) found", orphans.len());
+            Ok(())
+        }
+        _ => Err(error::WebGuiError::UnknownAdminCommand.into()),
+    }
+}
+
 fn handle_request(request: &Request, session_id: &str) -> Result<Response, failure::Error> {
     debug!("main.rs, handle_request()");
 
@@ -93,6 +127,11 @@ fn handle_request(request: &Request, session_id: &str) -> Result<Response, failu
             logout::handle(session_id)?
         },
 
+        // Administration:
+        (GET) ["/web_gui/admin/usage"] => {
+            admin::usage_get(session_id)?
+        },
+
         // Pecube:
         (GET) ["/web_gui/pecube"] => {
             pecube::about_get(session_id)?
@@ -102,15 +141,45 @@ fn handle_request(request: &Request, session_id: &str) -> Result<Response, failu
         (GET) ["/web_gui/grain"] => {
             grain::about_get(session_id)?
         },
+        (GET) ["/web_gui/grain/samples"] => {
+            grain::samples_get(session_id)?
+        },
+        (POST) ["/web_gui/grain/samples/create"] => {
+            grain::create_sample_post(session_id, request)?
+        },
+        (POST) ["/web_gui/grain/samples/update"] => {
+            grain::update_sample_post(session_id, request)?
+        },
+        (GET) ["/web_gui/grain/export"] => {
+            grain::export_get(session_id, request)?
+        },
+        (POST) ["/web_gui/grain/import"] => {
+            grain::import_post(session_id, request)?
+        },
+        (GET) ["/web_gui/grain/projects"] => {
+            grain::projects_get(session_id)?
+        },
+        (POST) ["/web_gui/grain/projects/create"] => {
+            grain::create_project_post(session_id, request)?
+        },
+        (POST) ["/web_gui/grain/projects/update"] => {
+            grain::update_project_post(session_id, request)?
+        },
         (GET) ["/web_gui/grain/load_images"] => {
-            grain::load_images_get(session_id)?
+            grain::load_images_get(session_id, request)?
         },
         (POST) ["/web_gui/grain/load_images"] => {
             grain::load_images_post(session_id, request)?
         },
+        (POST) ["/web_gui/grain/bulk_upload"] => {
+            grain::bulk_upload_post(session_id, request)?
+        },
         (POST) ["/web_gui/grain/remove_images"] => {
             grain::remove_images_post(session_id, request)?
         },
+        (POST) ["/web_gui/grain/trash"] => {
+            grain::trash_post(session_id, request)?
+        },
         (GET) ["/web_gui/grain/outline_images"] => {
             grain::outline_images_get(session_id)?
         },
@@ -120,20 +189,63 @@ fn handle_request(request: &Request, session_id: &str) -> Result<Response, failu
         (POST) ["/web_gui/grain/store_outlines"] => {
             grain::store_outline_post(session_id, request)?
         },
+        (POST) ["/web_gui/grain/presets/create"] => {
+            grain::create_preset_post(session_id, request)?
+        },
+        (POST) ["/web_gui/grain/presets/from_image"] => {
+            grain::preset_from_image_post(session_id, request)?
+        },
+        (POST) ["/web_gui/grain/presets/update"] => {
+            grain::update_preset_post(session_id, request)?
+        },
+        (GET) ["/web_gui/grain/edit_image"] => {
+            grain::edit_image_get(session_id, request)?
+        },
+        (POST) ["/web_gui/grain/edit_image"] => {
+            grain::edit_image_post(session_id, request)?
+        },
+        (GET) ["/web_gui/grain/edit_outline"] => {
+            grain::edit_outline_get(session_id, request)?
+        },
+        (POST) ["/web_gui/grain/revert_outline"] => {
+            grain::revert_outline_post(session_id, request)?
+        },
+        (GET) ["/web_gui/grain/overlay"] => {
+            grain::overlay_get(session_id, request)?
+        },
+        (GET) ["/web_gui/grain/contact_sheet"] => {
+            grain::contact_sheet_get(session_id, request)?
+        },
+        (POST) ["/web_gui/grain/cancel_job"] => {
+            grain::cancel_job_post(session_id, request)?
+        },
+        (GET) ["/web_gui/grain/job_log"] => {
+            grain::job_log_get(session_id, request)?
+        },
         (GET) ["/web_gui/grain/calculate"] => {
             grain::calculate_get(session_id)?
         },
+        (POST) ["/web_gui/grain/detect_outline"] => {
+            grain::detect_outline_post(session_id, request)?
+        },
+        (POST) ["/web_gui/grain/detect_outlines"] => {
+            grain::detect_outlines_post(session_id, request)?
+        },
         (POST) ["/web_gui/grain/calculate"] => {
             grain::calculate_post(session_id, request)?
         },
 
         (GET) ["/web_gui/grain/user_data/{username}/{samplename}/{imagename}", username: String, samplename: String, imagename: String] => {
-            grain::sample_image_get(session_id, username, samplename, imagename)?
+            grain::sample_image_get(session_id, username, samplename, imagename, request.get_param("size"))?
         },
         (GET) ["/web_gui/js/grain_outline.js"] => {
             let file = File::open("js/grain_outline.js")?;
             Response::from_file("text/javascript", file)
         },
+        (GET) ["/web_gui/js/grain_edit_outline.js"] => {
+            let file = File::open("js/grain_edit_outline.js")?;
+            Response::from_file("text/javascript", file)
+        },
         (GET) ["/web_gui/js/grain_refresh.js"] => {
             let file = File::open("js/grain_refresh.js")?;
             Response::from_file("text/javascript", file)
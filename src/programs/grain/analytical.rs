@@ -0,0 +1,511 @@
+//! Alpha ejection correction (Ft) of the idealized grains of Ketcham et al. 2011 (hexagonal prisms with
+//! 0 to 2 pyramids, ellipsoids, cylinders and blocks) with the stopping distances of that paper.
+//! Ft of a shape is the volume it shares with itself shifted by the stopping distance, averaged over
+//! all directions and divided by its volume: the fraction of the alpha particles that stop inside.
+//! Ellipsoids are stretched spheres and use the sphere equation, prisms are integrated over their cross
+//! sections. The Ft values of the parent isotopes are weighted by their He production.
+//! Lengths are in micrometer. Assumes a homogeneous distribution of the parent nuclides.
+
+use std::f64::consts::PI;
+
+use super::parameters::{Mineral, Shape};
+
+/// Height of a pyramid termination relative to the width of the prism.
+const PYRAMID_HEIGHT_RATIO: f64 = 0.5;
+
+/// Steps of the midpoint rule over the cosine of the angle to the c-axis and over the azimuth.
+const POLAR_STEPS: usize = 32;
+const AZIMUTH_STEPS: usize = 4;
+
+/// Simpson intervals of each piece along the c-axis between the ends of pyramids, the shared cross section
+/// still bends inside a piece where one section starts to lie within the other.
+const SLICE_STEPS: usize = 16;
+
+/// Atoms 238U per atom 235U in natural uranium.
+const U238_U235_RATIO: f64 = 137.88;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Isotope {
+    U238,
+    U235,
+    Th232,
+    Sm147,
+}
+
+impl Isotope {
+    pub const ALL: [Isotope; 4] = [Isotope::U238, Isotope::U235, Isotope::Th232, Isotope::Sm147];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Isotope::U238 => "238U",
+            Isotope::U235 => "235U",
+            Isotope::Th232 => "232Th",
+            Isotope::Sm147 => "147Sm",
+        }
+    }
+
+    /// Per year.
+    fn decay_constant(&self) -> f64 {
+        match self {
+            Isotope::U238 => 1.55125e-10,
+            Isotope::U235 => 9.8485e-10,
+            Isotope::Th232 => 4.9475e-11,
+            Isotope::Sm147 => 6.54e-12,
+        }
+    }
+
+    /// Alpha particles emitted in the decay chain.
+    fn alpha_particles(&self) -> f64 {
+        match self {
+            Isotope::U238 => 8.0,
+            Isotope::U235 => 7.0,
+            Isotope::Th232 => 6.0,
+            Isotope::Sm147 => 1.0,
+        }
+    }
+
+    /// Mean alpha stopping distance (micrometer) from Ketcham et al. 2011.
+    pub fn stopping_distance(&self, mineral: Mineral) -> f64 {
+        match (mineral, self) {
+            (Mineral::Apatite, Isotope::U238) => 18.81,
+            (Mineral::Apatite, Isotope::U235) => 21.80,
+            (Mineral::Apatite, Isotope::Th232) => 22.25,
+            (Mineral::Apatite, Isotope::Sm147) => 5.93,
+            (Mineral::Zircon, Isotope::U238) => 15.55,
+            (Mineral::Zircon, Isotope::U235) => 18.05,
+            (Mineral::Zircon, Isotope::Th232) => 18.43,
+            (Mineral::Zircon, Isotope::Sm147) => 4.76,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Geometry {
+    pub volume: f64,
+    pub surface: f64,
+    /// Radius of the sphere with the same surface to volume ratio, for comparison only.
+    pub rs: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FtCorrection {
+    pub geometry: Geometry,
+    pub ft: Vec<(Isotope, f64)>,
+    /// Mean of the isotopes weighted by their He production.
+    pub mean_ft: f64,
+}
+
+/// Hexagonal prism or block along the c-axis from 0 to length, pyramids end at the tips.
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Prism {
+    shape: Shape,
+    length: f64,
+    width: f64,
+    pyramids: i32,
+}
+
+/// Volume and surface of the shape with the given length along the c-axis (including pyramids) and width across it.
+/// Hexagonal prisms are measured across the faces, blocks have a square cross section. Ellipsoids and cylinders have no pyramids.
+pub fn geometry(shape: Shape, length: f64, width: f64, pyramids: i32) -> Result<Geometry, String> {
+    if !(length > 0.0 && width > 0.0) {
+        return Err(format!("length and width must be larger than 0, found {:.1} and {:.1}", length, width))
+    }
+
+    let pyramid_height = PYRAMID_HEIGHT_RATIO * width;
+    let pyramids = f64::from(pyramids);
+    let prism_length = length - pyramids * pyramid_height;
+
+    if matches!(shape, Shape::Hexagonal | Shape::Block) && prism_length < 0.0 {
+        return Err(format!("the grain of length {:.1} is too short for {} pyramid(s) with a width of {:.1}", length, pyramids, width))
+    }
+
+    let (volume, surface) = match shape {
+        Shape::Hexagonal => {
+            let side = width / 3f64.sqrt();
+            let section = 3f64.sqrt() / 2.0 * width * width;
+            // Six triangles, their height runs from the middle of a face to the tip.
+            let pyramid_surface = 3.0 * side * pyramid_height.hypot(width / 2.0);
+
+            (section * prism_length + pyramids * section * pyramid_height / 3.0,
+                6.0 * side * prism_length + (2.0 - pyramids) * section + pyramids * pyramid_surface)
+        }
+        Shape::Block => {
+            let section = width * width;
+            let pyramid_surface = 2.0 * width * pyramid_height.hypot(width / 2.0);
+
+            (section * prism_length + pyramids * section * pyramid_height / 3.0,
+                4.0 * width * prism_length + (2.0 - pyramids) * section + pyramids * pyramid_surface)
+        }
+        Shape::Cylinder => {
+            let radius = width / 2.0;
+            (PI * radius * radius * length, 2.0 * PI * radius * (radius + length))
+        }
+        Shape::Ellipsoid => {
+            let (a, b) = (length / 2.0, width / 2.0);
+            (4.0 / 3.0 * PI * a * b * b, spheroid_surface(a, b))
+        }
+    };
+
+    Ok(Geometry { volume, surface, rs: 3.0 * volume / surface })
+}
+
+/// Surface of the spheroid with semi-axis a along the axis of rotation and b across it.
+fn spheroid_surface(a: f64, b: f64) -> f64 {
+    if (a - b).abs() < 1e-9 * a.max(b) {
+        4.0 * PI * a * a
+    } else if a > b {
+        let e = (1.0 - b * b / (a * a)).sqrt();
+        2.0 * PI * b * b * (1.0 + a / (b * e) * e.asin())
+    } else {
+        let e = (1.0 - a * a / (b * b)).sqrt();
+        2.0 * PI * b * b * (1.0 + (1.0 - e * e) / e * e.atanh())
+    }
+}
+
+/// Fraction of the alpha particles retained in a sphere of radius rs (Ketcham et al. 2011).
+pub fn sphere_ft(stopping_distance: f64, rs: f64) -> f64 {
+    if stopping_distance >= 2.0 * rs {
+        return 0.0
+    }
+
+    let ratio = stopping_distance / rs;
+    1.0 - 0.75 * ratio + ratio.powi(3) / 16.0
+}
+
+/// Fraction of the alpha particles with the given stopping distance retained in the shape, see geometry() for the dimensions.
+pub fn shape_ft(shape: Shape, length: f64, width: f64, pyramids: i32, stopping_distance: f64) -> Result<f64, String> {
+    let volume = geometry(shape, length, width, pyramids)?.volume;
+
+    Ok(match shape {
+        // The unit sphere stretched by the semi-axes, a shift along the unit vector u is one of |(u_c / a, u_w / b)| in the sphere.
+        Shape::Ellipsoid => {
+            let (a, b) = (length / 2.0, width / 2.0);
+            polar_mean(|cos| sphere_ft(stopping_distance * (cos * cos / (a * a) + (1.0 - cos * cos) / (b * b)).sqrt(), 1.0))
+        }
+        Shape::Cylinder => {
+            let radius = width / 2.0;
+            polar_mean(|cos| (length - stopping_distance * cos).max(0.0) * disk_overlap(radius, stopping_distance * (1.0 - cos * cos).sqrt())) / volume
+        }
+        Shape::Hexagonal | Shape::Block => {
+            let prism = Prism { shape, length, width, pyramids };
+            direction_mean(shape, |cos, azimuth| prism.overlap(stopping_distance, cos, azimuth)) / volume
+        }
+    })
+}
+
+/// Ft of each isotope and their mean, weighted by the He production of the present day ratios (mol) of Th and Sm to U.
+pub fn ft_correction(mineral: Mineral, shape: Shape, length: f64, width: f64, pyramids: i32, ratio_232_238: f64, ratio_147_238: f64) -> Result<FtCorrection, String> {
+    let geometry = geometry(shape, length, width, pyramids)?;
+
+    let mut ft = Vec::new();
+    for isotope in Isotope::ALL.iter() {
+        ft.push((*isotope, shape_ft(shape, length, width, pyramids, isotope.stopping_distance(mineral))?));
+    }
+
+    let mean_ft = weighted_mean_ft(&ft, ratio_232_238, ratio_147_238);
+
+    Ok(FtCorrection { geometry, ft, mean_ft })
+}
+
+impl Prism {
+    /// Width at the position z along the c-axis, the pyramids narrow it to 0 at the tips (the one at the end first).
+    fn width_at(&self, z: f64) -> f64 {
+        let pyramid_height = PYRAMID_HEIGHT_RATIO * self.width;
+        let mut width = self.width;
+
+        if self.pyramids >= 1 {
+            width = width.min(self.width * (self.length - z) / pyramid_height);
+        }
+
+        if self.pyramids >= 2 {
+            width = width.min(self.width * z / pyramid_height);
+        }
+
+        width.max(0.0)
+    }
+
+    /// Cross section of the given width, counter clockwise around the c-axis shifted by center.
+    /// Hexagons have a corner at azimuth 0, the corners of blocks are at 45 degrees.
+    fn section(&self, width: f64, center: (f64, f64)) -> Vec<(f64, f64)> {
+        let (corners, radius, first) = match self.shape {
+            Shape::Hexagonal => (6, width / 3f64.sqrt(), 0.0),
+            _ => (4, width / 2f64.sqrt(), PI / 4.0),
+        };
+
+        (0..corners).map(|i| {
+            let angle = first + 2.0 * PI * f64::from(i) / f64::from(corners);
+            (center.0 + radius * angle.cos(), center.1 + radius * angle.sin())
+        }).collect()
+    }
+
+    /// Volume shared with the prism shifted by distance in the direction given by the cosine of its angle to the c-axis and its azimuth.
+    fn overlap(&self, distance: f64, cos: f64, azimuth: f64) -> f64 {
+        let along = distance * cos;
+        let across = distance * (1.0 - cos * cos).sqrt();
+        let shift = (across * azimuth.cos(), across * azimuth.sin());
+
+        if along >= self.length {
+            return 0.0
+        }
+
+        let area = |z: f64| polygon_overlap(&self.section(self.width_at(z), (0.0, 0.0)), &self.section(self.width_at(z - along), shift));
+
+        if self.pyramids == 0 {
+            return (self.length - along) * area(self.length)
+        }
+
+        // The widths change linearly between the ends of the pyramids of both prisms.
+        let pyramid_height = PYRAMID_HEIGHT_RATIO * self.width;
+        let mut bounds = vec![along, self.length];
+        for end in [pyramid_height, self.length - pyramid_height].iter() {
+            bounds.push(*end);
+            bounds.push(end + along);
+        }
+
+        bounds.retain(|z| *z >= along && *z <= self.length);
+        bounds.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+
+        bounds.windows(2).map(|piece| simpson(area, piece[0], piece[1])).sum()
+    }
+}
+
+/// Mean over the directions of a half sphere, by the cosine of the angle to the c-axis.
+fn polar_mean<F>(f: F) -> f64 where F: Fn(f64) -> f64 {
+    (0..POLAR_STEPS).map(|i| f((i as f64 + 0.5) / POLAR_STEPS as f64)).sum::<f64>() / POLAR_STEPS as f64
+}
+
+/// Mean over all directions of a prism, by the cosine of the angle to the c-axis and the azimuth.
+/// The cross sections are mirrored every 30 (hexagon) or 45 (square) degrees.
+fn direction_mean<F>(shape: Shape, f: F) -> f64 where F: Fn(f64, f64) -> f64 {
+    let mirrored = if shape == Shape::Hexagonal {PI / 6.0} else {PI / 4.0};
+
+    polar_mean(|cos| {
+        (0..AZIMUTH_STEPS).map(|i| f(cos, mirrored * (i as f64 + 0.5) / AZIMUTH_STEPS as f64)).sum::<f64>() / AZIMUTH_STEPS as f64
+    })
+}
+
+fn simpson<F>(f: F, from: f64, to: f64) -> f64 where F: Fn(f64) -> f64 {
+    let step = (to - from) / SLICE_STEPS as f64;
+    let inner: f64 = (1..SLICE_STEPS).map(|i| if i % 2 == 1 {4.0} else {2.0} * f(from + i as f64 * step)).sum();
+
+    (f(from) + inner + f(to)) * step / 3.0
+}
+
+/// Area shared by two disks of the given radius whose centers are distance apart.
+fn disk_overlap(radius: f64, distance: f64) -> f64 {
+    if distance >= 2.0 * radius {
+        return 0.0
+    }
+
+    2.0 * radius * radius * (distance / (2.0 * radius)).acos() - distance / 2.0 * (4.0 * radius * radius - distance * distance).sqrt()
+}
+
+/// Area shared by two convex polygons in counter clockwise order (Sutherland-Hodgman clipping).
+fn polygon_overlap(subject: &[(f64, f64)], clip: &[(f64, f64)]) -> f64 {
+    let mut clipped = subject.to_vec();
+
+    for (i, start) in clip.iter().enumerate() {
+        let end = clip[(i + 1) % clip.len()];
+        let side = |point: &(f64, f64)| (end.0 - start.0) * (point.1 - start.1) - (end.1 - start.1) * (point.0 - start.0);
+        let input = clipped;
+        clipped = Vec::new();
+
+        for (j, current) in input.iter().enumerate() {
+            let previous = input[(j + input.len() - 1) % input.len()];
+            let (current_side, previous_side) = (side(current), side(&previous));
+
+            if (current_side >= 0.0) != (previous_side >= 0.0) {
+                let t = previous_side / (previous_side - current_side);
+                clipped.push((previous.0 + t * (current.0 - previous.0), previous.1 + t * (current.1 - previous.1)));
+            }
+
+            if current_side >= 0.0 {
+                clipped.push(*current);
+            }
+        }
+
+        if clipped.is_empty() {
+            return 0.0
+        }
+    }
+
+    let twice_area: f64 = clipped.iter().enumerate()
+        .map(|(i, point)| {
+            let next = clipped[(i + 1) % clipped.len()];
+            point.0 * next.1 - next.0 * point.1
+        })
+        .sum();
+
+    twice_area / 2.0
+}
+
+/// Mean of the Ft values of the isotopes, weighted by their He production at the given ratios (mol) of Th and Sm to U.
+pub fn weighted_mean_ft(ft: &[(Isotope, f64)], ratio_232_238: f64, ratio_147_238: f64) -> f64 {
+    let atoms = |isotope: Isotope| match isotope {
+        Isotope::U238 => 1.0,
+        Isotope::U235 => 1.0 / U238_U235_RATIO,
+        Isotope::Th232 => ratio_232_238,
+        Isotope::Sm147 => ratio_147_238,
+    };
+
+    let production = |isotope: Isotope| isotope.alpha_particles() * isotope.decay_constant() * atoms(isotope);
+    let total: f64 = ft.iter().map(|(isotope, _)| production(*isotope)).sum();
+
+    ft.iter().map(|(isotope, ft)| production(*isotope) * ft).sum::<f64>() / total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::monte_carlo::SplitMix64;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9 * a.abs().max(b.abs()).max(1.0)
+    }
+
+    #[test]
+    fn sphere_ft_limits() {
+        assert_eq!(sphere_ft(0.0, 50.0), 1.0);
+        assert!(close(sphere_ft(50.0, 50.0), 0.3125));
+        assert_eq!(sphere_ft(100.0, 50.0), 0.0);
+        assert_eq!(sphere_ft(150.0, 50.0), 0.0);
+    }
+
+    #[test]
+    fn ellipsoid_with_equal_axes_is_a_sphere() {
+        let sphere = geometry(Shape::Ellipsoid, 100.0, 100.0, 0).unwrap();
+
+        assert!(close(sphere.volume, 4.0 / 3.0 * PI * 50f64.powi(3)));
+        assert!(close(sphere.surface, 4.0 * PI * 2500.0));
+        assert!(close(sphere.rs, 50.0));
+
+        // Both branches of the spheroid surface approach the sphere.
+        assert!((spheroid_surface(50.001, 50.0) / sphere.surface - 1.0).abs() < 1e-4);
+        assert!((spheroid_surface(49.999, 50.0) / sphere.surface - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn prisms_without_pyramids() {
+        let block = geometry(Shape::Block, 100.0, 50.0, 0).unwrap();
+        assert!(close(block.volume, 250_000.0));
+        assert!(close(block.surface, 4.0 * 50.0 * 100.0 + 2.0 * 2500.0));
+
+        let cylinder = geometry(Shape::Cylinder, 100.0, 50.0, 0).unwrap();
+        assert!(close(cylinder.volume, PI * 625.0 * 100.0));
+        assert!(close(cylinder.rs, 3.0 * 625.0 * 100.0 / (2.0 * 25.0 * (25.0 + 100.0))));
+
+        // Across flats 2 * sqrt(3): sides of 2, a section of 6 * sqrt(3).
+        let hexagonal = geometry(Shape::Hexagonal, 10.0, 2.0 * 3f64.sqrt(), 0).unwrap();
+        assert!(close(hexagonal.volume, 60.0 * 3f64.sqrt()));
+        assert!(close(hexagonal.surface, 120.0 + 12.0 * 3f64.sqrt()));
+    }
+
+    #[test]
+    fn pyramids_reduce_volume_and_rs() {
+        // A block of width 4 with two pyramids of height 2: prism of length 6, slant height sqrt(8).
+        let block = geometry(Shape::Block, 10.0, 4.0, 2).unwrap();
+        assert!(close(block.volume, 16.0 * 6.0 + 2.0 * 16.0 * 2.0 / 3.0));
+        assert!(close(block.surface, 16.0 * 6.0 + 2.0 * 8.0 * 8f64.sqrt()));
+
+        let without = geometry(Shape::Hexagonal, 100.0, 40.0, 0).unwrap();
+        let with = geometry(Shape::Hexagonal, 100.0, 40.0, 2).unwrap();
+        assert!(with.volume < without.volume);
+        assert!(with.rs < without.rs);
+
+        assert!(geometry(Shape::Hexagonal, 30.0, 40.0, 2).is_err());
+        assert!(geometry(Shape::Cylinder, 0.0, 40.0, 0).is_err());
+    }
+
+    /// Follows particles from random points of the shape to the end of their path, independent of the overlap integrals.
+    fn simulated_ft(shape: Shape, length: f64, width: f64, pyramids: i32, stopping_distance: f64, particles: usize) -> f64 {
+        let prism = Prism { shape, length, width, pyramids };
+        let inside = |x: f64, y: f64, z: f64| match shape {
+            Shape::Ellipsoid => ((z - length / 2.0) / (length / 2.0)).powi(2) + (x * x + y * y) / (width / 2.0).powi(2) <= 1.0,
+            Shape::Cylinder => z >= 0.0 && z <= length && x.hypot(y) <= width / 2.0,
+            // Faces at azimuth 30, 90 and 150 degrees, between the corners of Prism::section().
+            Shape::Hexagonal => z >= 0.0 && z <= length && [PI / 6.0, PI / 2.0, 5.0 * PI / 6.0].iter()
+                .all(|face| (x * face.cos() + y * face.sin()).abs() <= prism.width_at(z) / 2.0),
+            Shape::Block => z >= 0.0 && z <= length && x.abs().max(y.abs()) <= prism.width_at(z) / 2.0,
+        };
+
+        let mut rng = SplitMix64(2011);
+        let (mut emitted, mut retained) = (0, 0);
+
+        while emitted < particles {
+            let (x, y, z) = ((rng.next_f64() - 0.5) * 2.0 * width, (rng.next_f64() - 0.5) * 2.0 * width, rng.next_f64() * length);
+            if !inside(x, y, z) {
+                continue
+            }
+
+            let cos = 2.0 * rng.next_f64() - 1.0;
+            let azimuth = 2.0 * PI * rng.next_f64();
+            let across = stopping_distance * (1.0 - cos * cos).sqrt();
+
+            emitted += 1;
+            if inside(x + across * azimuth.cos(), y + across * azimuth.sin(), z + stopping_distance * cos) {
+                retained += 1;
+            }
+        }
+
+        f64::from(retained) / particles as f64
+    }
+
+    #[test]
+    fn ellipsoid_with_equal_axes_has_the_sphere_ft() {
+        for distance in [5.93, 18.81, 22.25, 60.0, 120.0].iter() {
+            assert!(close(shape_ft(Shape::Ellipsoid, 100.0, 100.0, 0, *distance).unwrap(), sphere_ft(*distance, 50.0)));
+        }
+    }
+
+    #[test]
+    fn block_matches_closed_form() {
+        // The shared volume is (W - s|u_x|)(W - s|u_y|)(L - s|u_z|) for a stopping distance s below the width, averaged over all directions u.
+        let (length, width) = (120.0, 50.0);
+
+        for s in [4.76, 18.81, 40.0].iter() {
+            let expected = 1.0 - s / 2.0 * (2.0 / width + 1.0 / length) + 2.0 * s * s / (3.0 * PI) * (1.0 / (width * width) + 2.0 / (width * length))
+                - s.powi(3) / (4.0 * PI * width * width * length);
+
+            assert!((shape_ft(Shape::Block, length, width, 0, *s).unwrap() - expected).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn shapes_match_simulation() {
+        let shapes = [(Shape::Hexagonal, 150.0, 80.0, 0), (Shape::Hexagonal, 150.0, 80.0, 1), (Shape::Hexagonal, 150.0, 80.0, 2),
+            (Shape::Hexagonal, 60.0, 40.0, 2), (Shape::Block, 120.0, 60.0, 2), (Shape::Cylinder, 120.0, 60.0, 0),
+            (Shape::Ellipsoid, 150.0, 70.0, 0), (Shape::Ellipsoid, 50.0, 90.0, 0)];
+
+        for (shape, length, width, pyramids) in shapes.iter() {
+            for mineral in [Mineral::Apatite, Mineral::Zircon].iter() {
+                for isotope in [Isotope::U238, Isotope::Th232].iter() {
+                    let distance = isotope.stopping_distance(*mineral);
+                    let ft = shape_ft(*shape, *length, *width, *pyramids, distance).unwrap();
+                    let simulated = simulated_ft(*shape, *length, *width, *pyramids, distance, 200_000);
+
+                    assert!((ft - simulated).abs() < 0.006, "{:?} {} x {} with {} pyramid(s), {:?} {:?}: {:.4} instead of {:.4}",
+                        shape, length, width, pyramids, mineral, isotope, ft, simulated);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn production_weighted_mean() {
+        // An ellipsoid with equal axes of 120 is a sphere of radius 60.
+        let ft_correction = |mineral, ratio_232_238| ft_correction(mineral, Shape::Ellipsoid, 120.0, 120.0, 0, ratio_232_238, 0.0).unwrap();
+        let uranium_only = ft_correction(Mineral::Apatite, 0.0);
+
+        let ft = |isotope: Isotope| uranium_only.ft.iter().find(|(other, _)| *other == isotope).unwrap().1;
+        assert!(close(ft(Isotope::U238), sphere_ft(18.81, 60.0)));
+        assert!(ft(Isotope::Sm147) > ft(Isotope::U238));
+        assert!(uranium_only.mean_ft < ft(Isotope::U238) && uranium_only.mean_ft > ft(Isotope::U235));
+
+        // Thorium has the longest stopping distance and lowers the mean.
+        let with_thorium = ft_correction(Mineral::Apatite, 4.0);
+        assert!(with_thorium.mean_ft < uranium_only.mean_ft);
+
+        // Zircon stops the particles sooner.
+        assert!(ft_correction(Mineral::Zircon, 0.0).mean_ft > uranium_only.mean_ft);
+    }
+}
@@ -0,0 +1,164 @@
+//! Command lines of the external calculation programs, configured per engine in the config file
+//! (see configuration::Executor), so that 3D-He can run in matlab, octave or any other program.
+
+use std::process::Command;
+
+use configuration::Executor;
+
+/// Files of one calculation: the input written by the web gui, the result the program has to write and the folder of both.
+pub struct JobFiles<'a> {
+    pub input_file: &'a str,
+    pub output_file: &'a str,
+    pub grain_folder: &'a str,
+}
+
+/// The command that runs the calculation, None for native engines.
+pub fn command(executor: &Executor, files: &JobFiles) -> Result<Option<Command>, String> {
+    match executor {
+        Executor::Matlab { exec, folder, script } => {
+            let mut command = Command::new(exec);
+            command.args(["-nodisplay", "-nosplash", "-nodesktop", "-sd", folder, "-r", &script_start(files, script)]);
+            Ok(Some(command))
+        }
+        Executor::Octave { exec, folder, script } => {
+            let mut command = Command::new(exec);
+            let statements = format!("cd({});{}", matlab_string(folder), script_start(files, script));
+            command.args(["--no-gui", "--no-window-system", "--quiet", "--eval", &statements]);
+            Ok(Some(command))
+        }
+        Executor::Command { command: template } => {
+            let mut command = Command::new("sh");
+            command.args(["-c", &expand(template, files)?]);
+            Ok(Some(command))
+        }
+        Executor::Native { .. } => Ok(None),
+    }
+}
+
+/// The program that is started, for error messages.
+pub fn program(executor: &Executor) -> &str {
+    match executor {
+        Executor::Matlab { exec, .. } | Executor::Octave { exec, .. } => exec,
+        Executor::Command { .. } => "sh",
+        Executor::Native { .. } => "native engine",
+    }
+}
+
+/// Sets the variables the script reads and runs it.
+fn script_start(files: &JobFiles, script: &str) -> String {
+    format!("input_file={};output_file={};grain_folder={};run({})",
+        matlab_string(files.input_file), matlab_string(files.output_file), matlab_string(files.grain_folder), matlab_string(script))
+}
+
+/// A string literal of matlab and octave, quotes inside are doubled.
+fn matlab_string(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Replaces {input_file}, {output_file} and {grain_folder} in the template by the quoted paths.
+/// Other braces are kept for the shell, unless they look like a misspelled placeholder.
+pub fn expand(template: &str, files: &JobFiles) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let end = rest.find('}').unwrap_or(0);
+        let name = &rest[1..end.max(1)];
+
+        if end == 0 || name.is_empty() || !name.chars().all(|c| c.is_ascii_lowercase() || c == '_') {
+            expanded.push('{');
+            rest = &rest[1..];
+            continue
+        }
+
+        let value = match name {
+            "input_file" => files.input_file,
+            "output_file" => files.output_file,
+            "grain_folder" => files.grain_folder,
+            _ => return Err(format!("unknown placeholder {{{}}} in the command '{}'", name, template)),
+        };
+
+        expanded.push_str(&shell_quote(value));
+        rest = &rest[end + 1..];
+    }
+
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Single quotes for sh, a quote inside ends the quoted string, is escaped and starts a new one.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::process;
+
+    fn files() -> JobFiles<'static> {
+        JobFiles { input_file: "matlab/test_user/test1/matlab_input.csv", output_file: "matlab/test_user/test1/result.txt", grain_folder: "/srv/web_gui/matlab/test_user/test1" }
+    }
+
+    fn arguments(command: &Command) -> Vec<String> {
+        command.get_args().map(|argument| argument.to_string_lossy().into_owned()).collect()
+    }
+
+    #[test]
+    fn matlab_and_octave_run_the_script() {
+        let matlab = Executor::Matlab { exec: "matlab".to_string(), folder: "/opt/ft_model".to_string(), script: "run_3DFt.m".to_string() };
+        let command = super::command(&matlab, &files()).unwrap().unwrap();
+
+        assert_eq!(arguments(&command), vec!["-nodisplay", "-nosplash", "-nodesktop", "-sd", "/opt/ft_model", "-r",
+            "input_file='matlab/test_user/test1/matlab_input.csv';output_file='matlab/test_user/test1/result.txt';grain_folder='/srv/web_gui/matlab/test_user/test1';run('run_3DFt.m')"]);
+
+        let octave = Executor::Octave { exec: "octave-cli".to_string(), folder: "/opt/o'brien".to_string(), script: "run_3DFt.m".to_string() };
+        let command = super::command(&octave, &files()).unwrap().unwrap();
+
+        assert_eq!(command.get_program(), "octave-cli");
+        assert!(arguments(&command)[4].starts_with("cd('/opt/o''brien');input_file="));
+        assert!(super::command(&Executor::Native { engine: ::configuration::NativeEngine::MonteCarlo }, &files()).unwrap().is_none());
+    }
+
+    #[test]
+    fn templates_quote_the_paths() {
+        let files = JobFiles { input_file: "in put.csv", output_file: "it's.txt", grain_folder: "/tmp" };
+
+        assert_eq!(expand("ft {input_file} -o {output_file} -d {grain_folder}", &files).unwrap(), "ft 'in put.csv' -o 'it'\\''s.txt' -d '/tmp'");
+        assert_eq!(expand("echo ${HOME} {1,2} {", &files).unwrap(), "echo ${HOME} {1,2} {");
+        assert!(expand("ft {input}", &files).unwrap_err().contains("{input}"));
+    }
+
+    #[test]
+    fn stub_script_is_run_as_matlab_and_as_command() {
+        let folder = env::temp_dir().join(format!("web_gui_executor_{}", process::id()));
+        fs::create_dir_all(&folder).unwrap();
+
+        // Writes its arguments to the result, one per line.
+        let stub = folder.join("stub.sh");
+        fs::write(&stub, "#!/bin/sh\nprintf '%s\\n' \"$@\" > \"$RESULT\"\n").unwrap();
+        fs::set_permissions(&stub, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let result = folder.join("result.txt");
+        let result = result.to_str().unwrap();
+        let files = JobFiles { input_file: "input.csv", output_file: result, grain_folder: folder.to_str().unwrap() };
+
+        let matlab = Executor::Matlab { exec: stub.to_str().unwrap().to_string(), folder: "/opt/ft_model".to_string(), script: "run_3DFt.m".to_string() };
+        let status = super::command(&matlab, &files).unwrap().unwrap().env("RESULT", result).status().unwrap();
+        assert!(status.success());
+        assert!(fs::read_to_string(result).unwrap().starts_with("-nodisplay\n-nosplash\n-nodesktop\n-sd\n/opt/ft_model\n-r\ninput_file='input.csv';"));
+
+        let template = Executor::Command { command: format!("{} {{input_file}} 'two words'", stub.display()) };
+        let status = super::command(&template, &files).unwrap().unwrap().env("RESULT", result).status().unwrap();
+        assert!(status.success());
+        assert_eq!(fs::read_to_string(result).unwrap(), "input.csv\ntwo words\n");
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
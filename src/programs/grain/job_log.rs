@@ -0,0 +1,112 @@
+//! Output of calculation jobs. The log of a job is capped in size so that a run that prints
+//! without end can not fill the disk, and a short reason for a failure is taken from its lines.
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+
+/// Longest failure message taken from a log, in characters.
+const MAX_MESSAGE_LENGTH: usize = 200;
+
+/// Writes until the limit (bytes) is reached, of the rest only the last end_size bytes are written by finish(),
+/// as errors are usually printed last.
+pub struct CappedLog<W: Write> {
+    inner: W,
+    limit: u64,
+    written: u64,
+    end: VecDeque<u8>,
+    end_size: usize,
+    left_out: u64,
+}
+
+impl<W: Write> CappedLog<W> {
+    pub fn new(inner: W, limit: u64, end_size: usize) -> CappedLog<W> {
+        CappedLog { inner, limit, written: 0, end: VecDeque::new(), end_size, left_out: 0 }
+    }
+
+    pub fn append(&mut self, data: &[u8]) -> io::Result<()> {
+        let room = (self.limit - self.written).min(data.len() as u64) as usize;
+        self.inner.write_all(&data[..room])?;
+        self.written += room as u64;
+
+        self.end.extend(&data[room..]);
+        if self.end.len() > self.end_size {
+            let excess = self.end.len() - self.end_size;
+            self.end.drain(..excess);
+            self.left_out += excess as u64;
+        }
+
+        self.inner.flush()
+    }
+
+    /// Writes the end of the output that did not fit.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.left_out > 0 {
+            writeln!(self.inner, "\n[{} bytes left out]", self.left_out)?;
+        }
+
+        let (first, second) = self.end.as_slices();
+        self.inner.write_all(first)?;
+        self.inner.write_all(second)?;
+        self.inner.flush()?;
+
+        Ok(self.inner)
+    }
+}
+
+/// The first line that reports an error (matlab, octave or a panic), otherwise the last line that is not empty.
+pub fn failure_message(log: &str) -> Option<String> {
+    let lines: Vec<_> = log.lines().map(str::trim).filter(|line| !line.is_empty()).collect();
+
+    let error = lines.iter()
+        .find(|line| line.starts_with("Error") || line.starts_with("error:") || line.starts_with("???") || line.contains("panicked at"))
+        .or_else(|| lines.last());
+
+    error.map(|line| line.chars().take(MAX_MESSAGE_LENGTH).collect())
+}
+
+/// The end of the log starting at a whole line, at most max_bytes long.
+pub fn tail(log: &[u8], max_bytes: usize) -> String {
+    if log.len() <= max_bytes {
+        return String::from_utf8_lossy(log).into_owned()
+    }
+
+    let end = &log[log.len() - max_bytes..];
+    let start = end.iter().position(|byte| *byte == b'\n').map_or(0, |position| position + 1);
+
+    String::from_utf8_lossy(&end[start..]).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_keeps_start_and_end() {
+        let mut log = CappedLog::new(Vec::new(), 10, 4);
+        log.append(b"12345").unwrap();
+        log.append(b"6789abcdef").unwrap();
+        log.append(b"ghij").unwrap();
+
+        assert_eq!(String::from_utf8(log.finish().unwrap()).unwrap(), "123456789a\n[5 bytes left out]\nghij");
+
+        let mut log = CappedLog::new(Vec::new(), 4, 4);
+        log.append(b"123456").unwrap();
+        assert_eq!(log.finish().unwrap(), b"123456");
+    }
+
+    #[test]
+    fn error_lines_are_preferred() {
+        let matlab = "Loading input\nError using readmatrix\nFile not found.\n\n";
+        assert_eq!(failure_message(matlab), Some("Error using readmatrix".to_string()));
+
+        assert_eq!(failure_message("step 1\nerror: 'x' undefined\n"), Some("error: 'x' undefined".to_string()));
+        assert_eq!(failure_message("step 1\nKilled\n  \n"), Some("Killed".to_string()));
+        assert_eq!(failure_message("\n \n"), None);
+    }
+
+    #[test]
+    fn tail_starts_at_a_line() {
+        assert_eq!(tail(b"one\ntwo\nthree\n", 100), "one\ntwo\nthree\n");
+        assert_eq!(tail(b"one\ntwo\nthree\n", 9), "three\n");
+    }
+}
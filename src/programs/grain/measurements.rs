@@ -0,0 +1,195 @@
+//! Dimensions of a grain from its outline, available right after the outline is stored.
+//! Lengths are in micrometer and areas in square micrometer.
+
+use super::outline::polygon_area;
+use super::{Axis, Coordinates};
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Measurements {
+    pub area: f64,
+    pub perimeter: f64,
+    pub hull_area: f64,
+    pub hull_perimeter: f64,
+    /// Extent of the outline along the c-axis, None without an axis.
+    pub length: Option<f64>,
+    /// Extent of the outline across the c-axis, None without an axis.
+    pub width: Option<f64>,
+    pub aspect_ratio: Option<f64>,
+    /// Radius of the circle with the same area as the outline, not the sphere-equivalent radius Rs = 3V/S.
+    pub circle_equivalent_radius: f64,
+}
+
+impl Measurements {
+    /// Names of the values in the order of values(), as written to the matlab input.
+    pub const COLUMNS: [&'static str; 8] = ["area", "perimeter", "hull area", "hull perimeter",
+        "c-axis length", "width", "aspect ratio", "circle-equivalent radius"];
+
+    /// All values, NaN for the ones that are not known.
+    pub fn values(&self) -> [f64; 8] {
+        [self.area, self.perimeter, self.hull_area, self.hull_perimeter,
+            self.length.unwrap_or(f64::NAN), self.width.unwrap_or(f64::NAN),
+            self.aspect_ratio.unwrap_or(f64::NAN), self.circle_equivalent_radius]
+    }
+}
+
+/// Measures the outline (a closed polygon in pixels of the working image), pixel_size is the size (micrometer) of a pixel.
+/// None for outlines with less than three points.
+pub fn measure(polygon: &[Coordinates], axis: &Axis, pixel_size: f64) -> Option<Measurements> {
+    if polygon.len() < 3 {
+        return None
+    }
+
+    let area = polygon_area(polygon).abs() * pixel_size * pixel_size;
+    let hull = convex_hull(polygon);
+
+    let (length, width) = match axis_direction(axis) {
+        Some(direction) => {
+            let across = (-direction.1, direction.0);
+            (Some(extent(&hull, direction) * pixel_size), Some(extent(&hull, across) * pixel_size))
+        }
+        None => (None, None),
+    };
+
+    Some(Measurements {
+        area,
+        perimeter: perimeter(polygon) * pixel_size,
+        hull_area: polygon_area(&hull).abs() * pixel_size * pixel_size,
+        hull_perimeter: perimeter(&hull) * pixel_size,
+        length,
+        width,
+        aspect_ratio: match (length, width) {
+            (Some(length), Some(width)) if width > 0.0 => Some(length / width),
+            _ => None,
+        },
+        circle_equivalent_radius: (area / std::f64::consts::PI).sqrt(),
+    })
+}
+
+fn perimeter(polygon: &[Coordinates]) -> f64 {
+    polygon.iter().enumerate()
+        .map(|(i, point)| {
+            let next = &polygon[(i + 1) % polygon.len()];
+            (next.x - point.x).hypot(next.y - point.y)
+        })
+        .sum()
+}
+
+/// Unit vector of the axis, None if its two points are the same.
+pub fn axis_direction(axis: &Axis) -> Option<(f64, f64)> {
+    let dx = f64::from(axis.x2) - f64::from(axis.x1);
+    let dy = f64::from(axis.y2) - f64::from(axis.y1);
+    let length = dx.hypot(dy);
+
+    if length > 0.0 {
+        Some((dx / length, dy / length))
+    } else {
+        None
+    }
+}
+
+/// Length of the projection of the points onto the direction (a unit vector).
+fn extent(points: &[Coordinates], direction: (f64, f64)) -> f64 {
+    let projections = points.iter().map(|point| point.x * direction.0 + point.y * direction.1);
+    let (min, max) = projections.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), value| (min.min(value), max.max(value)));
+    max - min
+}
+
+/// Convex hull in counter clockwise order (monotone chain), collinear points are left out.
+fn convex_hull(points: &[Coordinates]) -> Vec<Coordinates> {
+    let mut sorted = points.to_vec();
+    sorted.sort_by(|a, b| a.x.partial_cmp(&b.x).unwrap_or(std::cmp::Ordering::Equal)
+        .then(a.y.partial_cmp(&b.y).unwrap_or(std::cmp::Ordering::Equal)));
+    sorted.dedup();
+
+    if sorted.len() < 3 {
+        return sorted
+    }
+
+    let mut lower = half_hull(sorted.iter());
+    let mut upper = half_hull(sorted.iter().rev());
+
+    // The last point of each half is the first point of the other one.
+    lower.pop();
+    upper.pop();
+    lower.extend(upper);
+    lower
+}
+
+/// Keeps the points where the chain turns left.
+fn half_hull<'a, I>(points: I) -> Vec<Coordinates> where I: Iterator<Item = &'a Coordinates> {
+    let cross = |o: &Coordinates, a: &Coordinates, b: &Coordinates| (a.x - o.x) * (b.y - o.y) - (a.y - o.y) * (b.x - o.x);
+    let mut chain: Vec<Coordinates> = Vec::new();
+
+    for point in points {
+        while chain.len() >= 2 && cross(&chain[chain.len() - 2], &chain[chain.len() - 1], point) <= 0.0 {
+            chain.pop();
+        }
+        chain.push(point.clone());
+    }
+
+    chain
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(x: f64, y: f64) -> Coordinates {
+        Coordinates { x, y }
+    }
+
+    /// 40 x 20 rectangle with a notch of 10 x 10 in the middle of the top edge.
+    fn notched_rectangle() -> Vec<Coordinates> {
+        vec![
+            point(0.0, 0.0), point(15.0, 0.0), point(15.0, 10.0), point(25.0, 10.0), point(25.0, 0.0),
+            point(40.0, 0.0), point(40.0, 20.0), point(0.0, 20.0),
+        ]
+    }
+
+    #[test]
+    fn hull_of_notched_rectangle() {
+        let hull = convex_hull(&notched_rectangle());
+
+        assert_eq!(hull.len(), 4);
+        assert_eq!(polygon_area(&hull).abs(), 800.0);
+        assert_eq!(perimeter(&hull), 120.0);
+    }
+
+    #[test]
+    fn measurements_along_the_axis() {
+        let axis = Axis { x1: 5, y1: 10, x2: 35, y2: 10 };
+        let measurements = measure(&notched_rectangle(), &axis, 2.0).unwrap();
+
+        assert_eq!(measurements.area, 700.0 * 4.0);
+        assert_eq!(measurements.perimeter, 140.0 * 2.0);
+        assert_eq!(measurements.hull_area, 800.0 * 4.0);
+        assert_eq!(measurements.hull_perimeter, 120.0 * 2.0);
+        assert_eq!(measurements.length, Some(80.0));
+        assert_eq!(measurements.width, Some(40.0));
+        assert_eq!(measurements.aspect_ratio, Some(2.0));
+        assert!((measurements.circle_equivalent_radius - (2800.0 / std::f64::consts::PI).sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn diagonal_axis() {
+        // Square with diagonals of 20 along x and y, first with the axis along a diagonal, then along a side.
+        let diamond = vec![point(10.0, 0.0), point(20.0, 10.0), point(10.0, 20.0), point(0.0, 10.0)];
+        let measurements = measure(&diamond, &Axis { x1: 10, y1: 0, x2: 10, y2: 20 }, 1.0).unwrap();
+
+        assert!((measurements.length.unwrap() - 20.0).abs() < 1e-9);
+        assert!((measurements.width.unwrap() - 20.0).abs() < 1e-9);
+
+        let measurements = measure(&diamond, &Axis { x1: 0, y1: 0, x2: 20, y2: 20 }, 1.0).unwrap();
+        assert!((measurements.length.unwrap() - 200f64.sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn without_axis_or_outline() {
+        let measurements = measure(&notched_rectangle(), &Axis::default(), 1.0).unwrap();
+
+        assert_eq!(measurements.length, None);
+        assert_eq!(measurements.aspect_ratio, None);
+        assert!(measurements.values()[4].is_nan());
+        assert_eq!(measure(&[point(0.0, 0.0), point(1.0, 1.0)], &Axis::default(), 1.0), None);
+    }
+}
@@ -0,0 +1,4719 @@
+mod analytical;
+mod executor;
+mod job_log;
+mod measurements;
+mod monte_carlo;
+mod outline;
+mod overlay;
+mod parameters;
+mod preflight;
+
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::fs::{copy, create_dir_all, remove_file, remove_dir_all, rename, read_dir, File};
+use std::io::{self, BufWriter, BufReader, Write, Read, Cursor, Seek, SeekFrom};
+use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::{thread, time, env};
+use std::time::Instant;
+use std::process::{Command, ExitStatus, Stdio};
+use std::os::unix::process::CommandExt;
+
+use rouille::{Response, Request, input};
+use failure;
+use image::{self, DynamicImage, ImageFormat, ImageOutputFormat, RgbImage};
+use image::imageops::FilterType;
+use sha2::{Sha256, Digest};
+use toml;
+use serde_json;
+use zip::{ZipWriter, ZipArchive, CompressionMethod};
+use zip::write::FileOptions;
+use csv;
+
+use util::{self, UserRole};
+use configuration::{self, Executor, NativeEngine, Quota, Rescaling, ResizeFilter};
+use program_types::{ProgramType};
+use error::{WebGuiError};
+
+use self::executor::JobFiles;
+use self::job_log::CappedLog;
+use self::measurements::Measurements;
+use self::monte_carlo::Zoning;
+use self::parameters::{Choice, Mode, Mineral, Orientation, Shape};
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+struct GrainList {
+    grains: Vec<GrainImage>,
+    #[serde(default)]
+    samples: Vec<GrainSample>,
+    #[serde(default)]
+    projects: Vec<GrainProject>,
+    #[serde(default)]
+    presets: Vec<GrainPreset>,
+    #[serde(default)]
+    jobs: Vec<GrainJob>,
+}
+
+/// How the Ft correction of a sample is calculated. Queued engines run with the executor configured for them,
+/// see configuration::executor().
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+enum CalculationEngine {
+    /// 3D-He, by default in matlab, the result is read from result.txt when it is done.
+    ThreeDHe,
+    /// Ft of the idealized shape (see analytical.rs), calculated right away unless an executor is configured for it.
+    Analytical,
+    /// Simulation on a voxel model of the grain, written to result.txt like 3D-He.
+    MonteCarlo,
+}
+
+impl CalculationEngine {
+    /// 3D-He if no engine is given.
+    fn from_name(name: &str) -> Option<CalculationEngine> {
+        match name {
+            "" | "3d-he" => Some(CalculationEngine::ThreeDHe),
+            "analytical" => Some(CalculationEngine::Analytical),
+            "monte-carlo" => Some(CalculationEngine::MonteCarlo),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            CalculationEngine::ThreeDHe => "3d-he",
+            CalculationEngine::Analytical => "analytical",
+            CalculationEngine::MonteCarlo => "monte-carlo",
+        }
+    }
+}
+
+/// Alpha particles simulated per isotope by the Monte Carlo engine.
+const MONTE_CARLO_PARTICLES: usize = 100_000;
+
+/// Voxels along the c-axis of the grain model of the Monte Carlo engine.
+const MONTE_CARLO_VOXELS: usize = 100;
+
+/// Time a process gets to stop after SIGTERM before its process group is killed.
+const JOB_KILL_GRACE: time::Duration = time::Duration::from_secs(5);
+
+/// Largest log (bytes) kept of a job, further output is left out.
+const JOB_LOG_LIMIT: u64 = 1024 * 1024;
+
+/// End of the output (bytes) kept when a job prints more than JOB_LOG_LIMIT, it usually holds the error.
+const JOB_LOG_END: usize = 64 * 1024;
+
+/// End of a job log (bytes) shown on the calculate page.
+const JOB_LOG_TAIL: u64 = 4096;
+
+/// How the black and white threshold of an outline was chosen.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+enum ThresholdMethod {
+    /// Entered by the user or set with the plus and minus buttons.
+    Manual,
+    /// Otsu's method on the whole image.
+    Otsu,
+    /// Otsu's method on parts of the image, the stored threshold is the one for the whole image.
+    Adaptive,
+}
+
+impl ThresholdMethod {
+    fn from_name(name: &str) -> Option<ThresholdMethod> {
+        match name {
+            "manual" => Some(ThresholdMethod::Manual),
+            "otsu" => Some(ThresholdMethod::Otsu),
+            "adaptive" => Some(ThresholdMethod::Adaptive),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            ThresholdMethod::Manual => "manual",
+            ThresholdMethod::Otsu => "otsu",
+            ThresholdMethod::Adaptive => "adaptive",
+        }
+    }
+}
+
+/// Position in pixels of the working image. Outlines stored before polygons only have whole pixels.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct Coordinates {
+    x: f64,
+    y: f64,
+}
+
+/// How the points of an outline are arranged.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+enum OutlineKind {
+    /// The first and last point of each pixel row, as drawn in the browser and read by matlab.
+    #[default]
+    RowPairs,
+    /// A closed polygon with the points in order, converted to row pairs for matlab.
+    Polygon,
+}
+
+impl OutlineKind {
+    /// "polygon" (the default) or "rows".
+    fn from_name(name: &str) -> Option<OutlineKind> {
+        match name {
+            "" | "polygon" => Some(OutlineKind::Polygon),
+            "rows" => Some(OutlineKind::RowPairs),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+struct Axis {
+    x1: u32,
+    y1: u32,
+    x2: u32,
+    y2: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct GrainImage {
+    id: u32,
+    user_id: u16,
+    file_name: String,
+    #[serde(default)]
+    sample_id: u32,
+    /// Only read from databases written before samples had their own records, see migrate_samples().
+    #[serde(default, skip_serializing)]
+    sample_name: String,
+    size: f64,
+    mode: Mode,
+    mineral: Mineral,
+    ratio_232_238: f64,
+    ratio_147_238: f64,
+    orientation: Orientation,
+    shape: Shape,
+    pyramids: i32,
+    broken_tips: bool,
+    zoned: bool,
+    rim_width: f64,
+    ratio_rim_core: f64,
+    coordinates: Vec<Coordinates>,
+    #[serde(default)]
+    outline_kind: OutlineKind,
+    /// Black and white threshold of the stored outline, None for outlines stored before it was recorded.
+    #[serde(default)]
+    bw_threshold: Option<f64>,
+    #[serde(default)]
+    threshold_method: Option<ThresholdMethod>,
+    coordinate_file_name: String,
+    axis: Axis,
+    /// Pixel size (micrometer) of the resized working image that the outline coordinates refer to.
+    #[serde(default = "legacy_working_pixel_size")]
+    working_pixel_size: f64,
+    /// Name of the file as it was uploaded.
+    #[serde(default)]
+    original_file_name: String,
+    /// Where the unchanged upload is stored, relative to the sample folder: originals/{sha256}.{extension}.
+    /// Empty for images uploaded before, their original is stored as original_file_name.
+    #[serde(default)]
+    original_path: String,
+    #[serde(default)]
+    original_sha256: String,
+    #[serde(default)]
+    deleted_at: Option<u64>,
+    /// Every saved state of coordinates and axis, the last one is the current state.
+    #[serde(default)]
+    outline_revisions: Vec<OutlineRevision>,
+}
+
+/// What saved a revision of an outline.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+enum OutlineSource {
+    /// The outline stored before revisions were recorded.
+    Legacy,
+    /// Set with the black and white threshold in the browser.
+    Threshold,
+    /// Detected on the server.
+    Detected,
+    /// Vertices moved, added or deleted in the polygon editor.
+    Edited,
+    /// Copy of an earlier revision.
+    Reverted,
+}
+
+impl OutlineSource {
+    fn name(&self) -> &'static str {
+        match self {
+            OutlineSource::Legacy => "stored before revisions",
+            OutlineSource::Threshold => "bw threshold",
+            OutlineSource::Detected => "detected",
+            OutlineSource::Edited => "edited",
+            OutlineSource::Reverted => "reverted",
+        }
+    }
+}
+
+/// A saved state of the outline and axis of an image.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct OutlineRevision {
+    /// Numbered from 1 for each image.
+    revision: u32,
+    user_id: u16,
+    /// 0 for the legacy state, its time is unknown.
+    saved_at: u64,
+    source: OutlineSource,
+    #[serde(default)]
+    reverted_from: Option<u32>,
+    coordinates: Vec<Coordinates>,
+    outline_kind: OutlineKind,
+    axis: Axis,
+    #[serde(default)]
+    bw_threshold: Option<f64>,
+    #[serde(default)]
+    threshold_method: Option<ThresholdMethod>,
+}
+
+/// Images uploaded before the rescaling was configurable were always resized to 2 micrometer per pixel.
+fn legacy_working_pixel_size() -> f64 {
+    2.0
+}
+
+impl GrainImage {
+    /// The current outline and axis as a revision saved now.
+    fn outline_state(&self, user_id: u16, source: OutlineSource) -> OutlineRevision {
+        OutlineRevision {
+            revision: self.outline_revisions.last().map_or(1, |revision| revision.revision + 1),
+            user_id,
+            saved_at: util::timestamp_now(),
+            source,
+            reverted_from: None,
+            coordinates: self.coordinates.clone(),
+            outline_kind: self.outline_kind,
+            axis: self.axis.clone(),
+            bw_threshold: self.bw_threshold,
+            threshold_method: self.threshold_method,
+        }
+    }
+
+    /// All revisions, an outline stored before revisions were recorded is the first one.
+    fn outline_history(&self) -> Vec<OutlineRevision> {
+        if self.outline_revisions.is_empty() && (!self.coordinates.is_empty() || self.axis != Axis::default()) {
+            vec![OutlineRevision { saved_at: 0, ..self.outline_state(self.user_id, OutlineSource::Legacy) }]
+        } else {
+            self.outline_revisions.clone()
+        }
+    }
+
+    /// Makes the revision the current outline and axis and adds it to the history, only the last
+    /// MAX_OUTLINE_REVISIONS are kept. Returns false if it equals the current state, nothing is added then.
+    fn add_outline_revision(&mut self, mut revision: OutlineRevision) -> bool {
+        // The threshold page sends the threshold rounded to two digits.
+        let same_threshold = match (self.bw_threshold, revision.bw_threshold) {
+            (Some(current), Some(new)) => (current - new).abs() < 0.005,
+            (current, new) => current == new,
+        };
+
+        if same_threshold && self.coordinates == revision.coordinates && self.outline_kind == revision.outline_kind
+            && self.axis == revision.axis && self.threshold_method == revision.threshold_method {
+            return false
+        }
+
+        self.outline_revisions = self.outline_history();
+
+        revision.revision = self.outline_revisions.last().map_or(1, |last| last.revision + 1);
+        self.coordinates = revision.coordinates.clone();
+        self.outline_kind = revision.outline_kind;
+        self.axis = revision.axis.clone();
+        self.bw_threshold = revision.bw_threshold;
+        self.threshold_method = revision.threshold_method;
+        self.outline_revisions.push(revision);
+
+        let excess = self.outline_revisions.len().saturating_sub(MAX_OUTLINE_REVISIONS);
+        self.outline_revisions.drain(..excess);
+
+        true
+    }
+
+    /// The outline in the format of the coordinate files: whole pixels, left and right point of each row.
+    fn row_pairs(&self) -> Vec<Coordinates> {
+        match self.outline_kind {
+            OutlineKind::RowPairs => self.coordinates.clone(),
+            OutlineKind::Polygon => outline::polygon_to_row_pairs(&self.coordinates),
+        }
+    }
+
+    /// The outline as a closed polygon.
+    fn outline_polygon(&self) -> Vec<Coordinates> {
+        match self.outline_kind {
+            OutlineKind::Polygon => self.coordinates.clone(),
+            OutlineKind::RowPairs => outline::row_pairs_to_polygon(&self.coordinates),
+        }
+    }
+
+    /// Dimensions of the stored outline, None without an outline.
+    fn measurements(&self) -> Option<Measurements> {
+        measurements::measure(&self.outline_polygon(), &self.axis, self.working_pixel_size)
+    }
+
+    fn metadata(&self) -> ImageMetadata {
+        ImageMetadata {
+            size: self.size,
+            mode: self.mode,
+            mineral: self.mineral,
+            ratio_232_238: self.ratio_232_238,
+            ratio_147_238: self.ratio_147_238,
+            orientation: self.orientation,
+            shape: self.shape,
+            pyramids: self.pyramids,
+            broken_tips: self.broken_tips,
+            zoned: self.zoned,
+            rim_width: self.rim_width,
+            ratio_rim_core: self.ratio_rim_core,
+        }
+    }
+
+    fn set_metadata(&mut self, metadata: &ImageMetadata) {
+        self.size = metadata.size;
+        self.mode = metadata.mode;
+        self.mineral = metadata.mineral;
+        self.ratio_232_238 = metadata.ratio_232_238;
+        self.ratio_147_238 = metadata.ratio_147_238;
+        self.orientation = metadata.orientation;
+        self.shape = metadata.shape;
+        self.pyramids = metadata.pyramids;
+        self.broken_tips = metadata.broken_tips;
+        self.zoned = metadata.zoned;
+        self.rim_width = metadata.rim_width;
+        self.ratio_rim_core = metadata.ratio_rim_core;
+    }
+
+    /// Location of the uploaded original relative to the sample folder.
+    fn stored_original(&self) -> &str {
+        if self.original_path.is_empty() {
+            &self.original_file_name
+        } else {
+            &self.original_path
+        }
+    }
+}
+
+/// A sample groups the images of one grain. Its files are stored in user_data/{owner}/{folder}
+/// and matlab/{owner}/{folder}, the folder never changes when the sample is renamed.
+/// All images of a sample belong to the owner of the sample, even if a project member uploaded them.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+struct GrainSample {
+    id: u32,
+    user_id: u16,
+    #[serde(default)]
+    project_id: Option<u32>,
+    name: String,
+    folder: String,
+    description: String,
+    locality: String,
+    lithology: String,
+    collector: String,
+    created: u64,
+    notes: String,
+    #[serde(default)]
+    deleted_at: Option<u64>,
+    /// Set when parameters of its images change, cleared when a calculation is submitted.
+    /// A result of a sample with this set is outdated.
+    #[serde(default)]
+    parameters_changed_at: Option<u64>,
+}
+
+/// Groups samples of several users, ex. for a field campaign.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+struct GrainProject {
+    id: u32,
+    name: String,
+    owner_id: u16,
+    created: u64,
+    members: Vec<ProjectMember>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct ProjectMember {
+    user_id: u16,
+    permission: Permission,
+}
+
+/// Named values of all image parameters, used to fill the upload form and for bulk uploads.
+/// A preset shared with a project can be used by all of its members, only its owner changes it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct GrainPreset {
+    id: u32,
+    user_id: u16,
+    name: String,
+    #[serde(default)]
+    project_id: Option<u32>,
+    created: u64,
+    /// Users that fill the upload form with this preset.
+    #[serde(default)]
+    default_for: Vec<u16>,
+    metadata: ImageMetadata,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+enum JobState {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+    /// Stopped after the time limit of its engine.
+    TimedOut,
+}
+
+impl JobState {
+    fn is_finished(&self) -> bool {
+        !matches!(self, JobState::Queued | JobState::Running)
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            JobState::Queued => "queued",
+            JobState::Running => "running",
+            JobState::Succeeded => "succeeded",
+            JobState::Failed => "failed",
+            JobState::Cancelled => "cancelled",
+            JobState::TimedOut => "timed out",
+        }
+    }
+}
+
+/// A calculation of a sample, run by one of the job workers, see start_job_workers().
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct GrainJob {
+    id: u32,
+    sample_id: u32,
+    /// Who submitted the job.
+    user_id: u16,
+    engine: CalculationEngine,
+    /// Only used by the Monte Carlo engine.
+    #[serde(default)]
+    seed: Option<u64>,
+    state: JobState,
+    created: u64,
+    #[serde(default)]
+    started_at: Option<u64>,
+    #[serde(default)]
+    finished_at: Option<u64>,
+    /// Exit code of the process, None for engines without a process or if it was killed by a signal.
+    #[serde(default)]
+    exit_code: Option<i32>,
+    /// Why the job failed, empty otherwise.
+    #[serde(default)]
+    message: String,
+    #[serde(default)]
+    cancelled_by: Option<u16>,
+}
+
+/// Tells a running job when to stop: when it is cancelled or when its time is up.
+struct JobControl {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+}
+
+impl JobControl {
+    /// The state of the job if it has to stop now.
+    fn stop_state(&self) -> Option<JobState> {
+        if self.cancelled.load(Ordering::SeqCst) {
+            Some(JobState::Cancelled)
+        } else if self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            Some(JobState::TimedOut)
+        } else {
+            None
+        }
+    }
+}
+
+/// Rights on a sample. The owner of a sample and the owner of its project are always editors.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
+enum Permission {
+    Viewer,
+    Editor,
+}
+
+/// Revisions kept per image, they are stored in the grain database.
+const MAX_OUTLINE_REVISIONS: usize = 20;
+
+/// Largest side of the images on a contact sheet.
+const CONTACT_SHEET_CELL: u32 = 300;
+const CONTACT_SHEET_COLUMNS: u32 = 4;
+
+/// Smaller versions of the working image, cached in the previews folder of the sample.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum PreviewSize {
+    Thumbnail,
+    Medium,
+}
+
+impl PreviewSize {
+    fn all() -> [PreviewSize; 2] {
+        [PreviewSize::Thumbnail, PreviewSize::Medium]
+    }
+
+    /// As given in the size parameter of the image URL.
+    fn from_name(name: &str) -> Option<PreviewSize> {
+        PreviewSize::all().iter().find(|size| size.name() == name).cloned()
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            PreviewSize::Thumbnail => "thumb",
+            PreviewSize::Medium => "medium",
+        }
+    }
+
+    /// Longest side in pixel, smaller working images are not enlarged.
+    fn max_side(&self) -> u32 {
+        match self {
+            PreviewSize::Thumbnail => 160,
+            PreviewSize::Medium => 800,
+        }
+    }
+
+    /// Location of the preview of the given working image, relative to the sample folder.
+    fn file_name(&self, working_file_name: &str) -> String {
+        let stem = match working_file_name.rfind('.') {
+            Some(n) => &working_file_name[..n],
+            None => working_file_name,
+        };
+
+        format!("previews/{}_{}.jpg", stem, self.name())
+    }
+}
+
+/// Values of one image as entered in the upload form or read from the bulk upload spreadsheet.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct ImageMetadata {
+    size: f64,
+    mode: Mode,
+    mineral: Mineral,
+    ratio_232_238: f64,
+    ratio_147_238: f64,
+    orientation: Orientation,
+    shape: Shape,
+    pyramids: i32,
+    broken_tips: bool,
+    zoned: bool,
+    rim_width: f64,
+    ratio_rim_core: f64,
+}
+
+/// The values in the upload form for users without a default preset.
+impl Default for ImageMetadata {
+    fn default() -> ImageMetadata {
+        ImageMetadata {
+            size: 0.3998,
+            mode: Mode::Normal,
+            mineral: Mineral::Apatite,
+            ratio_232_238: 0.4444,
+            ratio_147_238: 0.0615,
+            orientation: Orientation::Parallel,
+            shape: Shape::Hexagonal,
+            pyramids: 0,
+            broken_tips: false,
+            zoned: false,
+            rim_width: 0.0,
+            ratio_rim_core: 1.0,
+        }
+    }
+}
+
+impl ImageMetadata {
+    /// Checks the ranges of the numbers and the combinations of values, one message per problem.
+    fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if self.size.is_nan() || self.size <= 0.0 {
+            errors.push(format!("size must be larger than 0, found {}", self.size));
+        }
+
+        for (field, value) in [("ratio_232_238", self.ratio_232_238), ("ratio_147_238", self.ratio_147_238), ("ratio_rim_core", self.ratio_rim_core)] {
+            if value.is_nan() || value < 0.0 {
+                errors.push(format!("{} must be a number >= 0, found {}", field, value));
+            }
+        }
+
+        if !(0..=2).contains(&self.pyramids) {
+            errors.push(format!("pyramids must be 0, 1 or 2, found {}", self.pyramids));
+        }
+
+        if self.zoned && (self.rim_width.is_nan() || self.rim_width <= 0.0) {
+            errors.push(format!("rim_width must be larger than 0 for a zoned grain, found {}", self.rim_width));
+        }
+
+        if !self.zoned && self.rim_width != 0.0 {
+            errors.push(format!("rim_width must be 0 for a grain that is not zoned, found {}", self.rim_width));
+        }
+
+        errors
+    }
+}
+
+/// One row of the bulk upload spreadsheet.
+#[derive(Clone, Debug, PartialEq)]
+struct BulkRow {
+    line: u64,
+    file_name: String,
+    metadata: ImageMetadata,
+}
+
+/// Columns of the bulk upload spreadsheet, named like the fields of GrainImage.
+const BULK_COLUMNS: [&str; 13] = ["file_name", "size", "mode", "mineral", "ratio_232_238", "ratio_147_238",
+    "orientation", "shape", "pyramids", "broken_tips", "zoned", "rim_width", "ratio_rim_core"];
+
+/// Contents of manifest.json in a workspace archive, see export_archive().
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct ArchiveManifest {
+    version: u32,
+    exported_at: u64,
+    samples: Vec<ArchiveSample>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct ArchiveSample {
+    owner: String,
+    sample: GrainSample,
+    images: Vec<GrainImage>,
+}
+
+const ARCHIVE_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct StorageUsage {
+    pub bytes: u64,
+    pub images: usize,
+    pub samples: usize,
+    pub quota: Quota,
+}
+
+impl StorageUsage {
+    /// Returns the reason if an upload of the given size would exceed the quota. The working copy
+    /// and the previews are only known once the image is stored, see undo_uploads_over_quota().
+    fn check_upload(&self, upload_bytes: u64) -> Option<String> {
+        if let Some(max_bytes) = self.quota.max_bytes {
+            if self.bytes + upload_bytes > max_bytes {
+                return Some(format!("Storage quota exceeded: {} used of {}, the upload needs {}",
+                    util::format_bytes(self.bytes), util::format_bytes(max_bytes), util::format_bytes(upload_bytes)))
+            }
+        }
+
+        if let Some(max_images) = self.quota.max_images {
+            if self.images + 1 > max_images {
+                return Some(format!("Image quota exceeded: {} of {} images used", self.images, max_images))
+            }
+        }
+
+        None
+    }
+
+    /// Returns the reason if the storage already used is above the quota.
+    fn check_stored(&self) -> Option<String> {
+        match self.quota.max_bytes {
+            Some(max_bytes) if self.bytes > max_bytes => Some(format!("Storage quota exceeded: {} of {} would be used with the working copies and previews of the upload",
+                util::format_bytes(self.bytes), util::format_bytes(max_bytes))),
+            _ => None,
+        }
+    }
+
+    /// Returns the reason if adding the given number of bytes, images and samples at once would exceed the quota.
+    fn check_import(&self, import_bytes: u64, import_images: usize, import_samples: usize) -> Option<String> {
+        if let Some(max_bytes) = self.quota.max_bytes {
+            if self.bytes + import_bytes > max_bytes {
+                return Some(format!("Storage quota exceeded: {} used of {}, {} more needed",
+                    util::format_bytes(self.bytes), util::format_bytes(max_bytes), util::format_bytes(import_bytes)))
+            }
+        }
+
+        if let Some(max_images) = self.quota.max_images {
+            if self.images + import_images > max_images {
+                return Some(format!("Image quota exceeded: {} of {} images used, {} more needed", self.images, max_images, import_images))
+            }
+        }
+
+        if let Some(max_samples) = self.quota.max_samples {
+            if self.samples + import_samples > max_samples {
+                return Some(format!("Sample quota exceeded: {} of {} samples used, {} more needed", self.samples, max_samples, import_samples))
+            }
+        }
+
+        None
+    }
+
+    /// Returns the reason if one more sample would exceed the quota.
+    fn check_new_sample(&self) -> Option<String> {
+        if let Some(max_samples) = self.quota.max_samples {
+            if self.samples + 1 > max_samples {
+                return Some(format!("Sample quota exceeded: {} of {} samples used", self.samples, max_samples))
+            }
+        }
+
+        None
+    }
+}
+
+lazy_static! {
+    static ref GRAIN_DB : Mutex<GrainList> = {
+        Mutex::new(GrainList::default())
+    };
+
+    /// Cancel flags of the running jobs by job id. Locked after the grain database.
+    static ref RUNNING_JOBS : Mutex<HashMap<u32, Arc<AtomicBool>>> = {
+        Mutex::new(HashMap::new())
+    };
+}
+
+fn get_db_lock<'a>() -> MutexGuard<'a, GrainList> {
+    loop {
+        let lock = GRAIN_DB.try_lock();
+        if let Ok(mutex) = lock {
+            return mutex
+        } else {
+            debug!("grain.rs, get_db_lock() -> thread_sleep");
+            // Sleep and try again to acquire the lock
+            let duration = time::Duration::from_millis(100);
+            thread::sleep(duration);
+        }
+    }
+}
+
+pub fn load_db() -> Result<(), failure::Error> {
+    debug!("utils.rs, load_db()");
+    let mut grain_db = get_db_lock();
+
+    let mut data = String::new();
+    let f = File::open(configuration::grain_db())?;
+    let mut f = BufReader::new(f);
+    f.read_to_string(&mut data)?;
+
+    let mut database: toml::Value = data.parse()?;
+    if let Some(toml::Value::Array(grains)) = database.get_mut("grains") {
+        for record in grains.iter_mut().filter_map(toml::Value::as_table_mut) {
+            let id = record.get("id").and_then(toml::Value::as_integer).unwrap_or(0);
+            for message in parameters::repair_legacy_codes(record) {
+                warn!("grain.rs, load_db(): image {}: {}", id, message);
+            }
+        }
+    }
+
+    let mut grain_list: GrainList = database.try_into()?;
+
+    if migrate_samples(&mut grain_list) {
+        save_db(&grain_list)?;
+    }
+
+    *grain_db = grain_list;
+    Ok(())
+}
+
+fn save_db(grain_db: &GrainList) -> Result<(), failure::Error> {
+    debug!("utils.rs, save_db()");
+
+    let serialized = toml::Value::try_from(grain_db)?.to_string();
+    let f = File::create(configuration::grain_db())?;
+    let mut f = BufWriter::new(f);
+    f.write_all(serialized.as_bytes())?;
+
+    Ok(())
+}
+
+/// Older databases only stored the sample name in each image. Create a sample record
+/// for each of these names, the existing folder named after the sample is kept.
+fn migrate_samples(grain_db: &mut GrainList) -> bool {
+    debug!("grain.rs, migrate_samples()");
+    let mut migrated = false;
+
+    for i in 0..grain_db.grains.len() {
+        if grain_db.grains[i].sample_id != 0 {
+            continue
+        }
+
+        let user_id = grain_db.grains[i].user_id;
+        let sample_name = grain_db.grains[i].sample_name.clone();
+
+        let sample_id = match grain_db.samples.iter().find(|sample| sample.user_id == user_id && sample.folder == sample_name) {
+            Some(sample) => sample.id,
+            None => {
+                let id = create_new_sample_id(&grain_db.samples);
+
+                grain_db.samples.push(GrainSample {
+                    id,
+                    user_id,
+                    name: sample_name.clone(),
+                    folder: sample_name,
+                    created: util::timestamp_now(),
+                    ..GrainSample::default()
+                });
+
+                id
+            }
+        };
+
+        grain_db.grains[i].sample_id = sample_id;
+        grain_db.grains[i].sample_name = String::new();
+        migrated = true;
+    }
+
+    migrated
+}
+
+fn find_sample(grain_db: &GrainList, sample_id: u32) -> Option<&GrainSample> {
+    grain_db.samples.iter().find(|sample| sample.id == sample_id)
+}
+
+fn sample_folder(grain_db: &GrainList, sample_id: u32) -> String {
+    match find_sample(grain_db, sample_id) {
+        Some(sample) => sample.folder.clone(),
+        None => format!("sample_{}", sample_id),
+    }
+}
+
+fn sample_name(grain_db: &GrainList, sample_id: u32) -> String {
+    match find_sample(grain_db, sample_id) {
+        Some(sample) => sample.name.clone(),
+        None => String::new(),
+    }
+}
+
+fn find_project(grain_db: &GrainList, project_id: u32) -> Option<&GrainProject> {
+    grain_db.projects.iter().find(|project| project.id == project_id)
+}
+
+fn project_permission(project: &GrainProject, user_id: u16) -> Option<Permission> {
+    if project.owner_id == user_id {
+        Some(Permission::Editor)
+    } else {
+        project.members.iter().find(|member| member.user_id == user_id).map(|member| member.permission)
+    }
+}
+
+fn sample_permission(grain_db: &GrainList, user_id: u16, sample: &GrainSample) -> Option<Permission> {
+    if sample.user_id == user_id {
+        return Some(Permission::Editor)
+    }
+
+    sample.project_id
+        .and_then(|project_id| find_project(grain_db, project_id))
+        .and_then(|project| project_permission(project, user_id))
+}
+
+fn image_permission(grain_db: &GrainList, user_id: u16, grain: &GrainImage) -> Option<Permission> {
+    find_sample(grain_db, grain.sample_id).and_then(|sample| sample_permission(grain_db, user_id, sample))
+}
+
+fn can_edit_image(grain_db: &GrainList, user_id: u16, grain: &GrainImage) -> bool {
+    image_permission(grain_db, user_id, grain) == Some(Permission::Editor)
+}
+
+/// Name of the sample as shown in selections, with owner and project for shared samples.
+fn sample_label(grain_db: &GrainList, user_id: u16, sample: &GrainSample) -> Result<String, failure::Error> {
+    let project_name = sample.project_id.and_then(|project_id| find_project(grain_db, project_id)).map(|project| project.name.clone());
+
+    Ok(match project_name {
+        Some(project_name) => format!("{} ({}, {})", sample.name, project_name, util::user_name(sample.user_id)?),
+        None if sample.user_id != user_id => format!("{} ({})", sample.name, util::user_name(sample.user_id)?),
+        None => sample.name.clone(),
+    })
+}
+
+fn list_of_grain_images(user_id: u16) -> Result<Vec<GrainImage>, failure::Error> {
+    debug!("grain.rs, list_of_grain_images()");
+    let grain_db = get_db_lock();
+
+    Ok(grain_db.grains.iter()
+        .filter(|grain| grain.user_id == user_id && grain.deleted_at.is_none())
+        .cloned().collect())
+}
+
+/// Images as shown in the load images page, with the name of their sample.
+/// Images in the trash are only listed for users that may restore them.
+fn grain_images_context(user_id: u16, trashed: bool) -> Result<Vec<serde_json::Value>, failure::Error> {
+    debug!("grain.rs, grain_images_context()");
+    let grain_db = get_db_lock();
+    let mut images = Vec::new();
+
+    for grain in grain_db.grains.iter().filter(|grain| grain.deleted_at.is_some() == trashed) {
+        let permission = match image_permission(&grain_db, user_id, grain) {
+            Some(permission) => permission,
+            None => continue,
+        };
+
+        if trashed && permission != Permission::Editor {
+            continue
+        }
+
+        let mut context = serde_json::to_value(grain)?;
+        context["sample_name"] = json!(sample_name(&grain_db, grain.sample_id));
+        context["deleted_at"] = json!(grain.deleted_at.map(util::format_timestamp));
+        context["working_pixel_size"] = json!(format!("{:.3}", grain.working_pixel_size));
+        context["mode"] = json!(grain.mode.name());
+        context["mineral"] = json!(grain.mineral.name());
+        context["orientation"] = json!(grain.orientation.name());
+        context["shape"] = json!(grain.shape.name());
+        context["url"] = json!(format!("/web_gui/grain/user_data/{}/{}/{}", util::user_name(grain.user_id)?, sample_folder(&grain_db, grain.sample_id), grain.file_name));
+        context["can_edit"] = json!(permission == Permission::Editor);
+        images.push(context);
+    }
+
+    Ok(images)
+}
+
+fn list_of_grain_samples(user_id: u16) -> Result<Vec<GrainSample>, failure::Error> {
+    debug!("grain.rs, list_of_grain_samples()");
+    let grain_db = get_db_lock();
+
+    Ok(grain_db.samples.iter()
+        .filter(|sample| sample.user_id == user_id && sample.deleted_at.is_none())
+        .cloned().collect())
+}
+
+/// Own samples and samples shared through projects with at least the given permission,
+/// as used in the sample selections.
+fn accessible_samples(user_id: u16, required: Permission) -> Result<Vec<serde_json::Value>, failure::Error> {
+    debug!("grain.rs, accessible_samples()");
+    let grain_db = get_db_lock();
+    let mut samples = Vec::new();
+
+    for sample in grain_db.samples.iter().filter(|sample| sample.deleted_at.is_none()) {
+        if let Some(permission) = sample_permission(&grain_db, user_id, sample) {
+            if permission >= required {
+                samples.push(json!({
+                    "id": sample.id,
+                    "name": sample.name,
+                    "label": sample_label(&grain_db, user_id, sample)?,
+                    "can_edit": permission == Permission::Editor,
+                }));
+            }
+        }
+    }
+
+    Ok(samples)
+}
+
+/// Samples as shown in the samples page, with the number of images.
+fn grain_samples_context(user_id: u16) -> Result<Vec<serde_json::Value>, failure::Error> {
+    debug!("grain.rs, grain_samples_context()");
+    let grain_db = get_db_lock();
+    let mut samples = Vec::new();
+
+    for sample in grain_db.samples.iter().filter(|sample| sample.deleted_at.is_none()) {
+        let permission = match sample_permission(&grain_db, user_id, sample) {
+            Some(permission) => permission,
+            None => continue,
+        };
+
+        let mut context = serde_json::to_value(sample)?;
+        context["created"] = json!(util::format_timestamp(sample.created));
+        context["images"] = json!(grain_db.grains.iter().filter(|grain| grain.sample_id == sample.id && grain.deleted_at.is_none()).count());
+        context["owner"] = json!(util::user_name(sample.user_id)?);
+        context["project_name"] = json!(sample.project_id.and_then(|project_id| find_project(&grain_db, project_id)).map(|project| project.name.clone()));
+        context["can_edit"] = json!(permission == Permission::Editor);
+        context["is_owner"] = json!(sample.user_id == user_id);
+        context["projects"] = json!(grain_db.projects.iter()
+            .filter(|project| project_permission(project, user_id) == Some(Permission::Editor))
+            .map(|project| json!({"id": project.id, "name": project.name, "selected": sample.project_id == Some(project.id)}))
+            .collect::<Vec<_>>());
+        samples.push(context);
+    }
+
+    Ok(samples)
+}
+
+/// The sample if the user has at least the given permission on it.
+fn sample_for_user(user_id: u16, sample_id: u32, required: Permission) -> Result<GrainSample, failure::Error> {
+    debug!("grain.rs, sample_for_user()");
+    let grain_db = get_db_lock();
+
+    let sample = grain_db.samples.iter()
+        .find(|sample| sample.id == sample_id && sample.deleted_at.is_none())
+        .ok_or(WebGuiError::SampleNotFoundForUser)?;
+
+    match sample_permission(&grain_db, user_id, sample) {
+        Some(permission) if permission >= required => Ok(sample.clone()),
+        Some(_) => Err(WebGuiError::SampleNotEditableForUser.into()),
+        None => Err(WebGuiError::SampleNotFoundForUser.into()),
+    }
+}
+
+fn sample_name_in_use(user_id: u16, name: &str, except_id: u32) -> Result<bool, failure::Error> {
+    debug!("grain.rs, sample_name_in_use()");
+    let grain_db = get_db_lock();
+
+    Ok(grain_db.samples.iter()
+        .any(|sample| sample.user_id == user_id && sample.name == name && sample.id != except_id && sample.deleted_at.is_none()))
+}
+
+fn create_new_sample_id(samples: &[GrainSample]) -> u32 {
+    // Sample ids start at 1, 0 marks images that have not been migrated yet.
+    samples.iter().map(|sample| sample.id).max().unwrap_or(0) + 1
+}
+
+fn add_grain_sample(mut new_sample: GrainSample) -> Result<u32, failure::Error> {
+    debug!("grain.rs, add_grain_sample()");
+    let mut grain_db = get_db_lock();
+
+    new_sample.id = create_new_sample_id(&grain_db.samples);
+    new_sample.folder = format!("sample_{}", new_sample.id);
+    let id = new_sample.id;
+    grain_db.samples.push(new_sample);
+
+    save_db(&grain_db)?;
+
+    Ok(id)
+}
+
+fn update_grain_sample(updated: GrainSample) -> Result<(), failure::Error> {
+    debug!("grain.rs, update_grain_sample()");
+    let mut grain_db = get_db_lock();
+
+    if let Some(sample) = grain_db.samples.iter_mut().find(|sample| sample.id == updated.id) {
+        sample.project_id = updated.project_id;
+        sample.name = updated.name;
+        sample.description = updated.description;
+        sample.locality = updated.locality;
+        sample.lithology = updated.lithology;
+        sample.collector = updated.collector;
+        sample.notes = updated.notes;
+    }
+
+    save_db(&grain_db)?;
+
+    Ok(())
+}
+
+/// Moves the sample and all of its images to the trash.
+fn trash_grain_sample(user_id: u16, sample_id: u32) -> Result<(), failure::Error> {
+    debug!("grain.rs, trash_grain_sample()");
+    let image_ids = {
+        let mut grain_db = get_db_lock();
+        let now = util::timestamp_now();
+
+        if let Some(sample) = grain_db.samples.iter_mut().find(|sample| sample.id == sample_id && sample.user_id == user_id) {
+            sample.deleted_at = Some(now);
+        }
+
+        save_db(&grain_db)?;
+
+        grain_db.grains.iter()
+            .filter(|grain| grain.sample_id == sample_id && grain.user_id == user_id)
+            .map(|grain| grain.id).collect::<Vec<_>>()
+    };
+
+    trash_grain_images(user_id, image_ids)?;
+
+    // Without any image there is nothing to restore, so remove the sample right away.
+    purge_grain_images(|_| false)?;
+
+    Ok(())
+}
+
+/// Projects the user owns or is a member of, as shown in the projects page.
+fn grain_projects_context(user_id: u16) -> Result<Vec<serde_json::Value>, failure::Error> {
+    debug!("grain.rs, grain_projects_context()");
+    let grain_db = get_db_lock();
+    let mut projects = Vec::new();
+
+    for project in grain_db.projects.iter() {
+        let permission = match project_permission(project, user_id) {
+            Some(permission) => permission,
+            None => continue,
+        };
+
+        let mut members = Vec::new();
+        for member in project.members.iter() {
+            members.push(json!({
+                "login_id": util::user_name(member.user_id)?,
+                "permission": member.permission,
+            }));
+        }
+
+        projects.push(json!({
+            "id": project.id,
+            "name": project.name,
+            "owner": util::user_name(project.owner_id)?,
+            "created": util::format_timestamp(project.created),
+            "permission": permission,
+            "is_owner": project.owner_id == user_id,
+            "members": members,
+            "samples": grain_db.samples.iter().filter(|sample| sample.project_id == Some(project.id) && sample.deleted_at.is_none()).count(),
+        }));
+    }
+
+    Ok(projects)
+}
+
+/// Projects the user may move own samples into.
+fn assignable_projects(user_id: u16) -> Result<Vec<serde_json::Value>, failure::Error> {
+    debug!("grain.rs, assignable_projects()");
+    let grain_db = get_db_lock();
+
+    Ok(grain_db.projects.iter()
+        .filter(|project| project_permission(project, user_id) == Some(Permission::Editor))
+        .map(|project| json!({"id": project.id, "name": project.name})).collect())
+}
+
+fn project_for_owner(user_id: u16, project_id: u32) -> Result<GrainProject, failure::Error> {
+    debug!("grain.rs, project_for_owner()");
+    let grain_db = get_db_lock();
+
+    grain_db.projects.iter()
+        .find(|project| project.id == project_id && project.owner_id == user_id)
+        .cloned().ok_or_else(|| WebGuiError::ProjectNotFoundForUser.into())
+}
+
+fn add_grain_project(name: String, owner_id: u16) -> Result<(), failure::Error> {
+    debug!("grain.rs, add_grain_project()");
+    let mut grain_db = get_db_lock();
+
+    let id = grain_db.projects.iter().map(|project| project.id).max().unwrap_or(0) + 1;
+
+    grain_db.projects.push(GrainProject {
+        id,
+        name,
+        owner_id,
+        created: util::timestamp_now(),
+        members: Vec::new(),
+    });
+
+    save_db(&grain_db)?;
+
+    Ok(())
+}
+
+/// Adds the member to the project or changes its permission. Without permission the member is removed.
+fn set_project_member(project_id: u32, member_id: u16, permission: Option<Permission>) -> Result<(), failure::Error> {
+    debug!("grain.rs, set_project_member()");
+    let mut grain_db = get_db_lock();
+
+    if let Some(project) = grain_db.projects.iter_mut().find(|project| project.id == project_id) {
+        project.members.retain(|member| member.user_id != member_id);
+
+        if let Some(permission) = permission {
+            project.members.push(ProjectMember { user_id: member_id, permission });
+        }
+    }
+
+    save_db(&grain_db)?;
+
+    Ok(())
+}
+
+/// Removes the project, its samples become private to their owners again.
+fn delete_grain_project(project_id: u32) -> Result<(), failure::Error> {
+    debug!("grain.rs, delete_grain_project()");
+    let mut grain_db = get_db_lock();
+
+    grain_db.projects.retain(|project| project.id != project_id);
+
+    for sample in grain_db.samples.iter_mut() {
+        if sample.project_id == Some(project_id) {
+            sample.project_id = None;
+        }
+    }
+
+    for preset in grain_db.presets.iter_mut() {
+        if preset.project_id == Some(project_id) {
+            preset.project_id = None;
+        }
+    }
+
+    save_db(&grain_db)?;
+
+    Ok(())
+}
+
+fn preset_permission(grain_db: &GrainList, user_id: u16, preset: &GrainPreset) -> Option<Permission> {
+    if preset.user_id == user_id {
+        return Some(Permission::Editor)
+    }
+
+    preset.project_id
+        .and_then(|project_id| find_project(grain_db, project_id))
+        .and_then(|project| project_permission(project, user_id))
+        .map(|_| Permission::Viewer)
+}
+
+/// The preset if it is one of the user or shared with one of their projects.
+fn find_preset(grain_db: &GrainList, user_id: u16, preset_id: u32) -> Option<&GrainPreset> {
+    grain_db.presets.iter().find(|preset| preset.id == preset_id && preset_permission(grain_db, user_id, preset).is_some())
+}
+
+/// Values of the given preset, or of the default preset of the user if none is given.
+fn preset_metadata(user_id: u16, preset_id: Option<u32>) -> Result<ImageMetadata, failure::Error> {
+    debug!("grain.rs, preset_metadata()");
+    let grain_db = get_db_lock();
+
+    let preset = match preset_id {
+        Some(preset_id) => Some(find_preset(&grain_db, user_id, preset_id).ok_or(WebGuiError::PresetNotFoundForUser)?),
+        None => grain_db.presets.iter()
+            .find(|preset| preset.default_for.contains(&user_id) && preset_permission(&grain_db, user_id, preset).is_some()),
+    };
+
+    Ok(preset.map(|preset| preset.metadata.clone()).unwrap_or_default())
+}
+
+/// Presets as listed in the load images page.
+fn grain_presets_context(user_id: u16) -> Result<Vec<serde_json::Value>, failure::Error> {
+    debug!("grain.rs, grain_presets_context()");
+    let grain_db = get_db_lock();
+    let mut presets = Vec::new();
+
+    for preset in grain_db.presets.iter() {
+        let permission = match preset_permission(&grain_db, user_id, preset) {
+            Some(permission) => permission,
+            None => continue,
+        };
+
+        let metadata = &preset.metadata;
+        presets.push(json!({
+            "id": preset.id,
+            "name": preset.name,
+            "owner": util::user_name(preset.user_id)?,
+            "project_name": preset.project_id.and_then(|project_id| find_project(&grain_db, project_id)).map(|project| project.name.clone()),
+            "is_default": preset.default_for.contains(&user_id),
+            "can_edit": permission == Permission::Editor,
+            "summary": format!("{} µm, {}, {}, {}, {}", metadata.size, metadata.mode.name(), metadata.mineral.name(), metadata.orientation.name(), metadata.shape.name()),
+            "projects": grain_db.projects.iter()
+                .filter(|project| project_permission(project, user_id) == Some(Permission::Editor))
+                .map(|project| json!({"id": project.id, "name": project.name, "selected": preset.project_id == Some(project.id)}))
+                .collect::<Vec<_>>(),
+        }));
+    }
+
+    Ok(presets)
+}
+
+fn add_grain_preset(user_id: u16, name: String, metadata: ImageMetadata) -> Result<(), failure::Error> {
+    debug!("grain.rs, add_grain_preset()");
+    let mut grain_db = get_db_lock();
+
+    let id = grain_db.presets.iter().map(|preset| preset.id).max().unwrap_or(0) + 1;
+
+    grain_db.presets.push(GrainPreset {
+        id,
+        user_id,
+        name,
+        project_id: None,
+        created: util::timestamp_now(),
+        default_for: Vec::new(),
+        metadata,
+    });
+
+    save_db(&grain_db)?;
+
+    Ok(())
+}
+
+fn preset_name_in_use(user_id: u16, name: &str) -> bool {
+    debug!("grain.rs, preset_name_in_use()");
+    let grain_db = get_db_lock();
+    grain_db.presets.iter().any(|preset| preset.user_id == user_id && preset.name == name)
+}
+
+/// Makes the preset the default of the user, or removes it as default if it already is.
+fn toggle_default_preset(user_id: u16, preset_id: u32) -> Result<(), failure::Error> {
+    debug!("grain.rs, toggle_default_preset()");
+    let mut grain_db = get_db_lock();
+
+    let was_default = find_preset(&grain_db, user_id, preset_id)
+        .ok_or(WebGuiError::PresetNotFoundForUser)?
+        .default_for.contains(&user_id);
+
+    for preset in grain_db.presets.iter_mut() {
+        preset.default_for.retain(|id| *id != user_id);
+
+        if preset.id == preset_id && !was_default {
+            preset.default_for.push(user_id);
+        }
+    }
+
+    save_db(&grain_db)?;
+
+    Ok(())
+}
+
+/// Shares the preset with a project (None: not shared) or deletes it. Only for the owner of the preset.
+fn update_grain_preset(user_id: u16, preset_id: u32, project_id: Option<u32>, delete: bool) -> Result<(), failure::Error> {
+    debug!("grain.rs, update_grain_preset()");
+    let mut grain_db = get_db_lock();
+
+    match find_preset(&grain_db, user_id, preset_id).and_then(|preset| preset_permission(&grain_db, user_id, preset)) {
+        Some(Permission::Editor) => {}
+        Some(_) => return Err(WebGuiError::PresetNotEditableForUser.into()),
+        None => return Err(WebGuiError::PresetNotFoundForUser.into()),
+    }
+
+    if let (false, Some(project_id)) = (delete, project_id) {
+        let can_share = find_project(&grain_db, project_id).and_then(|project| project_permission(project, user_id)) == Some(Permission::Editor);
+        if !can_share {
+            return Err(WebGuiError::ProjectNotFoundForUser.into())
+        }
+    }
+
+    if delete {
+        grain_db.presets.retain(|preset| preset.id != preset_id);
+    } else if let Some(preset) = grain_db.presets.iter_mut().find(|preset| preset.id == preset_id) {
+        preset.project_id = project_id;
+    }
+
+    save_db(&grain_db)?;
+
+    Ok(())
+}
+
+fn create_new_id() -> Result<u32, failure::Error> {
+    debug!("grain.rs, create_new_id()");
+    let grain_db = get_db_lock();
+
+    let num_of_elements = grain_db.grains.len();
+
+    if num_of_elements == 0 {
+        Ok(0)
+    } else {
+        Ok(grain_db.grains[num_of_elements - 1].id + 1)
+    }
+}
+
+fn add_grain_image(new_image: GrainImage) -> Result<(), failure::Error> {
+    debug!("grain.rs, add_grain_images()");
+    let mut grain_db = get_db_lock();
+    grain_db.grains.push(new_image);
+
+    save_db(&grain_db)?;
+
+    Ok(())
+}
+
+/// Ids of the given images that the user is allowed to change.
+fn editable_image_ids(grain_db: &GrainList, user_id: u16, image_ids: &[u32]) -> Vec<u32> {
+    grain_db.grains.iter()
+        .filter(|grain| image_ids.contains(&grain.id) && can_edit_image(grain_db, user_id, grain))
+        .map(|grain| grain.id).collect()
+}
+
+fn trash_grain_images(user_id: u16, image_ids: Vec<u32>) -> Result<(), failure::Error> {
+    debug!("grain.rs, trash_grain_images()");
+    let mut grain_db = get_db_lock();
+    let now = util::timestamp_now();
+    let image_ids = editable_image_ids(&grain_db, user_id, &image_ids);
+
+    for grain in grain_db.grains.iter_mut() {
+        if image_ids.contains(&grain.id) && grain.deleted_at.is_none() {
+            grain.deleted_at = Some(now);
+        }
+    }
+
+    save_db(&grain_db)?;
+
+    Ok(())
+}
+
+fn restore_grain_images(user_id: u16, image_ids: Vec<u32>) -> Result<(), failure::Error> {
+    debug!("grain.rs, restore_grain_images()");
+    let mut grain_db = get_db_lock();
+    let mut sample_ids = HashSet::new();
+    let image_ids = editable_image_ids(&grain_db, user_id, &image_ids);
+
+    for grain in grain_db.grains.iter_mut() {
+        if image_ids.contains(&grain.id) {
+            grain.deleted_at = None;
+            sample_ids.insert(grain.sample_id);
+        }
+    }
+
+    // Restoring an image of a removed sample brings back the sample as well.
+    for sample in grain_db.samples.iter_mut() {
+        if sample_ids.contains(&sample.id) {
+            sample.deleted_at = None;
+        }
+    }
+
+    save_db(&grain_db)?;
+
+    Ok(())
+}
+
+/// Changes the parameters of an image in place and moves it to the given sample, its outline is kept.
+/// The results of the samples involved are marked as outdated.
+fn update_grain_image(user_id: u16, id: u32, metadata: &ImageMetadata, sample_id: u32) -> Result<(), failure::Error> {
+    debug!("grain.rs, update_grain_image()");
+    let mut grain_db = get_db_lock();
+    let image_ids = editable_image_ids(&grain_db, user_id, &[id]);
+
+    let grain = grain_db.grains.iter()
+        .find(|grain| image_ids.contains(&grain.id) && grain.deleted_at.is_none())
+        .ok_or(WebGuiError::GrainImageNotFoundForUser)?.clone();
+
+    if sample_id != grain.sample_id {
+        let target = find_sample(&grain_db, sample_id)
+            .filter(|sample| sample.deleted_at.is_none())
+            .ok_or(WebGuiError::SampleNotFoundForUser)?;
+
+        if sample_permission(&grain_db, user_id, target) != Some(Permission::Editor) {
+            return Err(WebGuiError::SampleNotEditableForUser.into())
+        }
+
+        // The files stay in the folders of the owner and count against their quota.
+        if target.user_id != grain.user_id {
+            return Err(WebGuiError::ImageMovedToOtherOwner.into())
+        }
+
+        move_image_files(&grain_db, &grain, target)?;
+    }
+
+    let now = util::timestamp_now();
+    for sample in grain_db.samples.iter_mut().filter(|sample| sample.id == grain.sample_id || sample.id == sample_id) {
+        sample.parameters_changed_at = Some(now);
+    }
+
+    if let Some(grain) = grain_db.grains.iter_mut().find(|grain| grain.id == id) {
+        // The working image keeps its pixels, a corrected pixel size of the original changes the size of each of them.
+        if grain.size > 0.0 {
+            grain.working_pixel_size *= metadata.size / grain.size;
+        }
+
+        grain.set_metadata(metadata);
+        grain.sample_id = sample_id;
+    }
+
+    save_db(&grain_db)?;
+
+    Ok(())
+}
+
+/// Moves the working image, the original and the previews of an image into the folder of the target sample.
+/// The coordinate file is left out, the next calculation of the target sample writes it again.
+fn move_image_files(grain_db: &GrainList, grain: &GrainImage, target: &GrainSample) -> Result<(), failure::Error> {
+    debug!("grain.rs, move_image_files()");
+    let user_name = util::user_name(grain.user_id)?;
+    let folder = sample_folder(grain_db, grain.sample_id);
+    let from = format!("user_data/{}/{}", user_name, folder);
+    let to = format!("user_data/{}/{}", user_name, target.folder);
+
+    let mut files = vec![grain.file_name.clone()];
+    files.extend(PreviewSize::all().iter().map(|size| size.file_name(&grain.file_name)));
+    if !grain.stored_original().is_empty() {
+        files.push(grain.stored_original().to_string());
+    }
+
+    // Images uploaded before the files were kept may have some of them missing.
+    let files: Vec<_> = files.into_iter().filter(|file| Path::new(&format!("{}/{}", from, file)).exists()).collect();
+
+    if files.iter().any(|file| Path::new(&format!("{}/{}", to, file)).exists()) {
+        return Err(WebGuiError::ImageFileExistsInSample.into())
+    }
+
+    // An image in the trash may still use the same original.
+    let original_shared = grain_db.grains.iter()
+        .any(|other| other.id != grain.id && other.sample_id == grain.sample_id && other.stored_original() == grain.stored_original());
+
+    for file in files.iter() {
+        let destination = format!("{}/{}", to, file);
+        if let Some(parent) = Path::new(&destination).parent() {
+            create_dir_all(parent)?;
+        }
+
+        if original_shared && file == grain.stored_original() {
+            copy(format!("{}/{}", from, file), &destination)?;
+        } else {
+            rename(format!("{}/{}", from, file), &destination)?;
+        }
+    }
+
+    let coordinate_file = format!("matlab/{}/{}/{}", user_name, folder, grain.coordinate_file_name);
+    if Path::new(&coordinate_file).exists() {
+        remove_file(&coordinate_file)?;
+    }
+
+    Ok(())
+}
+
+/// All files on disk that belong to the given image: the original upload,
+/// the resized working image and the coordinate file written for matlab.
+fn image_files(grain_db: &GrainList, grain: &GrainImage) -> Result<Vec<String>, failure::Error> {
+    let user_name = util::user_name(grain.user_id)?;
+    let folder = sample_folder(grain_db, grain.sample_id);
+    let user_path = format!("user_data/{}/{}", user_name, folder);
+    let grain_folder = format!("matlab/{}/{}", user_name, folder);
+
+    let mut files = vec![
+        format!("{}/{}", user_path, grain.file_name),
+        format!("{}/{}", grain_folder, grain.coordinate_file_name),
+        // Written once per sample by submit_calculation() and matlab:
+        format!("{}/matlab_input.csv", grain_folder),
+        format!("{}/result.txt", grain_folder),
+    ];
+
+    if !grain.stored_original().is_empty() {
+        files.push(format!("{}/{}", user_path, grain.stored_original()));
+    }
+
+    for size in PreviewSize::all().iter() {
+        files.push(format!("{}/{}", user_path, size.file_name(&grain.file_name)));
+    }
+
+    Ok(files)
+}
+
+fn referenced_files(grain_db: &GrainList) -> Result<HashSet<String>, failure::Error> {
+    let mut files = HashSet::new();
+
+    for grain in grain_db.grains.iter() {
+        files.extend(image_files(grain_db, grain)?);
+    }
+
+    // The input and result of a live sample stay, even without images (ex. while its job is running).
+    for sample in grain_db.samples.iter().filter(|sample| sample.deleted_at.is_none()) {
+        let grain_folder = format!("matlab/{}/{}", util::user_name(sample.user_id)?, sample.folder);
+        files.insert(format!("{}/matlab_input.csv", grain_folder));
+        files.insert(format!("{}/result.txt", grain_folder));
+    }
+
+    Ok(files)
+}
+
+/// Removes all images matching the given predicate from the database and deletes
+/// their files. Removed samples without any remaining image are dropped from the
+/// database together with their folders. Files that cannot be deleted are logged and skipped.
+fn purge_grain_images<F>(should_purge: F) -> Result<usize, failure::Error> where F: Fn(&GrainImage) -> bool {
+    debug!("grain.rs, purge_grain_images()");
+    let mut grain_db = get_db_lock();
+
+    let (purged, remaining): (Vec<GrainImage>, Vec<GrainImage>) = grain_db.grains.drain(..).partition(|grain| should_purge(grain));
+    grain_db.grains = remaining;
+
+    let mut files = Vec::new();
+    for grain in purged.iter() {
+        files.extend(image_files(&grain_db, grain)?);
+    }
+
+    // Only removed samples lose their folders, live ones keep results and job logs.
+    let mut folders = Vec::new();
+    for sample in grain_db.samples.iter() {
+        if sample.deleted_at.is_some() && !grain_db.grains.iter().any(|grain| grain.sample_id == sample.id) {
+            let user_name = util::user_name(sample.user_id)?;
+            folders.push(format!("user_data/{}/{}", user_name, sample.folder));
+            folders.push(format!("matlab/{}/{}", user_name, sample.folder));
+        }
+    }
+
+    let num_of_samples = grain_db.samples.len();
+    let GrainList { ref grains, ref mut samples, .. } = *grain_db;
+    samples.retain(|sample| sample.deleted_at.is_none() || grains.iter().any(|grain| grain.sample_id == sample.id));
+
+    if purged.is_empty() && num_of_samples == grain_db.samples.len() {
+        return Ok(0)
+    }
+
+    save_db(&grain_db)?;
+
+    let still_used = referenced_files(&grain_db)?;
+
+    for file in files {
+        if !still_used.contains(&file) && Path::new(&file).exists() {
+            if let Err(e) = remove_file(&file) {
+                warn!("grain.rs, purge_grain_images(): file {} could not be deleted: {}", file, e);
+            }
+        }
+    }
+
+    for folder in folders {
+        if Path::new(&folder).exists() {
+            if let Err(e) = remove_dir_all(&folder) {
+                warn!("grain.rs, purge_grain_images(): folder {} could not be deleted: {}", folder, e);
+            }
+        }
+    }
+
+    Ok(purged.len())
+}
+
+fn purge_expired_trash() -> Result<usize, failure::Error> {
+    debug!("grain.rs, purge_expired_trash()");
+    let retention = configuration::trash_retention_days() * 24 * 60 * 60;
+    let now = util::timestamp_now();
+
+    purge_grain_images(|grain| match grain.deleted_at {
+        Some(deleted_at) => deleted_at + retention <= now,
+        None => false,
+    })
+}
+
+/// Purges expired images from the trash once per hour.
+pub fn start_trash_purger() {
+    debug!("grain.rs, start_trash_purger()");
+
+    thread::spawn(|| {
+        loop {
+            match purge_expired_trash() {
+                Ok(0) => {}
+                Ok(n) => info!("grain.rs, start_trash_purger(): {} image(s) purged from trash", n),
+                Err(e) => error!("grain.rs, start_trash_purger(): An error occured: {}", e),
+            }
+
+            thread::sleep(time::Duration::from_secs(60 * 60));
+        }
+    });
+}
+
+fn list_files(folder: &Path, files: &mut Vec<String>) -> io::Result<()> {
+    for entry in read_dir(folder)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            list_files(&path, files)?;
+        } else {
+            files.push(path.to_string_lossy().to_string());
+        }
+    }
+
+    Ok(())
+}
+
+fn folder_size(folder: &Path) -> io::Result<u64> {
+    let mut size = 0;
+
+    for entry in read_dir(folder)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+
+        if metadata.is_dir() {
+            size += folder_size(&entry.path())?;
+        } else {
+            size += metadata.len();
+        }
+    }
+
+    Ok(size)
+}
+
+/// Disk space (including the trash) and number of images and samples owned by the given user.
+pub fn storage_usage(user_id: u16, user_name: &str) -> Result<StorageUsage, failure::Error> {
+    debug!("grain.rs, storage_usage()");
+    let images = list_of_grain_images(user_id)?.len();
+    let samples = list_of_grain_samples(user_id)?.len();
+    let role = util::user_role(user_id)?;
+
+    let mut bytes = 0;
+    for folder in &[format!("user_data/{}", user_name), format!("matlab/{}", user_name)] {
+        if Path::new(folder).exists() {
+            bytes += folder_size(Path::new(folder))?;
+        }
+    }
+
+    Ok(StorageUsage {
+        bytes,
+        images,
+        samples,
+        quota: configuration::quota(user_name, role.name()),
+    })
+}
+
+/// Files in user_data/ and matlab/ that do not belong to any image in the database (including the trash).
+pub fn find_orphaned_files() -> Result<Vec<String>, failure::Error> {
+    debug!("grain.rs, find_orphaned_files()");
+    let grain_db = get_db_lock();
+
+    let referenced = referenced_files(&grain_db)?;
+    let mut files = Vec::new();
+
+    for folder in &["user_data", "matlab"] {
+        if Path::new(folder).exists() {
+            list_files(Path::new(folder), &mut files)?;
+        }
+    }
+
+    let mut orphans = files.into_iter().filter(|file| !referenced.contains(file)).collect::<Vec<_>>();
+    orphans.sort();
+
+    Ok(orphans)
+}
+
+fn save_preview(working_image: &DynamicImage, user_path: &str, working_file_name: &str, size: PreviewSize) -> Result<(), failure::Error> {
+    let max_side = size.max_side();
+    let preview = if working_image.width() > max_side || working_image.height() > max_side {
+        working_image.thumbnail(max_side, max_side)
+    } else {
+        working_image.clone()
+    };
+
+    let path = format!("{}/{}", user_path, size.file_name(working_file_name));
+    create_dir_all(format!("{}/previews", user_path))?;
+    DynamicImage::ImageRgb8(preview.to_rgb8()).save_with_format(path, ImageFormat::Jpeg)?;
+
+    Ok(())
+}
+
+/// Path of the preview of the given working image, it is created first if it is not cached yet
+/// (ex. for images uploaded before previews existed or imported from an archive).
+fn preview_path(user_path: &str, working_file_name: &str, size: PreviewSize) -> Result<String, failure::Error> {
+    debug!("grain.rs, preview_path()");
+    let path = format!("{}/{}", user_path, size.file_name(working_file_name));
+
+    if !Path::new(&path).exists() {
+        let working_image = image::open(format!("{}/{}", user_path, working_file_name))?;
+        save_preview(&working_image, user_path, working_file_name, size)?;
+    }
+
+    Ok(path)
+}
+
+fn list_of_selected_grain_images(sample_id: u32) -> Result<Vec<(String, u32)>, failure::Error> {
+    debug!("grain.rs, list_of_selected_grain_images()");
+    let grain_db = get_db_lock();
+
+    Ok(grain_db.grains.iter()
+        .filter(|grain| grain.sample_id == sample_id && grain.deleted_at.is_none())
+        .map(|grain| (grain.file_name.clone(), grain.id)).collect::<Vec<_>>())
+}
+
+/// Checks if the user may see the image stored in user_data/{owner_name}/{folder}/{file_name}.
+fn user_can_view_image(user_id: u16, owner_name: &str, folder: &str, file_name: &str) -> Result<bool, failure::Error> {
+    debug!("grain.rs, user_can_view_image()");
+    let grain_db = get_db_lock();
+
+    for grain in grain_db.grains.iter().filter(|grain| grain.file_name == file_name && grain.deleted_at.is_none()) {
+        if let Some(sample) = find_sample(&grain_db, grain.sample_id) {
+            if sample.folder == folder && util::user_name(sample.user_id)? == owner_name && sample_permission(&grain_db, user_id, sample).is_some() {
+                return Ok(true)
+            }
+        }
+    }
+
+    Ok(false)
+}
+
+/// Path of the working image, if the user may see it.
+fn working_image_path(user_id: u16, id: u32) -> Result<String, failure::Error> {
+    debug!("grain.rs, working_image_path()");
+    let grain_db = get_db_lock();
+
+    let grain = grain_db.grains.iter()
+        .find(|grain| grain.id == id && grain.deleted_at.is_none() && image_permission(&grain_db, user_id, grain).is_some())
+        .ok_or(WebGuiError::GrainImageNotFoundForUser)?;
+
+    Ok(format!("user_data/{}/{}/{}", util::user_name(grain.user_id)?, sample_folder(&grain_db, grain.sample_id), grain.file_name))
+}
+
+/// Ft of an image with the analytical engine, from the c-axis length and width of the outline.
+fn analytical_ft(grain: &GrainImage) -> Result<analytical::FtCorrection, String> {
+    if grain.orientation != Orientation::Parallel {
+        return Err("the c-axis must be parallel to the image to measure its length".to_string())
+    }
+
+    let measurements = grain.measurements().ok_or("no outline stored")?;
+    let (length, width) = match (measurements.length, measurements.width) {
+        (Some(length), Some(width)) => (length, width),
+        _ => return Err("no c-axis stored".to_string()),
+    };
+
+    analytical::ft_correction(grain.mineral, grain.shape, length, width, grain.pyramids, grain.ratio_232_238, grain.ratio_147_238)
+}
+
+/// Results of the analytical engine for all images of the sample, rounded for display.
+fn analytical_results_context(sample: &GrainSample) -> Result<Vec<serde_json::Value>, failure::Error> {
+    debug!("grain.rs, analytical_results_context()");
+    let grain_db = get_db_lock();
+    let mut images = Vec::new();
+
+    for grain in grain_db.grains.iter().filter(|grain| grain.sample_id == sample.id && grain.deleted_at.is_none()) {
+        let name = if grain.original_file_name.is_empty() {&grain.file_name} else {&grain.original_file_name};
+
+        images.push(match analytical_ft(grain) {
+            Ok(correction) => json!({
+                "name": name,
+                "shape": grain.shape.name(),
+                "mineral": grain.mineral.name(),
+                "volume": format!("{:.0}", correction.geometry.volume),
+                "surface": format!("{:.0}", correction.geometry.surface),
+                "rs": format!("{:.1}", correction.geometry.rs),
+                "ft": correction.ft.iter().map(|(isotope, ft)| json!({"isotope": isotope.name(), "value": format!("{:.3}", ft)})).collect::<Vec<_>>(),
+                "mean_ft": format!("{:.3}", correction.mean_ft),
+                "note": if grain.zoned {"zoned, calculated as homogeneous"} else {""},
+            }),
+            Err(message) => json!({"name": name, "error": message}),
+        });
+    }
+
+    Ok(images)
+}
+
+/// Measurements rounded for display, null without an outline.
+fn measurements_context(measurements: Option<Measurements>) -> serde_json::Value {
+    let optional = |value: Option<f64>, precision: usize| value.map_or_else(|| "-".to_string(), |value| format!("{:.*}", precision, value));
+
+    match measurements {
+        Some(measurements) => json!({
+            "area": format!("{:.0}", measurements.area),
+            "perimeter": format!("{:.1}", measurements.perimeter),
+            "hull_area": format!("{:.0}", measurements.hull_area),
+            "hull_perimeter": format!("{:.1}", measurements.hull_perimeter),
+            "length": optional(measurements.length, 1),
+            "width": optional(measurements.width, 1),
+            "aspect_ratio": optional(measurements.aspect_ratio, 2),
+            "circle_equivalent_radius": format!("{:.1}", measurements.circle_equivalent_radius),
+        }),
+        None => serde_json::Value::Null,
+    }
+}
+
+fn image_measurements(image_id: u32) -> Option<Measurements> {
+    debug!("grain.rs, image_measurements()");
+    let grain_db = get_db_lock();
+    grain_db.grains.iter().find(|grain| grain.id == image_id).and_then(|grain| grain.measurements())
+}
+
+/// Measurements of the images of all samples the user can see, for the calculate page.
+fn sample_measurements_context(user_id: u16) -> Result<Vec<serde_json::Value>, failure::Error> {
+    debug!("grain.rs, sample_measurements_context()");
+    let grain_db = get_db_lock();
+    let mut samples = Vec::new();
+
+    for sample in grain_db.samples.iter().filter(|sample| sample.deleted_at.is_none()) {
+        if sample_permission(&grain_db, user_id, sample).is_none() {
+            continue
+        }
+
+        let images: Vec<_> = grain_db.grains.iter()
+            .filter(|grain| grain.sample_id == sample.id && grain.deleted_at.is_none())
+            .map(|grain| json!({
+                "name": if grain.original_file_name.is_empty() {&grain.file_name} else {&grain.original_file_name},
+                "measurements": measurements_context(grain.measurements()),
+            }))
+            .collect();
+
+        if !images.is_empty() {
+            samples.push(json!({"label": sample_label(&grain_db, user_id, sample)?, "images": images}));
+        }
+    }
+
+    Ok(samples)
+}
+
+/// Pre-flight problems of a sample as a whole and of each of its images, see preflight.rs.
+struct SamplePreflight<'a> {
+    problems: Vec<String>,
+    images: Vec<(&'a GrainImage, Vec<String>)>,
+}
+
+fn sample_preflight<'a>(grain_db: &'a GrainList, sample: &GrainSample) -> SamplePreflight<'a> {
+    let images: Vec<_> = grain_db.grains.iter()
+        .filter(|grain| grain.sample_id == sample.id && grain.deleted_at.is_none())
+        .collect();
+    let metadata: Vec<_> = images.iter().map(|grain| grain.metadata()).collect();
+
+    SamplePreflight {
+        problems: preflight::sample_problems(&metadata),
+        images: images.into_iter()
+            .map(|grain| (grain, preflight::image_problems(&grain.metadata(), &grain.outline_polygon(), &grain.axis, grain.working_pixel_size)))
+            .collect(),
+    }
+}
+
+/// Reasons why the sample can not be submitted, empty if it is ready.
+fn preflight_problems(sample: &GrainSample) -> Vec<String> {
+    debug!("grain.rs, preflight_problems()");
+    let grain_db = get_db_lock();
+    let SamplePreflight { mut problems, images } = sample_preflight(&grain_db, sample);
+
+    for (grain, image_problems) in images {
+        let name = if grain.original_file_name.is_empty() {&grain.file_name} else {&grain.original_file_name};
+        problems.extend(image_problems.iter().map(|problem| format!("{}: {}", name, problem)));
+    }
+
+    problems
+}
+
+/// Checklist of every sample the user can see, for the calculate page.
+fn preflight_context(user_id: u16) -> Result<Vec<serde_json::Value>, failure::Error> {
+    debug!("grain.rs, preflight_context()");
+    let grain_db = get_db_lock();
+    let mut samples = Vec::new();
+
+    for sample in grain_db.samples.iter().filter(|sample| sample.deleted_at.is_none()) {
+        if sample_permission(&grain_db, user_id, sample).is_none() {
+            continue
+        }
+
+        let SamplePreflight { problems, images } = sample_preflight(&grain_db, sample);
+        let ready = problems.is_empty() && images.iter().all(|(_, image_problems)| image_problems.is_empty());
+
+        let images: Vec<_> = images.into_iter()
+            .map(|(grain, image_problems)| json!({
+                "name": if grain.original_file_name.is_empty() {&grain.file_name} else {&grain.original_file_name},
+                "orientation": grain.orientation.name(),
+                "outline": grain.outline_polygon().len() >= 3,
+                "axis": measurements::axis_direction(&grain.axis).is_some(),
+                "problems": image_problems,
+            }))
+            .collect();
+
+        samples.push(json!({
+            "label": sample_label(&grain_db, user_id, sample)?,
+            "ready": ready,
+            "problems": problems,
+            "images": images,
+        }));
+    }
+
+    Ok(samples)
+}
+
+/// Values of a parameter for a selection, with the given one selected.
+fn choice_options<T: Choice>(selected: T) -> Vec<serde_json::Value> {
+    T::ALL.iter()
+        .map(|choice| json!({"code": choice.code(), "name": choice.name(), "selected": *choice == selected}))
+        .collect()
+}
+
+fn yes_no_options(selected: bool) -> serde_json::Value {
+    json!([
+        {"code": 0, "name": "no", "selected": !selected},
+        {"code": 1, "name": "yes", "selected": selected},
+    ])
+}
+
+/// The edit form of an image, filled with the stored parameters or the values that were sent and rejected.
+/// Images can be moved to the editable samples of their owner.
+fn edit_image_context(user_id: u16, id: u32, values: Option<(&ImageMetadata, u32)>) -> Result<serde_json::Value, failure::Error> {
+    debug!("grain.rs, edit_image_context()");
+    let grain_db = get_db_lock();
+
+    let grain = grain_db.grains.iter()
+        .find(|grain| grain.id == id && grain.deleted_at.is_none() && can_edit_image(&grain_db, user_id, grain))
+        .ok_or(WebGuiError::GrainImageNotFoundForUser)?;
+    let stored = grain.metadata();
+    let (metadata, sample_id) = values.unwrap_or((&stored, grain.sample_id));
+
+    let mut samples = Vec::new();
+    for sample in grain_db.samples.iter().filter(|sample| sample.user_id == grain.user_id && sample.deleted_at.is_none()) {
+        if sample_permission(&grain_db, user_id, sample) == Some(Permission::Editor) {
+            samples.push(json!({
+                "id": sample.id,
+                "label": sample_label(&grain_db, user_id, sample)?,
+                "selected": sample.id == sample_id,
+            }));
+        }
+    }
+
+    let mut context = metadata_form_context(metadata);
+    context["id"] = json!(grain.id);
+    context["name"] = json!(if grain.original_file_name.is_empty() {&grain.file_name} else {&grain.original_file_name});
+    context["url"] = json!(format!("/web_gui/grain/user_data/{}/{}/{}", util::user_name(grain.user_id)?, sample_folder(&grain_db, grain.sample_id), grain.file_name));
+    context["samples"] = json!(samples);
+
+    Ok(context)
+}
+
+/// The parameters for the selections and inputs of the upload and edit forms.
+fn metadata_form_context(metadata: &ImageMetadata) -> serde_json::Value {
+    json!({
+        "size": metadata.size,
+        "modes": choice_options(metadata.mode),
+        "minerals": choice_options(metadata.mineral),
+        "ratio_232_238": metadata.ratio_232_238,
+        "ratio_147_238": metadata.ratio_147_238,
+        "orientations": choice_options(metadata.orientation),
+        "shapes": choice_options(metadata.shape),
+        "pyramids": (0..=2).map(|pyramids| json!({"code": pyramids, "name": pyramids, "selected": pyramids == metadata.pyramids})).collect::<Vec<_>>(),
+        "broken_tips": yes_no_options(metadata.broken_tips),
+        "zoned": yes_no_options(metadata.zoned),
+        "rim_width": metadata.rim_width,
+        "ratio_rim_core": metadata.ratio_rim_core,
+    })
+}
+
+/// Everything the outline editor shows of an image: the outline as a polygon, the axis and the revisions, newest first.
+fn outline_editor_context(user_id: u16, id: u32) -> Result<serde_json::Value, failure::Error> {
+    debug!("grain.rs, outline_editor_context()");
+    let grain_db = get_db_lock();
+
+    let grain = grain_db.grains.iter().find(|grain| grain.id == id && grain.deleted_at.is_none())
+        .ok_or(WebGuiError::GrainImageNotFoundForUser)?;
+    let permission = image_permission(&grain_db, user_id, grain).ok_or(WebGuiError::GrainImageNotFoundForUser)?;
+    let path = format!("{}/{}/{}", util::user_name(grain.user_id)?, sample_folder(&grain_db, grain.sample_id), grain.file_name);
+    let width = image::image_dimensions(format!("user_data/{}", path)).map(|(width, _)| width).unwrap_or(0);
+
+    let polygon = grain.outline_polygon();
+
+    let history = grain.outline_history();
+    let current = history.last().map(|revision| revision.revision);
+    let mut revisions = Vec::new();
+    for revision in history.iter().rev() {
+        let source = match revision.reverted_from {
+            Some(old) => format!("{} to revision {}", revision.source.name(), old),
+            None => revision.source.name().to_string(),
+        };
+
+        revisions.push(json!({
+            "revision": revision.revision,
+            "author": util::user_name(revision.user_id).unwrap_or_else(|_| "unknown".to_string()),
+            "saved_at": if revision.saved_at == 0 {"unknown".to_string()} else {util::format_timestamp(revision.saved_at)},
+            "source": source,
+            "points": revision.coordinates.len(),
+            "current": Some(revision.revision) == current,
+        }));
+    }
+
+    Ok(json!({
+        "id": grain.id,
+        "path": path,
+        "width": width,
+        "polygon": serde_json::to_string(&polygon)?,
+        "axis": serde_json::to_string(&grain.axis)?,
+        "bw_threshold": grain.bw_threshold,
+        "threshold_method": grain.threshold_method.map(|method| method.name()),
+        "can_edit": permission == Permission::Editor,
+        "measurements": measurements_context(grain.measurements()),
+        "revisions": revisions,
+    }))
+}
+
+/// The images with their working image path for drawing overlays: one image or all images of a sample.
+fn overlay_images(user_id: u16, image_id: Option<u32>, sample_id: Option<u32>) -> Result<Vec<(GrainImage, String)>, failure::Error> {
+    debug!("grain.rs, overlay_images()");
+    let grain_db = get_db_lock();
+    let mut result = Vec::new();
+
+    for grain in grain_db.grains.iter().filter(|grain| grain.deleted_at.is_none()) {
+        if image_id.is_none_or(|id| id == grain.id) && sample_id.is_none_or(|id| id == grain.sample_id)
+            && image_permission(&grain_db, user_id, grain).is_some() {
+            let path = format!("user_data/{}/{}/{}", util::user_name(grain.user_id)?, sample_folder(&grain_db, grain.sample_id), grain.file_name);
+            result.push((grain.clone(), path));
+        }
+    }
+
+    Ok(result)
+}
+
+/// The stored outline and axis drawn onto the working image, reduced to max_side.
+fn render_image_overlay(grain: &GrainImage, path: &str, max_side: u32) -> Result<RgbImage, failure::Error> {
+    let image = image::open(path)?;
+    Ok(overlay::render_overlay(&image, &grain.outline_polygon(), &grain.axis, grain.working_pixel_size, max_side))
+}
+
+fn png_response(image: RgbImage) -> Result<Response, failure::Error> {
+    let mut data = Vec::new();
+    DynamicImage::ImageRgb8(image).write_to(&mut Cursor::new(&mut data), ImageOutputFormat::Png)?;
+
+    Ok(Response::from_data("image/png", data))
+}
+
+/// The threshold and method of the stored outline of an image.
+fn stored_threshold(id: u32) -> Option<(f64, ThresholdMethod)> {
+    debug!("grain.rs, stored_threshold()");
+    let grain_db = get_db_lock();
+
+    grain_db.grains.iter().find(|grain| grain.id == id)
+        .and_then(|grain| grain.bw_threshold.and_then(|threshold| grain.threshold_method.map(|method| (threshold, method))))
+}
+
+/// Reads the threshold method and value from the form: without a method the threshold is manual
+/// if a value is given and otherwise chosen with Otsu's method.
+fn requested_threshold(method: &Option<String>, bw_threshold: Option<f64>) -> Result<(ThresholdMethod, Option<f64>), failure::Error> {
+    let method = match method.as_ref().map(|method| method.as_str()) {
+        None | Some("") if bw_threshold.is_some() => ThresholdMethod::Manual,
+        None | Some("") => ThresholdMethod::Otsu,
+        Some(name) => ThresholdMethod::from_name(name).ok_or(WebGuiError::UnknownThresholdMethod)?,
+    };
+
+    match (method, bw_threshold) {
+        (ThresholdMethod::Manual, None) => Err(WebGuiError::UnknownThresholdMethod.into()),
+        (ThresholdMethod::Manual, Some(value)) => Ok((method, Some(value.clamp(0.0, 1.0)))),
+        _ => Ok((method, None)),
+    }
+}
+
+/// Finds the outline of the grain in a working image, returns it with the threshold that was used
+/// (for the adaptive method the one of the whole image).
+fn detect_outline(image: &image::DynamicImage, method: ThresholdMethod, bw_threshold: Option<f64>, kind: OutlineKind) -> (Vec<Coordinates>, f64) {
+    let (threshold, value) = match method {
+        ThresholdMethod::Manual => {
+            let value = bw_threshold.unwrap_or(0.5);
+            (outline::Threshold::Global(value), value)
+        }
+        ThresholdMethod::Otsu => {
+            let value = outline::otsu_threshold(image);
+            (outline::Threshold::Global(value), value)
+        }
+        ThresholdMethod::Adaptive => (outline::adaptive_threshold(image), outline::otsu_threshold(image)),
+    };
+
+    let coordinates = match kind {
+        OutlineKind::Polygon => outline::trace_outline(image, &threshold),
+        OutlineKind::RowPairs => outline::detect_outline(image, &threshold),
+    };
+
+    (coordinates, value)
+}
+
+/// Detects and stores the outlines of all images of the sample, returns the number of images
+/// that could be processed and the total number of images.
+fn detect_outlines_for_sample(user_id: u16, sample: &GrainSample, method: ThresholdMethod, bw_threshold: Option<f64>, kind: OutlineKind) -> Result<(usize, usize), failure::Error> {
+    debug!("grain.rs, detect_outlines_for_sample()");
+    let user_path = format!("user_data/{}/{}", util::user_name(sample.user_id)?, sample.folder);
+    let images = list_of_selected_grain_images(sample.id)?;
+
+    // The images are processed without holding the database lock.
+    let mut outlines = Vec::new();
+    for (file_name, id) in images.iter() {
+        match image::open(format!("{}/{}", user_path, file_name)) {
+            Ok(image) => outlines.push((*id, detect_outline(&image, method, bw_threshold, kind))),
+            Err(e) => warn!("grain.rs, detect_outlines_for_sample(): image {} could not be read: {}", file_name, e),
+        }
+    }
+
+    let mut grain_db = get_db_lock();
+    for (id, (coordinates, value)) in outlines.iter() {
+        if let Some(grain) = grain_db.grains.iter_mut().find(|grain| grain.id == *id) {
+            let revision = OutlineRevision {
+                coordinates: coordinates.clone(),
+                outline_kind: kind,
+                bw_threshold: Some(*value),
+                threshold_method: Some(method),
+                ..grain.outline_state(user_id, OutlineSource::Detected)
+            };
+            grain.add_outline_revision(revision);
+        }
+    }
+
+    save_db(&grain_db)?;
+
+    Ok((outlines.len(), images.len()))
+}
+
+/// Outline and axis of one image as sent by the outline editor or the threshold page.
+struct PostedOutline {
+    image_id: u32,
+    coordinates: Vec<Coordinates>,
+    kind: OutlineKind,
+    axis: Axis,
+    threshold: Option<(f64, ThresholdMethod)>,
+}
+
+/// Position of the image in the database if the user can change it, otherwise the reason why not.
+fn editable_image_index(grain_db: &GrainList, user_id: u16, id: u32) -> Result<usize, failure::Error> {
+    let index = grain_db.grains.iter().position(|grain| grain.id == id && grain.deleted_at.is_none())
+        .ok_or(WebGuiError::GrainImageNotFoundForUser)?;
+
+    match image_permission(grain_db, user_id, &grain_db.grains[index]) {
+        Some(Permission::Editor) => Ok(index),
+        Some(_) => Err(WebGuiError::SampleNotEditableForUser.into()),
+        None => Err(WebGuiError::GrainImageNotFoundForUser.into()),
+    }
+}
+
+/// Stores the outlines and axes as new revisions. Polygons come from the outline editor,
+/// row pairs from the threshold in the browser. Nothing is stored if one of the images can not be changed.
+fn save_outlines(user_id: u16, outlines: Vec<PostedOutline>) -> Result<(), failure::Error> {
+    debug!("grain.rs, save_outlines()");
+    let mut grain_db = get_db_lock();
+
+    let indices = outlines.iter()
+        .map(|outline| editable_image_index(&grain_db, user_id, outline.image_id))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut changed = false;
+    for (index, outline) in indices.into_iter().zip(outlines) {
+        let grain = &mut grain_db.grains[index];
+        let source = match outline.kind {
+            OutlineKind::Polygon => OutlineSource::Edited,
+            OutlineKind::RowPairs => OutlineSource::Threshold,
+        };
+        let revision = OutlineRevision {
+            coordinates: outline.coordinates,
+            outline_kind: outline.kind,
+            axis: outline.axis,
+            bw_threshold: outline.threshold.map(|(value, _)| value),
+            threshold_method: outline.threshold.map(|(_, method)| method),
+            ..grain.outline_state(user_id, source)
+        };
+        changed |= grain.add_outline_revision(revision);
+    }
+
+    if changed {
+        save_db(&grain_db)?;
+    }
+
+    Ok(())
+}
+
+/// Makes an earlier revision the current outline again, as a new revision.
+fn revert_outline(user_id: u16, id: u32, revision: u32) -> Result<(), failure::Error> {
+    debug!("grain.rs, revert_outline()");
+    let mut grain_db = get_db_lock();
+    let index = editable_image_index(&grain_db, user_id, id)?;
+
+    let grain = &mut grain_db.grains[index];
+    let old = grain.outline_history().into_iter().find(|old| old.revision == revision)
+        .ok_or(WebGuiError::OutlineRevisionNotFound)?;
+
+    let revision = OutlineRevision {
+        user_id,
+        saved_at: util::timestamp_now(),
+        source: OutlineSource::Reverted,
+        reverted_from: Some(old.revision),
+        ..old
+    };
+    grain.add_outline_revision(revision);
+
+    save_db(&grain_db)?;
+
+    Ok(())
+}
+
+/// Checks coordinates sent by the browser: finite, not negative and for polygons at least three points.
+fn valid_outline(coordinates: &[Coordinates], kind: OutlineKind) -> bool {
+    let min_points = match kind {
+        OutlineKind::Polygon => 3,
+        OutlineKind::RowPairs => 0,
+    };
+
+    coordinates.len() >= min_points
+        && coordinates.iter().all(|point| point.x.is_finite() && point.y.is_finite() && point.x >= 0.0 && point.y >= 0.0)
+}
+
+/// Writes the input of external programs for the sample and returns the paths of the input file,
+/// the result and the sample folder.
+fn prepare_input(sample: &GrainSample) -> Result<(String, String, String), failure::Error> {
+    debug!("grain.rs, prepare_input()");
+    let mut grain_db = get_db_lock();
+
+    let grain_folder = format!("matlab/{}/{}", util::user_name(sample.user_id)?, sample.folder);
+    create_dir_all(&grain_folder)?;
+
+    let input_file = format!("{}/matlab_input.csv", grain_folder);
+    let f = File::create(&input_file)?;
+    let mut grain_file = BufWriter::new(f);
+
+    // Write out header
+    write!(grain_file, "# coordinate file, sample name, size, mode, mineral, ratio 232-238, ratio 147-238, orientation, shape, pyramids, broken tips, zoned, rim width, ratio rim core, axis x1, axis y1, axis x2, axis y2, working pixel size, {}\n", Measurements::COLUMNS.join(", "))?;
+
+    for grain in grain_db.grains.iter() {
+        if grain.sample_id == sample.id && grain.deleted_at.is_none() {
+            write!(grain_file, "{}, ", grain.coordinate_file_name)?;
+            write!(grain_file, "{}, ", sample.name)?;
+            write!(grain_file, "{}, ", grain.size)?;
+            write!(grain_file, "{}, ", grain.mode.name())?;
+            write!(grain_file, "{}, ", grain.mineral.name())?;
+            write!(grain_file, "{}, ", grain.ratio_232_238)?;
+            write!(grain_file, "{}, ", grain.ratio_147_238)?;
+            write!(grain_file, "{}, ", grain.orientation.name())?;
+            write!(grain_file, "{}, ", grain.shape.name())?;
+            write!(grain_file, "{}, ", grain.pyramids)?;
+            write!(grain_file, "{}, ", grain.broken_tips)?;
+            write!(grain_file, "{}, ", grain.zoned)?;
+            write!(grain_file, "{}, ", grain.rim_width)?;
+            write!(grain_file, "{}, ", grain.ratio_rim_core)?;
+            write!(grain_file, "{}, ", grain.axis.x1)?;
+            write!(grain_file, "{}, ", grain.axis.y1)?;
+            write!(grain_file, "{}, ", grain.axis.x2)?;
+            write!(grain_file, "{}, ", grain.axis.y2)?;
+            write!(grain_file, "{}", grain.working_pixel_size)?;
+
+            // Measured from the outline, NaN without an outline or axis.
+            let values = grain.measurements().map_or([f64::NAN; 8], |measurements| measurements.values());
+            for value in values.iter() {
+                write!(grain_file, ", {}", value)?;
+            }
+            writeln!(grain_file)?;
+
+            let f = File::create(format!("{}/{}", grain_folder, grain.coordinate_file_name))?;
+            let mut coordinates_file = BufWriter::new(f);
+
+            for coordinate in grain.row_pairs().iter() {
+                write!(coordinates_file, "{}, {}\n", coordinate.x, coordinate.y)?;
+            }
+        }
+    }
+
+    let output_file = clear_result(&mut grain_db, sample)?;
+    let current_folder = env::current_dir()?;
+
+    Ok((input_file, output_file, format!("{}/{}", current_folder.display(), grain_folder)))
+
+/*
+    Test on MacOS:
+            /Applications/MATLAB_R2018a.app/bin/matlab -nodisplay -nosplash -nodesktop -sd /Users/willi/tmp/FT_model_180419 -r "input_file='matlab_input.csv';output_file='result.txt';grain_folder='/Users/willi/tmp/git/web_gui/matlab/test_user/test1';run('run_3DFt.m')"
+
+    Test on Linux (webserver):
+*/
+
+}
+
+/// Picks the first outlined image of the sample with the c-axis parallel to the field of view and the first one
+/// with the c-axis perpendicular to it for the Monte Carlo engine and returns them with the path of result.txt.
+/// Mineral, ratios, broken tips and zoning are the ones of the parallel image.
+fn prepare_monte_carlo(sample: &GrainSample) -> Result<(GrainImage, GrainImage, String), failure::Error> {
+    debug!("grain.rs, prepare_monte_carlo()");
+    let mut grain_db = get_db_lock();
+
+    let outlined_view = |orientation: Orientation| grain_db.grains.iter()
+        .find(|grain| grain.sample_id == sample.id && grain.deleted_at.is_none() && grain.orientation == orientation && !grain.coordinates.is_empty())
+        .cloned();
+
+    let (parallel, perpendicular) = match (outlined_view(Orientation::Parallel), outlined_view(Orientation::Perpendicular)) {
+        (Some(parallel), Some(perpendicular)) => (parallel, perpendicular),
+        _ => return Err(WebGuiError::MonteCarloViewsMissing.into()),
+    };
+
+    let output_file = clear_result(&mut grain_db, sample)?;
+
+    Ok((parallel, perpendicular, output_file))
+}
+
+/// Removes result.txt of the sample before a new calculation and returns its path.
+fn clear_result(grain_db: &mut GrainList, sample: &GrainSample) -> Result<String, failure::Error> {
+    let grain_folder = format!("matlab/{}/{}", util::user_name(sample.user_id)?, sample.folder);
+    create_dir_all(&grain_folder)?;
+
+    let output_file = format!("{}/result.txt", grain_folder);
+    if Path::new(&output_file).exists() {
+        remove_file(&output_file)?;
+    }
+
+    // The new result uses the current parameters.
+    if let Some(sample) = grain_db.samples.iter_mut().find(|stored| stored.id == sample.id) {
+        sample.parameters_changed_at = None;
+    }
+    save_db(grain_db)?;
+
+    Ok(output_file)
+}
+
+/// Runs the Monte Carlo engine, the result has a header with the settings and one line of values.
+/// None if stop returned true before the simulation was done.
+fn monte_carlo_result(sample_name: &str, parallel: &GrainImage, perpendicular: &GrainImage, seed: u64, stop: &(dyn Fn() -> bool + Sync)) -> Result<Option<String>, String> {
+    let zoning = if parallel.zoned {
+        Some(Zoning { rim_width: parallel.rim_width, ratio_rim_core: parallel.ratio_rim_core })
+    } else {
+        None
+    };
+
+    let grain = monte_carlo::reconstruct(&parallel.outline_polygon(), &parallel.axis, parallel.working_pixel_size,
+        &perpendicular.outline_polygon(), perpendicular.working_pixel_size, zoning, MONTE_CARLO_VOXELS)?;
+
+    let settings = monte_carlo::Settings {
+        particles: MONTE_CARLO_PARTICLES,
+        seed,
+        threads: thread::available_parallelism().map(|threads| threads.get()).unwrap_or(1),
+    };
+    let correction = match monte_carlo::ft_correction(&grain, parallel.mineral, parallel.broken_tips, parallel.ratio_232_238, parallel.ratio_147_238, &settings, stop) {
+        Some(correction) => correction,
+        None => return Ok(None),
+    };
+
+    let name = |grain: &GrainImage| if grain.original_file_name.is_empty() {grain.file_name.clone()} else {grain.original_file_name.clone()};
+    let ft: Vec<_> = correction.ft.iter().map(|(_, ft)| format!("{:.4}", ft)).collect();
+    let ft_columns: Vec<_> = correction.ft.iter().map(|(isotope, _)| format!("Ft {}", isotope.name())).collect();
+
+    Ok(Some(format!("# Monte Carlo alpha ejection, seed {}, {} particles per isotope, voxel size {:.3} micrometer\n\
+        # sample name, parallel image, perpendicular image, mineral, broken tips, zoned, volume, {}, mean Ft\n\
+        {}, {}, {}, {}, {}, {}, {:.0}, {}, {:.4}\n",
+        seed, MONTE_CARLO_PARTICLES, grain.voxel_size(), ft_columns.join(", "),
+        sample_name, name(parallel), name(perpendicular), parallel.mineral.name(), parallel.broken_tips, parallel.zoned,
+        grain.volume(), ft.join(", "), correction.mean_ft)))
+}
+
+/// The analytical engine as a job: one line per image that could be calculated, the others are
+/// listed as comments with the reason.
+fn analytical_result(sample: &GrainSample) -> String {
+    debug!("grain.rs, analytical_result()");
+    let grain_db = get_db_lock();
+    let mut result = "# Ft of the idealized shapes with the stopping distances of Ketcham et al. 2011, rs for comparison\n\
+        # sample name, image, shape, mineral, volume, surface, rs, Ft per isotope, mean Ft\n".to_string();
+
+    for grain in grain_db.grains.iter().filter(|grain| grain.sample_id == sample.id && grain.deleted_at.is_none()) {
+        let name = if grain.original_file_name.is_empty() {&grain.file_name} else {&grain.original_file_name};
+
+        match analytical_ft(grain) {
+            Ok(correction) => {
+                let ft: Vec<_> = correction.ft.iter().map(|(isotope, ft)| format!("{} {:.4}", isotope.name(), ft)).collect();
+                result.push_str(&format!("{}, {}, {}, {}, {:.0}, {:.0}, {:.1}, {}, {:.4}\n", sample.name, name, grain.shape.name(), grain.mineral.name(),
+                    correction.geometry.volume, correction.geometry.surface, correction.geometry.rs, ft.join(" "), correction.mean_ft));
+            }
+            Err(message) => result.push_str(&format!("# {}: {}\n", name, message)),
+        }
+    }
+
+    result
+}
+
+/// Queues a calculation of the sample, refused with a message while another one of the sample is not finished.
+fn queue_job(user_id: u16, sample: &GrainSample, engine: CalculationEngine, seed: Option<u64>) -> Result<Result<u32, String>, failure::Error> {
+    debug!("grain.rs, queue_job()");
+    let mut grain_db = get_db_lock();
+
+    if grain_db.jobs.iter().any(|job| job.sample_id == sample.id && !job.state.is_finished()) {
+        return Ok(Err(format!("a calculation of sample {} is already queued or running", sample.name)))
+    }
+
+    let id = grain_db.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+
+    grain_db.jobs.push(GrainJob {
+        id,
+        sample_id: sample.id,
+        user_id,
+        engine,
+        seed,
+        state: JobState::Queued,
+        created: util::timestamp_now(),
+        started_at: None,
+        finished_at: None,
+        exit_code: None,
+        message: String::new(),
+        cancelled_by: None,
+    });
+
+    save_db(&grain_db)?;
+
+    Ok(Ok(id))
+}
+
+/// Starts the configured number of job workers. Jobs that were running when the server stopped are marked as failed.
+pub fn start_job_workers() {
+    debug!("grain.rs, start_job_workers()");
+
+    {
+        let mut grain_db = get_db_lock();
+        let now = util::timestamp_now();
+
+        for job in grain_db.jobs.iter_mut().filter(|job| job.state == JobState::Running) {
+            job.state = JobState::Failed;
+            job.finished_at = Some(now);
+            job.message = "the server stopped while the job was running".to_string();
+        }
+
+        if let Err(e) = save_db(&grain_db) {
+            error!("grain.rs, start_job_workers(): An error occured: {}", e);
+        }
+    }
+
+    for _ in 0..configuration::calculation_workers() {
+        thread::spawn(|| {
+            loop {
+                match next_job() {
+                    Ok(Some((job, control))) => {
+                        let (state, exit_code, message) = match run_job(&job, &control) {
+                            Ok(outcome) => outcome,
+                            Err(e) => (JobState::Failed, None, e.to_string()),
+                        };
+
+                        if let Err(e) = finish_job(job.id, state, exit_code, message) {
+                            error!("grain.rs, start_job_workers(): job {} could not be stored: {}", job.id, e);
+                        }
+                    }
+                    Ok(None) => thread::sleep(time::Duration::from_secs(2)),
+                    Err(e) => {
+                        error!("grain.rs, start_job_workers(): An error occured: {}", e);
+                        thread::sleep(time::Duration::from_secs(2));
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// Marks the oldest queued job as running and returns it with the control to stop it.
+fn next_job() -> Result<Option<(GrainJob, JobControl)>, failure::Error> {
+    let mut grain_db = get_db_lock();
+
+    let job = match grain_db.jobs.iter_mut().find(|job| job.state == JobState::Queued) {
+        Some(job) => {
+            job.state = JobState::Running;
+            job.started_at = Some(util::timestamp_now());
+            job.clone()
+        }
+        None => return Ok(None),
+    };
+
+    save_db(&grain_db)?;
+
+    // Registered before the lock is released, so that a running job can always be cancelled.
+    let cancelled = Arc::new(AtomicBool::new(false));
+    RUNNING_JOBS.lock().map_err(|_| WebGuiError::JobRegistryPoisoned)?.insert(job.id, cancelled.clone());
+
+    let deadline = configuration::job_timeout(job.engine.name()).map(|seconds| Instant::now() + time::Duration::from_secs(seconds));
+
+    Ok(Some((job, JobControl { cancelled, deadline })))
+}
+
+fn finish_job(id: u32, state: JobState, exit_code: Option<i32>, message: String) -> Result<(), failure::Error> {
+    debug!("grain.rs, finish_job()");
+    let mut grain_db = get_db_lock();
+
+    if let Some(job) = grain_db.jobs.iter_mut().find(|job| job.id == id) {
+        job.state = state;
+        job.exit_code = exit_code;
+        job.finished_at = Some(util::timestamp_now());
+        job.message = message;
+
+        // A job that finished before its worker saw the cancel request was not cancelled.
+        if job.state != JobState::Cancelled {
+            job.cancelled_by = None;
+        }
+    }
+
+    RUNNING_JOBS.lock().map_err(|_| WebGuiError::JobRegistryPoisoned)?.remove(&id);
+
+    save_db(&grain_db)
+}
+
+/// Cancels a queued or running job. Users can cancel the jobs they submitted, admins any job.
+/// A running job is stopped by its worker, which then marks it as cancelled.
+fn cancel_job(user_id: u16, job_id: u32) -> Result<(), failure::Error> {
+    debug!("grain.rs, cancel_job()");
+    let is_admin = util::user_role(user_id)? == UserRole::Admin;
+    let mut grain_db = get_db_lock();
+
+    let job = grain_db.jobs.iter_mut()
+        .find(|job| job.id == job_id && (job.user_id == user_id || is_admin))
+        .ok_or(WebGuiError::JobNotFoundForUser)?;
+
+    match job.state {
+        JobState::Queued => {
+            job.state = JobState::Cancelled;
+            job.finished_at = Some(util::timestamp_now());
+        }
+        JobState::Running => {
+            if let Some(cancelled) = RUNNING_JOBS.lock().map_err(|_| WebGuiError::JobRegistryPoisoned)?.get(&job_id) {
+                cancelled.store(true, Ordering::SeqCst);
+            }
+        }
+        _ => return Ok(()),
+    }
+
+    job.cancelled_by = Some(user_id);
+    save_db(&grain_db)
+}
+
+/// Runs the job in the current thread, its output goes to the job log in the folder of the sample.
+/// Returns the final state, the exit code of the process and why it failed.
+fn run_job(job: &GrainJob, control: &JobControl) -> Result<(JobState, Option<i32>, String), failure::Error> {
+    debug!("grain.rs, run_job()");
+
+    let sample = {
+        let grain_db = get_db_lock();
+        grain_db.samples.iter()
+            .find(|sample| sample.id == job.sample_id && sample.deleted_at.is_none())
+            .cloned()
+            .ok_or(WebGuiError::SampleNotFoundForUser)?
+    };
+    let log_path = job_log_path(&sample, job.id)?;
+
+    match configuration::executor(job.engine.name()) {
+        Executor::Native { engine: NativeEngine::MonteCarlo } => {
+            let (parallel, perpendicular, output_file) = prepare_monte_carlo(&sample)?;
+            let mut log = CappedLog::new(File::create(&log_path)?, JOB_LOG_LIMIT, JOB_LOG_END);
+            let seed = job.seed.unwrap_or(job.created);
+
+            log.append(format!("Monte Carlo of sample {} with images {} (parallel) and {} (perpendicular), seed {}\n",
+                sample.name, parallel.id, perpendicular.id, seed).as_bytes())?;
+
+            match monte_carlo_result(&sample.name, &parallel, &perpendicular, seed, &|| control.stop_state().is_some()) {
+                Ok(None) => {
+                    let stopped = control.stop_state().unwrap_or(JobState::Cancelled);
+                    log.append(format!("{}\n", stop_message(stopped, job.engine)).as_bytes())?;
+                    log.finish()?;
+                    Ok((stopped, None, stop_message(stopped, job.engine)))
+                }
+                Ok(Some(contents)) => {
+                    File::create(&output_file)?.write_all(contents.as_bytes())?;
+                    log.append(contents.as_bytes())?;
+                    log.finish()?;
+                    Ok((JobState::Succeeded, None, String::new()))
+                }
+                Err(message) => {
+                    log.append(format!("Error: {}\n", message).as_bytes())?;
+                    log.finish()?;
+                    Ok((JobState::Failed, None, message))
+                }
+            }
+        }
+        Executor::Native { engine: NativeEngine::Analytical } => {
+            let output_file = {
+                let mut grain_db = get_db_lock();
+                clear_result(&mut grain_db, &sample)?
+            };
+            let mut log = CappedLog::new(File::create(&log_path)?, JOB_LOG_LIMIT, JOB_LOG_END);
+
+            let result = analytical_result(&sample);
+            log.append(result.as_bytes())?;
+            log.finish()?;
+
+            if result.lines().any(|line| !line.starts_with('#')) {
+                File::create(&output_file)?.write_all(result.as_bytes())?;
+                Ok((JobState::Succeeded, None, String::new()))
+            } else {
+                Ok((JobState::Failed, None, "no image of the sample could be calculated".to_string()))
+            }
+        }
+        external => {
+            let (input_file, output_file, grain_folder) = prepare_input(&sample)?;
+            let files = JobFiles { input_file: &input_file, output_file: &output_file, grain_folder: &grain_folder };
+            let mut log = CappedLog::new(File::create(&log_path)?, JOB_LOG_LIMIT, JOB_LOG_END);
+
+            let command = match executor::command(&external, &files) {
+                Ok(Some(command)) => command,
+                Ok(None) => return Ok((JobState::Failed, None, format!("{} has no command", executor::program(&external)))),
+                Err(message) => {
+                    log.append(format!("Error: {}\n", message).as_bytes())?;
+                    log.finish()?;
+                    return Ok((JobState::Failed, None, message))
+                }
+            };
+
+            let status = run_logged(command, &mut log, control);
+            log.finish()?;
+
+            let status = match status {
+                Ok((status, None)) => status,
+                Ok((status, Some(stopped))) => return Ok((stopped, status.code(), stop_message(stopped, job.engine))),
+                Err(e) => return Ok((JobState::Failed, None, format!("{} could not be started: {}", executor::program(&external), e))),
+            };
+            let result_written = Path::new(&output_file).exists();
+
+            // The reason of a failure is usually the last error the program printed.
+            let with_log_message = |summary: String| match read_log_tail(&log_path).ok().and_then(|log| job_log::failure_message(&log)) {
+                Some(line) => format!("{}: {}", summary, line),
+                None => summary,
+            };
+
+            Ok(match (status.success(), result_written) {
+                (true, true) => (JobState::Succeeded, status.code(), String::new()),
+                (true, false) => (JobState::Failed, status.code(), with_log_message("no result.txt was written".to_string())),
+                (false, _) => (JobState::Failed, status.code(), with_log_message(format!("{} stopped with {}", executor::program(&external), status))),
+            })
+        }
+    }
+}
+
+/// Why a job was stopped, for the job list.
+fn stop_message(state: JobState, engine: CalculationEngine) -> String {
+    match (state, configuration::job_timeout(engine.name())) {
+        (JobState::TimedOut, Some(seconds)) => format!("stopped after the time limit of {} seconds", seconds),
+        _ => "cancelled while running".to_string(),
+    }
+}
+
+/// Runs the command in its own process group and waits for it, standard output and error both go to the log.
+/// When the job has to stop, the whole group gets SIGTERM and after JOB_KILL_GRACE SIGKILL.
+/// Returns the state of the job if it was stopped.
+fn run_logged(mut command: Command, log: &mut CappedLog<File>, control: &JobControl) -> io::Result<(ExitStatus, Option<JobState>)> {
+    let mut child = command.process_group(0).stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    let process_group = child.id();
+    let (stdout, stderr) = (child.stdout.take(), child.stderr.take());
+    let log = Mutex::new(log);
+
+    // Both pipes are read to the end, even after the log is full, so that the process never blocks on them.
+    let copy = |pipe: &mut dyn Read| -> io::Result<()> {
+        let mut buffer = [0; 8192];
+        loop {
+            match pipe.read(&mut buffer)? {
+                0 => return Ok(()),
+                n => log.lock().map_err(|_| io::Error::other("job log lock poisoned"))?.append(&buffer[..n])?,
+            }
+        }
+    };
+
+    let (status, stdout_copied, stderr_copied) = thread::scope(|scope| {
+        let stdout_copy = scope.spawn(|| stdout.map_or(Ok(()), |mut pipe| copy(&mut pipe)));
+        let stderr_copy = scope.spawn(|| stderr.map_or(Ok(()), |mut pipe| copy(&mut pipe)));
+
+        let mut stopping: Option<(JobState, Instant)> = None;
+        let status = loop {
+            match child.try_wait() {
+                Ok(Some(status)) => break Ok(status),
+                Ok(None) => {}
+                Err(e) => break Err(e),
+            }
+
+            match stopping {
+                None => if let Some(state) = control.stop_state() {
+                    signal_process_group(process_group, "TERM");
+                    stopping = Some((state, Instant::now() + JOB_KILL_GRACE));
+                },
+                Some((_, kill_at)) if Instant::now() >= kill_at => signal_process_group(process_group, "KILL"),
+                Some(_) => {}
+            }
+
+            thread::sleep(time::Duration::from_millis(200));
+        };
+
+        // Children of the process that are still running would keep the pipes open.
+        if stopping.is_some() {
+            signal_process_group(process_group, "KILL");
+        }
+
+        let joined = |copy: thread::ScopedJoinHandle<io::Result<()>>| copy.join().unwrap_or_else(|_| Err(io::Error::other("job log thread panicked")));
+        (status.map(|status| (status, stopping.map(|(state, _)| state))), joined(stdout_copy), joined(stderr_copy))
+    });
+
+    stdout_copied?;
+    stderr_copied?;
+
+    status
+}
+
+/// Sends the signal (ex. "TERM") to all processes of the group.
+fn signal_process_group(process_group: u32, signal: &str) {
+    let result = Command::new("kill")
+        .args([&format!("-{}", signal), "--", &format!("-{}", process_group)])
+        .stderr(Stdio::null())
+        .status();
+
+    if let Err(e) = result {
+        warn!("grain.rs, signal_process_group(): kill could not be started: {}", e);
+    }
+}
+
+/// Log of the job in the matlab folder of the sample, next to result.txt.
+fn job_log_path(sample: &GrainSample, job_id: u32) -> Result<String, failure::Error> {
+    Ok(format!("matlab/{}/{}/job_{}.log", util::user_name(sample.user_id)?, sample.folder, job_id))
+}
+
+/// The last JOB_LOG_TAIL bytes of the log, starting at a whole line.
+fn read_log_tail(path: &str) -> io::Result<String> {
+    let mut f = File::open(path)?;
+    let length = f.metadata()?.len();
+
+    // One byte more than the tail, to know whether it starts at a whole line.
+    f.seek(SeekFrom::Start(length.saturating_sub(JOB_LOG_TAIL + 1)))?;
+    let mut contents = Vec::new();
+    f.read_to_end(&mut contents)?;
+
+    Ok(job_log::tail(&contents, JOB_LOG_TAIL as usize))
+}
+
+/// Jobs of the samples the user can see, newest first.
+fn jobs_context(user_id: u16) -> Result<Vec<serde_json::Value>, failure::Error> {
+    debug!("grain.rs, jobs_context()");
+    let is_admin = util::user_role(user_id)? == UserRole::Admin;
+    let grain_db = get_db_lock();
+    let mut jobs = Vec::new();
+    let optional_time = |timestamp: Option<u64>| timestamp.map(util::format_timestamp).unwrap_or_default();
+
+    // Admins see all jobs to cancel them.
+    for job in grain_db.jobs.iter().rev() {
+        let sample = match grain_db.samples.iter().find(|sample| sample.id == job.sample_id && sample.deleted_at.is_none()) {
+            Some(sample) if is_admin || sample_permission(&grain_db, user_id, sample).is_some() => sample,
+            _ => continue,
+        };
+
+        let message = match job.cancelled_by {
+            Some(cancelled_by) if job.message.is_empty() => format!("cancelled by {}", util::user_name(cancelled_by)?),
+            Some(cancelled_by) => format!("{} by {}", job.message, util::user_name(cancelled_by)?),
+            None => job.message.clone(),
+        };
+
+        jobs.push(json!({
+            "id": job.id,
+            "sample": sample_label(&grain_db, user_id, sample)?,
+            "engine": job.engine.name(),
+            "state": job.state.name(),
+            "submitted_by": util::user_name(job.user_id)?,
+            "created": util::format_timestamp(job.created),
+            "started_at": optional_time(job.started_at),
+            "finished_at": optional_time(job.finished_at),
+            "exit_code": job.exit_code.map(|code| code.to_string()).unwrap_or_default(),
+            "message": message,
+            "log": read_log_tail(&job_log_path(sample, job.id)?).ok(),
+            "can_cancel": !job.state.is_finished() && (is_admin || job.user_id == user_id),
+        }));
+    }
+
+    Ok(jobs)
+}
+
+/// Label, contents and whether image parameters changed since the result was calculated.
+fn get_results(user_id: u16) -> Result<Vec<(String, String, bool)>, failure::Error> {
+    debug!("grain.rs, get_results()");
+    let grain_db = get_db_lock();
+
+    let mut results = Vec::new();
+
+    for sample in grain_db.samples.iter() {
+        if sample.deleted_at.is_none() && sample_permission(&grain_db, user_id, sample).is_some() {
+            let path = format!("matlab/{}/{}/result.txt", util::user_name(sample.user_id)?, sample.folder);
+            if Path::new(&path).exists() {
+                let mut f = File::open(path)?;
+                let mut contents = String::new();
+                f.read_to_string(&mut contents)?;
+
+                results.push((sample_label(&grain_db, user_id, sample)?, contents, sample.parameters_changed_at.is_some()));
+            }
+        }
+    }
+
+    Ok(results)
+}
+
+
+/// Files of a sample that go into an archive, relative to user_data/{owner}/{folder} and matlab/{owner}/{folder}.
+fn sample_archive_files(images: &[GrainImage]) -> (Vec<String>, Vec<String>) {
+    let mut user_files = Vec::new();
+    let mut matlab_files = vec!["matlab_input.csv".to_string(), "result.txt".to_string()];
+
+    for grain in images.iter() {
+        for file in &[&grain.file_name, grain.stored_original()] {
+            if !file.is_empty() && !user_files.iter().any(|user_file| user_file == file) {
+                user_files.push(file.to_string());
+            }
+        }
+
+        if !matlab_files.contains(&grain.coordinate_file_name) {
+            matlab_files.push(grain.coordinate_file_name.clone());
+        }
+    }
+
+    (user_files, matlab_files)
+}
+
+fn valid_archive_file_name(file_name: &str) -> bool {
+    !file_name.is_empty() && !file_name.starts_with('.') && util::replace_characters(file_name) == file_name
+}
+
+/// Only plain file names and the originals/ folder are accepted from an archive,
+/// so an import can never write outside of the sample folders.
+fn valid_archive_path(path: &str) -> bool {
+    match path.split('/').collect::<Vec<_>>()[..] {
+        [file_name] => valid_archive_file_name(file_name),
+        ["originals", file_name] => valid_archive_file_name(file_name),
+        _ => false,
+    }
+}
+
+/// Bundles the samples owned by the user, or all samples of the given project, into one zip archive:
+/// manifest.json with the sample and image records (including outlines and axes)
+/// and for each sample the uploaded images, the coordinate files, the matlab input and the result.
+fn export_archive(user_id: u16, project_id: Option<u32>) -> Result<Vec<u8>, failure::Error> {
+    debug!("grain.rs, export_archive()");
+    let grain_db = get_db_lock();
+
+    if let Some(project_id) = project_id {
+        let project = find_project(&grain_db, project_id).ok_or(WebGuiError::ProjectNotFoundForUser)?;
+        if project_permission(project, user_id).is_none() {
+            return Err(WebGuiError::ProjectNotFoundForUser.into())
+        }
+    }
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    let mut manifest = ArchiveManifest {
+        version: ARCHIVE_VERSION,
+        exported_at: util::timestamp_now(),
+        samples: Vec::new(),
+    };
+
+    let selected = grain_db.samples.iter().filter(|sample| sample.deleted_at.is_none() && match project_id {
+        Some(project_id) => sample.project_id == Some(project_id),
+        None => sample.user_id == user_id,
+    });
+
+    for sample in selected {
+        let owner = util::user_name(sample.user_id)?;
+        let images = grain_db.grains.iter()
+            .filter(|grain| grain.sample_id == sample.id && grain.deleted_at.is_none())
+            .cloned().collect::<Vec<_>>();
+        let (user_files, matlab_files) = sample_archive_files(&images);
+
+        for (kind, files) in &[("user_data", user_files), ("matlab", matlab_files)] {
+            for file in files.iter() {
+                let path = format!("{}/{}/{}/{}", kind, owner, sample.folder, file);
+
+                if Path::new(&path).exists() {
+                    let mut data = Vec::new();
+                    File::open(&path)?.read_to_end(&mut data)?;
+                    zip.start_file(format!("samples/{}/{}/{}", sample.id, kind, file), options)?;
+                    zip.write_all(&data)?;
+                }
+            }
+        }
+
+        manifest.samples.push(ArchiveSample {
+            owner,
+            sample: sample.clone(),
+            images,
+        });
+    }
+
+    zip.start_file("manifest.json", options)?;
+    zip.write_all(serde_json::to_string_pretty(&manifest)?.as_bytes())?;
+
+    Ok(zip.finish()?.into_inner())
+}
+
+/// Reads and checks the manifest of an uploaded archive and returns it with the total size of all files in it.
+fn read_archive_manifest(archive: &mut ZipArchive<Cursor<&[u8]>>) -> Result<(ArchiveManifest, u64), failure::Error> {
+    debug!("grain.rs, read_archive_manifest()");
+    let manifest: ArchiveManifest = {
+        let mut file = archive.by_name("manifest.json").map_err(|_| WebGuiError::InvalidArchive)?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        serde_json::from_str(&contents).map_err(|_| WebGuiError::InvalidArchive)?
+    };
+
+    if manifest.version > ARCHIVE_VERSION {
+        return Err(WebGuiError::InvalidArchive.into())
+    }
+
+    for archived in manifest.samples.iter() {
+        let (user_files, matlab_files) = sample_archive_files(&archived.images);
+
+        if !user_files.iter().chain(matlab_files.iter()).all(|file| valid_archive_path(file)) {
+            return Err(WebGuiError::InvalidArchive.into())
+        }
+    }
+
+    let mut bytes = 0;
+    for i in 0..archive.len() {
+        bytes += archive.by_index(i)?.size();
+    }
+
+    Ok((manifest, bytes))
+}
+
+/// Adds all samples of an archive to the workspace of the user. Samples and images get new ids
+/// and folders and belong to the importing user, project assignments are not kept.
+/// A sample whose name is already in use gets the suffix "_imported".
+fn import_archive(user_id: u16, archive: &mut ZipArchive<Cursor<&[u8]>>, manifest: ArchiveManifest) -> Result<usize, failure::Error> {
+    debug!("grain.rs, import_archive()");
+    let mut grain_db = get_db_lock();
+    let user_name = util::user_name(user_id)?;
+    let num_of_samples = manifest.samples.len();
+
+    let mut next_image_id = grain_db.grains.last().map(|grain| grain.id + 1).unwrap_or(0);
+
+    for archived in manifest.samples {
+        let old_id = archived.sample.id;
+        let (user_files, matlab_files) = sample_archive_files(&archived.images);
+
+        let mut name = archived.sample.name.clone();
+        let mut counter = 1;
+        while grain_db.samples.iter().any(|sample| sample.user_id == user_id && sample.name == name && sample.deleted_at.is_none()) {
+            name = if counter == 1 {
+                format!("{}_imported", archived.sample.name)
+            } else {
+                format!("{}_imported_{}", archived.sample.name, counter)
+            };
+            counter += 1;
+        }
+
+        let id = create_new_sample_id(&grain_db.samples);
+        let sample = GrainSample {
+            id,
+            user_id,
+            project_id: None,
+            name,
+            folder: format!("sample_{}", id),
+            deleted_at: None,
+            ..archived.sample
+        };
+
+        for (kind, files) in &[("user_data", user_files), ("matlab", matlab_files)] {
+            let folder = format!("{}/{}/{}", kind, user_name, sample.folder);
+            create_dir_all(&folder)?;
+
+            for file in files.iter() {
+                if let Ok(mut entry) = archive.by_name(&format!("samples/{}/{}/{}", old_id, kind, file)) {
+                    let mut data = Vec::new();
+                    entry.read_to_end(&mut data)?;
+                    let path = format!("{}/{}", folder, file);
+                    if let Some(parent) = Path::new(&path).parent() {
+                        create_dir_all(parent)?;
+                    }
+                    BufWriter::new(File::create(&path)?).write_all(&data)?;
+                }
+            }
+        }
+
+        for grain in archived.images {
+            // The authors of the revisions are users of the exporting installation.
+            grain_db.grains.push(GrainImage {
+                id: next_image_id,
+                user_id,
+                sample_id: sample.id,
+                deleted_at: None,
+                outline_revisions: Vec::new(),
+                ..grain
+            });
+            next_image_id += 1;
+        }
+
+        grain_db.samples.push(sample);
+    }
+
+    save_db(&grain_db)?;
+
+    Ok(num_of_samples)
+}
+
+
+/// File extension used for stored originals of the supported upload formats.
+fn image_extension(format: ImageFormat) -> Option<&'static str> {
+    match format {
+        ImageFormat::Jpeg => Some("jpg"),
+        ImageFormat::Png => Some("png"),
+        ImageFormat::Tiff => Some("tif"),
+        ImageFormat::Bmp => Some("bmp"),
+        _ => None,
+    }
+}
+
+/// Detects the format from the content of the upload (not from its name) and decodes it.
+/// 16 bit images (ex. TIFF exports of microscope cameras) keep their full bit depth.
+fn decode_upload(data: &[u8]) -> Result<(DynamicImage, &'static str), String> {
+    let format = image::guess_format(data).map_err(|_| "unknown image format".to_string())?;
+    let extension = image_extension(format).ok_or_else(|| format!("{:?} images are not supported, please use JPEG, PNG, TIFF or BMP", format))?;
+    let image = image::load_from_memory_with_format(data, format).map_err(|e| format!("image could not be read: {}", e))?;
+
+    Ok((image, extension))
+}
+
+fn filter_type(filter: ResizeFilter) -> FilterType {
+    match filter {
+        ResizeFilter::Nearest => FilterType::Nearest,
+        ResizeFilter::Triangle => FilterType::Triangle,
+        ResizeFilter::CatmullRom => FilterType::CatmullRom,
+        ResizeFilter::Gaussian => FilterType::Gaussian,
+        ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+    }
+}
+
+/// The rescaling of the installation, overridden by the values given in the upload form.
+/// Empty form fields keep the configured value.
+fn upload_rescaling(target_pixel_size: Option<f64>, filter: &str) -> Result<Rescaling, String> {
+    let mut rescaling = configuration::rescaling();
+
+    if let Some(target_pixel_size) = target_pixel_size {
+        if target_pixel_size <= 0.0 {
+            return Err("The working pixel size must be larger than 0".to_string())
+        }
+        rescaling.target_pixel_size = target_pixel_size;
+    }
+
+    if !filter.is_empty() {
+        rescaling.filter = ResizeFilter::from_name(filter).ok_or_else(|| format!("Unknown resize filter: {}", filter))?;
+    }
+
+    Ok(rescaling)
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Describes the image of the given owner with identical content, if there is one.
+fn find_duplicate_image(owner_id: u16, sha256: &str) -> Option<String> {
+    debug!("grain.rs, find_duplicate_image()");
+    let grain_db = get_db_lock();
+
+    grain_db.grains.iter()
+        .find(|grain| grain.user_id == owner_id && grain.original_sha256 == sha256 && grain.deleted_at.is_none())
+        .map(|grain| format!("{} in sample {}", grain.original_file_name, sample_name(&grain_db, grain.sample_id)))
+}
+
+/// Stores the unchanged upload under its content hash and a lossless (PNG) resized working copy,
+/// which is shown in the browser and used by matlab, in the folder of the sample and adds the image to the database.
+/// The working copy is named after the image id, so uploads with the same file name never overwrite each other.
+/// Stores the original, the working copy and the previews of an uploaded image and returns its id.
+fn store_grain_image(sample: &GrainSample, owner_name: &str, image_input: &str, data: &[u8], metadata: &ImageMetadata, rescaling: &Rescaling) -> Result<u32, failure::Error> {
+    debug!("grain.rs, store_grain_image()");
+    let (img_in, extension) = decode_upload(data).map_err(|_| WebGuiError::UnsupportedImageFormat)?;
+    let sha256 = sha256_hex(data);
+
+    let id = create_new_id()?;
+    let stem = match image_input.rfind('.') {
+        Some(n) if n > 0 => &image_input[..n],
+        _ => image_input,
+    };
+    let image_output = format!("{}_{}.png", id, stem);
+    let coordinate_file_name = format!("{}_{}.txt", id, stem);
+    let original_path = format!("originals/{}.{}", sha256, extension);
+
+    let user_path = format!("user_data/{}/{}", owner_name, sample.folder);
+
+    create_dir_all(format!("{}/originals", user_path))?;
+
+    BufWriter::new(File::create(format!("{}/{}", user_path, original_path))?).write_all(data)?;
+
+    let factor : f64 = metadata.size / rescaling.target_pixel_size;
+    let new_width = (((img_in.width() as f64) * factor).round() as u32).max(1);
+    let new_height = (((img_in.height() as f64) * factor).round() as u32).max(1);
+    let img_out = img_in.resize_exact(new_width, new_height, filter_type(rescaling.filter));
+
+    // Differs slightly from the target because the new size is rounded to whole pixels.
+    let working_pixel_size = metadata.size * (img_in.width() as f64) / (new_width as f64);
+
+    img_out.save_with_format(format!("{}/{}", user_path, image_output), ImageFormat::Png)?;
+
+    for size in PreviewSize::all().iter() {
+        save_preview(&img_out, &user_path, &image_output, *size)?;
+    }
+
+    add_grain_image(GrainImage {
+        id,
+        user_id: sample.user_id,
+        file_name: image_output,
+        sample_id: sample.id,
+        sample_name: String::new(),
+        size: metadata.size,
+        mode: metadata.mode,
+        mineral: metadata.mineral,
+        ratio_232_238: metadata.ratio_232_238,
+        ratio_147_238: metadata.ratio_147_238,
+        orientation: metadata.orientation,
+        shape: metadata.shape,
+        pyramids: metadata.pyramids,
+        broken_tips: metadata.broken_tips,
+        zoned: metadata.zoned,
+        rim_width: metadata.rim_width,
+        ratio_rim_core: metadata.ratio_rim_core,
+        coordinates: Vec::new(),
+        outline_kind: OutlineKind::default(),
+        bw_threshold: None,
+        threshold_method: None,
+        coordinate_file_name,
+        axis: Axis::default(),
+        working_pixel_size,
+        original_file_name: image_input.to_string(),
+        original_path,
+        original_sha256: sha256,
+        deleted_at: None,
+        outline_revisions: Vec::new(),
+    })?;
+
+    Ok(id)
+}
+
+/// Removes the just stored images again if they took the owner above the storage quota
+/// and returns the reason.
+fn undo_uploads_over_quota(sample: &GrainSample, owner_name: &str, image_ids: &[u32]) -> Result<Option<String>, failure::Error> {
+    debug!("grain.rs, undo_uploads_over_quota()");
+    let message = storage_usage(sample.user_id, owner_name)?.check_stored();
+
+    if message.is_some() {
+        purge_grain_images(|grain| image_ids.contains(&grain.id))?;
+    }
+
+    Ok(message)
+}
+
+/// Image parameters as posted by the upload, edit and preset forms, see post_input_with_metadata!.
+struct MetadataForm {
+    size: f64,
+    mode: i32,
+    mineral: i32,
+    ratio_232_238: f64,
+    ratio_147_238: f64,
+    orientation: i32,
+    shape: i32,
+    pyramids: i32,
+    broken_tips: i32,
+    zoned: i32,
+    rim_width: f64,
+    ratio_rim_core: f64,
+}
+
+/// post_input! for a form with the image parameters, returns the other fields and the parameters.
+macro_rules! post_input_with_metadata {
+    ($request:expr, { $($field:ident: $kind:ty,)* }) => {
+        post_input!($request, {
+            $($field: $kind,)*
+            size: f64,
+            mode: i32,
+            mineral: i32,
+            ratio_232_238: f64,
+            ratio_147_238: f64,
+            orientation: i32,
+            shape: i32,
+            pyramids: i32,
+            broken_tips: i32,
+            zoned: i32,
+            rim_width: f64,
+            ratio_rim_core: f64,
+        }).map(|data| {
+            let form = MetadataForm {
+                size: data.size,
+                mode: data.mode,
+                mineral: data.mineral,
+                ratio_232_238: data.ratio_232_238,
+                ratio_147_238: data.ratio_147_238,
+                orientation: data.orientation,
+                shape: data.shape,
+                pyramids: data.pyramids,
+                broken_tips: data.broken_tips,
+                zoned: data.zoned,
+                rim_width: data.rim_width,
+                ratio_rim_core: data.ratio_rim_core,
+            };
+            (data, form)
+        })
+    };
+}
+
+/// The parameters of the form and a message for each problem with them.
+fn metadata_from_form(form: &MetadataForm) -> (ImageMetadata, Vec<String>) {
+    let mut errors = Vec::new();
+    let metadata = ImageMetadata {
+        size: form.size,
+        mode: choice_from_form(form.mode, &mut errors),
+        mineral: choice_from_form(form.mineral, &mut errors),
+        ratio_232_238: form.ratio_232_238,
+        ratio_147_238: form.ratio_147_238,
+        orientation: choice_from_form(form.orientation, &mut errors),
+        shape: choice_from_form(form.shape, &mut errors),
+        pyramids: form.pyramids,
+        broken_tips: form.broken_tips != 0,
+        zoned: form.zoned != 0,
+        rim_width: form.rim_width,
+        ratio_rim_core: form.ratio_rim_core,
+    };
+    errors.extend(metadata.validate());
+
+    (metadata, errors)
+}
+
+/// The parameter with the given number, or the first value and a message in errors.
+fn choice_from_form<T: Choice>(code: i32, errors: &mut Vec<String>) -> T {
+    T::from_code(code).unwrap_or_else(|| {
+        errors.push(T::invalid(&code.to_string()));
+        T::ALL[0]
+    })
+}
+
+/// The parameter with the given number or name, or the first value and a message in errors.
+fn choice_from_spreadsheet<T: Choice>(value: &str, errors: &mut Vec<String>) -> T {
+    T::from_name(value).unwrap_or_else(|| {
+        errors.push(T::invalid(value));
+        T::ALL[0]
+    })
+}
+
+fn parse_yes_no(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_ref() {
+        "1" | "yes" | "true" => Some(true),
+        "0" | "no" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// Reads the bulk upload spreadsheet, every problem is added to errors together with the row it was found in.
+/// With a preset only the file_name column is required, missing columns and empty cells take the value of the preset.
+/// Returns the valid rows and the file names of all rows.
+fn read_bulk_rows(data: &[u8], preset: Option<&ImageMetadata>, errors: &mut Vec<String>) -> (Vec<BulkRow>, HashSet<String>) {
+    debug!("grain.rs, read_bulk_rows()");
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(data);
+
+    let headers = match reader.headers() {
+        Ok(headers) => headers.clone(),
+        Err(e) => {
+            errors.push(format!("Spreadsheet could not be read: {}", e));
+            return (Vec::new(), HashSet::new())
+        }
+    };
+
+    let mut columns = Vec::new();
+    for (i, column) in BULK_COLUMNS.iter().enumerate() {
+        let index = headers.iter().position(|header| header == *column);
+        if index.is_none() && (i == 0 || preset.is_none()) {
+            errors.push(format!("Spreadsheet: column '{}' is missing", column));
+        }
+        columns.push(index);
+    }
+
+    if !errors.is_empty() {
+        return (Vec::new(), HashSet::new())
+    }
+
+    let mut rows = Vec::new();
+    let mut file_names = HashSet::new();
+
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                errors.push(format!("Spreadsheet could not be read: {}", e));
+                break
+            }
+        };
+
+        let line = record.position().map(|position| position.line()).unwrap_or(0);
+        let value = |column: usize| columns[column].and_then(|index| record.get(index)).unwrap_or("");
+        let from_preset = |column: usize| preset.filter(|_| value(column).is_empty());
+        let mut row_errors = Vec::new();
+
+        let mut number = |column: usize, min: f64| -> f64 {
+            match value(column).parse::<f64>() {
+                Ok(number) if number >= min => number,
+                _ => {
+                    row_errors.push(format!("{} must be a number >= {}, found '{}'", BULK_COLUMNS[column], min, value(column)));
+                    0.0
+                }
+            }
+        };
+
+        let size = from_preset(1).map_or_else(|| number(1, 0.0), |preset| preset.size);
+        let ratio_232_238 = from_preset(4).map_or_else(|| number(4, 0.0), |preset| preset.ratio_232_238);
+        let ratio_147_238 = from_preset(5).map_or_else(|| number(5, 0.0), |preset| preset.ratio_147_238);
+        let rim_width = from_preset(11).map_or_else(|| number(11, 0.0), |preset| preset.rim_width);
+        let ratio_rim_core = from_preset(12).map_or_else(|| number(12, 0.0), |preset| preset.ratio_rim_core);
+
+        let mode = from_preset(2).map_or_else(|| choice_from_spreadsheet(value(2), &mut row_errors), |preset| preset.mode);
+        let mineral = from_preset(3).map_or_else(|| choice_from_spreadsheet(value(3), &mut row_errors), |preset| preset.mineral);
+        let orientation = from_preset(6).map_or_else(|| choice_from_spreadsheet(value(6), &mut row_errors), |preset| preset.orientation);
+        let shape = from_preset(7).map_or_else(|| choice_from_spreadsheet(value(7), &mut row_errors), |preset| preset.shape);
+        let pyramids = from_preset(8).map_or_else(|| value(8).parse::<i32>().unwrap_or_else(|_| {
+            row_errors.push(format!("pyramids must be 0, 1 or 2, found '{}'", value(8)));
+            0
+        }), |preset| preset.pyramids);
+
+        let mut yes_no = |column: usize| -> bool {
+            parse_yes_no(value(column)).unwrap_or_else(|| {
+                row_errors.push(format!("{} must be yes or no, found '{}'", BULK_COLUMNS[column], value(column)));
+                false
+            })
+        };
+
+        let broken_tips = from_preset(9).map_or_else(|| yes_no(9), |preset| preset.broken_tips);
+        let zoned = from_preset(10).map_or_else(|| yes_no(10), |preset| preset.zoned);
+
+        let file_name = util::replace_characters(value(0));
+        if file_name.is_empty() {
+            row_errors.push("file_name is missing".to_string());
+        }
+        file_names.insert(file_name.clone());
+
+        let metadata = ImageMetadata {
+            size, mode, mineral, ratio_232_238, ratio_147_238, orientation, shape,
+            pyramids, broken_tips, zoned, rim_width, ratio_rim_core,
+        };
+
+        // Combinations are only checked if all values could be read.
+        if row_errors.is_empty() {
+            row_errors = metadata.validate();
+        }
+
+        if row_errors.is_empty() {
+            rows.push(BulkRow { line, file_name, metadata });
+        } else {
+            for row_error in row_errors {
+                errors.push(format!("Row {}: {}", line, row_error));
+            }
+        }
+    }
+
+    if rows.is_empty() && errors.is_empty() {
+        errors.push("Spreadsheet does not contain any rows".to_string());
+    }
+
+    (rows, file_names)
+}
+
+/// The uploaded images by (cleaned up) file name, the content of zip archives is added as well.
+fn collect_bulk_files(uploads: Vec<input::post::BufferedFile>, errors: &mut Vec<String>) -> Vec<(String, Vec<u8>)> {
+    debug!("grain.rs, collect_bulk_files()");
+    let mut files = Vec::new();
+
+    for upload in uploads {
+        let file_name = util::replace_characters(&upload.filename.unwrap_or_default());
+
+        if file_name.is_empty() && upload.data.is_empty() {
+            // Empty file input in the form
+            continue
+        }
+
+        if file_name.to_lowercase().ends_with(".zip") {
+            let mut archive = match ZipArchive::new(Cursor::new(&upload.data[..])) {
+                Ok(archive) => archive,
+                Err(e) => {
+                    errors.push(format!("{}: not a valid zip archive: {}", file_name, e));
+                    continue
+                }
+            };
+
+            for i in 0..archive.len() {
+                let mut entry = match archive.by_index(i) {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        errors.push(format!("{}: entry {} could not be read: {}", file_name, i, e));
+                        continue
+                    }
+                };
+
+                // Only the file name counts, folders inside the archive are ignored.
+                let entry_name = entry.name().rsplit('/').next().unwrap_or("").to_string();
+
+                if entry.is_dir() || entry_name.starts_with('.') || entry.name().starts_with("__MACOSX") {
+                    continue
+                }
+
+                let mut data = Vec::new();
+                if let Err(e) = entry.read_to_end(&mut data) {
+                    errors.push(format!("{}: {} could not be read: {}", file_name, entry_name, e));
+                    continue
+                }
+
+                files.push((util::replace_characters(&entry_name), data));
+            }
+        } else {
+            files.push((file_name, upload.data));
+        }
+    }
+
+    files
+}
+
+/// Matches rows and files and checks everything that could make the upload fail before anything is stored.
+fn check_bulk_upload(sample: &GrainSample, rows: &[BulkRow], listed: &HashSet<String>, files: &[(String, Vec<u8>)], errors: &mut Vec<String>) {
+    debug!("grain.rs, check_bulk_upload()");
+    for row in rows.iter() {
+        match files.iter().filter(|(file_name, _)| *file_name == row.file_name).count() {
+            0 => errors.push(format!("Row {}: no uploaded file named {}", row.line, row.file_name)),
+            1 => {}
+            _ => errors.push(format!("Row {}: more than one uploaded file named {}", row.line, row.file_name)),
+        }
+    }
+
+    let mut uploaded = Vec::new();
+
+    for (file_name, data) in files.iter() {
+        if !listed.contains(file_name) {
+            errors.push(format!("{}: no row in the spreadsheet", file_name));
+        } else if let Err(e) = decode_upload(data) {
+            errors.push(format!("{}: {}", file_name, e));
+        } else {
+            let sha256 = sha256_hex(data);
+
+            if let Some(duplicate) = find_duplicate_image(sample.user_id, &sha256) {
+                errors.push(format!("{}: identical to {}", file_name, duplicate));
+            } else if let Some((other, _)) = uploaded.iter().find(|(_, other_sha256)| *other_sha256 == sha256) {
+                errors.push(format!("{}: identical to {} in this upload", file_name, other));
+            }
+
+            uploaded.push((file_name.clone(), sha256));
+        }
+    }
+}
+
+
+
+// URL route targets:
+
+pub fn about_get(session_id: &str) -> Result<Response, failure::Error> {
+    debug!("grain.rs, about_get()");
+    util::show_program(session_id, &ProgramType::Grain3DHe)
+}
+
+/// The upload form is filled with the given preset or the default preset of the user.
+fn render_load_images(user_name: &str, user_id: u16, allowed_programs: &[ProgramType], preset_id: Option<u32>, message: &str, errors: &[String]) -> Result<Response, failure::Error> {
+    let context = json!({
+        "login_id": user_name,
+        "programs": util::build_program_menu(allowed_programs),
+        "usage": util::build_usage(user_id, user_name)?,
+        "grain_samples": accessible_samples(user_id, Permission::Editor)?,
+        "grain_images": grain_images_context(user_id, false)?,
+        "trashed_images": grain_images_context(user_id, true)?,
+        "trash_retention_days": configuration::trash_retention_days(),
+        "bulk_columns": BULK_COLUMNS.join(","),
+        "rescaling": configuration::rescaling(),
+        "resize_filters": ResizeFilter::all(),
+        "form": metadata_form_context(&preset_metadata(user_id, preset_id)?),
+        "presets": grain_presets_context(user_id)?,
+        "message": message,
+        "errors": errors,
+    });
+
+    Ok(Response::html(util::render("grain_load_images", &context)?))
+}
+
+pub fn load_images_get(session_id: &str, request: &Request) -> Result<Response, failure::Error> {
+    debug!("grain.rs, load_image_get()");
+    if util::logged_in(session_id)? {
+        let (user_name, user_id) = util::login_id(session_id)?;
+        let allowed_programs = util::list_of_allowed_programs(user_id)?;
+
+        if allowed_programs.contains(&ProgramType::Grain3DHe) {
+            let preset_id = request.get_param("preset").and_then(|preset_id| preset_id.parse::<u32>().ok());
+            render_load_images(&user_name, user_id, &allowed_programs, preset_id, "", &[])
+        } else {
+            Ok(Response::redirect_303(util::get_template_name(&allowed_programs[0])))
+        }
+    } else {
+        Ok(Response::redirect_303("/web_gui/"))
+    }
+}
+
+pub fn load_images_post(session_id: &str, request: &Request) -> Result<Response, failure::Error> {
+    debug!("grain.rs, load_image_post()");
+    if util::logged_in(session_id)? {
+        let (user_name, user_id) = util::login_id(session_id)?;
+        let allowed_programs = util::list_of_allowed_programs(user_id)?;
+
+        if allowed_programs.contains(&ProgramType::Grain3DHe) {
+            let (data, form) = post_input_with_metadata!(request, {
+                image: input::post::BufferedFile,
+                sample_id: u32,
+                target_pixel_size: Option<f64>,
+                filter: String,
+            })?;
+
+            let image_input = data.image.filename.ok_or(WebGuiError::NoFilenameForGrainImage)?;
+            let image_input = util::replace_characters(&image_input);
+
+            let (metadata, errors) = metadata_from_form(&form);
+
+            if !errors.is_empty() {
+                return render_load_images(&user_name, user_id, &allowed_programs, None, &format!("{} was not uploaded, please correct the following problems:", image_input), &errors)
+            }
+
+            let sample = sample_for_user(user_id, data.sample_id, Permission::Editor)?;
+            let owner_name = util::user_name(sample.user_id)?;
+
+            // Uploads to a shared sample count against the quota of the sample owner.
+            let usage = storage_usage(sample.user_id, &owner_name)?;
+            if let Some(message) = usage.check_upload(data.image.data.len() as u64) {
+                return render_load_images(&user_name, user_id, &allowed_programs, None, &message, &[])
+            }
+
+            let rescaling = match upload_rescaling(data.target_pixel_size, &data.filter) {
+                Ok(rescaling) => rescaling,
+                Err(message) => return render_load_images(&user_name, user_id, &allowed_programs, None, &message, &[]),
+            };
+
+            if let Err(e) = decode_upload(&data.image.data) {
+                return render_load_images(&user_name, user_id, &allowed_programs, None, &format!("{}: {}", image_input, e), &[])
+            }
+
+            if let Some(duplicate) = find_duplicate_image(sample.user_id, &sha256_hex(&data.image.data)) {
+                return render_load_images(&user_name, user_id, &allowed_programs, None, &format!("{} has already been uploaded as {}", image_input, duplicate), &[])
+            }
+
+            let image_id = store_grain_image(&sample, &owner_name, &image_input, &data.image.data, &metadata, &rescaling)?;
+
+            if let Some(message) = undo_uploads_over_quota(&sample, &owner_name, &[image_id])? {
+                return render_load_images(&user_name, user_id, &allowed_programs, None, &format!("{} was not uploaded. {}", image_input, message), &[])
+            }
+
+            Ok(Response::redirect_303("/web_gui/grain/load_images"))
+        } else {
+            Ok(Response::redirect_303(util::get_template_name(&allowed_programs[0])))
+        }
+    } else {
+        Ok(Response::redirect_303("/web_gui/"))
+    }
+}
+
+fn render_edit_image(user_name: &str, user_id: u16, allowed_programs: &[ProgramType], id: u32, values: Option<(&ImageMetadata, u32)>, errors: &[String]) -> Result<Response, failure::Error> {
+    let context = json!({
+        "login_id": user_name,
+        "programs": util::build_program_menu(allowed_programs),
+        "usage": util::build_usage(user_id, user_name)?,
+        "image": edit_image_context(user_id, id, values)?,
+        "errors": errors,
+    });
+
+    Ok(Response::html(util::render("grain_edit_image", &context)?))
+}
+
+pub fn edit_image_get(session_id: &str, request: &Request) -> Result<Response, failure::Error> {
+    debug!("grain.rs, edit_image_get()");
+    if util::logged_in(session_id)? {
+        let (user_name, user_id) = util::login_id(session_id)?;
+        let allowed_programs = util::list_of_allowed_programs(user_id)?;
+
+        if allowed_programs.contains(&ProgramType::Grain3DHe) {
+            let image_id = request.get_param("image").and_then(|image_id| image_id.parse::<u32>().ok())
+                .ok_or(WebGuiError::GrainImageNotFoundForUser)?;
+
+            render_edit_image(&user_name, user_id, &allowed_programs, image_id, None, &[])
+        } else {
+            Ok(Response::redirect_303(util::get_template_name(&allowed_programs[0])))
+        }
+    } else {
+        Ok(Response::redirect_303("/web_gui/"))
+    }
+}
+
+pub fn edit_image_post(session_id: &str, request: &Request) -> Result<Response, failure::Error> {
+    debug!("grain.rs, edit_image_post()");
+    if util::logged_in(session_id)? {
+        let (user_name, user_id) = util::login_id(session_id)?;
+        let allowed_programs = util::list_of_allowed_programs(user_id)?;
+
+        if allowed_programs.contains(&ProgramType::Grain3DHe) {
+            let (data, form) = post_input_with_metadata!(request, {
+                image_id: u32,
+                sample_id: u32,
+            })?;
+
+            let (metadata, errors) = metadata_from_form(&form);
+
+            if !errors.is_empty() {
+                return render_edit_image(&user_name, user_id, &allowed_programs, data.image_id, Some((&metadata, data.sample_id)), &errors)
+            }
+
+            update_grain_image(user_id, data.image_id, &metadata, data.sample_id)?;
+
+            Ok(Response::redirect_303("/web_gui/grain/load_images"))
+        } else {
+            Ok(Response::redirect_303(util::get_template_name(&allowed_programs[0])))
+        }
+    } else {
+        Ok(Response::redirect_303("/web_gui/"))
+    }
+}
+
+pub fn bulk_upload_post(session_id: &str, request: &Request) -> Result<Response, failure::Error> {
+    debug!("grain.rs, bulk_upload_post()");
+    if util::logged_in(session_id)? {
+        let (user_name, user_id) = util::login_id(session_id)?;
+        let allowed_programs = util::list_of_allowed_programs(user_id)?;
+
+        if allowed_programs.contains(&ProgramType::Grain3DHe) {
+            let data = post_input!(request, {
+                sample_id: u32,
+                target_pixel_size: Option<f64>,
+                filter: String,
+                preset: Option<u32>,
+                metadata: Option<input::post::BufferedFile>,
+                images: Vec<input::post::BufferedFile>,
+            })?;
+
+            let sample = sample_for_user(user_id, data.sample_id, Permission::Editor)?;
+            let owner_name = util::user_name(sample.user_id)?;
+            let preset = match data.preset {
+                Some(preset_id) => Some(preset_metadata(user_id, Some(preset_id))?),
+                None => None,
+            };
+            let spreadsheet = data.metadata.map(|metadata| metadata.data).filter(|data| !data.is_empty());
+
+            let mut errors = Vec::new();
+            let files = collect_bulk_files(data.images, &mut errors);
+
+            // Without a spreadsheet all images get the values of the preset.
+            let (rows, listed) = match (spreadsheet, &preset) {
+                (Some(spreadsheet), _) => read_bulk_rows(&spreadsheet, preset.as_ref(), &mut errors),
+                (None, Some(preset)) => (
+                    files.iter().map(|(file_name, _)| BulkRow { line: 0, file_name: file_name.clone(), metadata: preset.clone() }).collect(),
+                    files.iter().map(|(file_name, _)| file_name.clone()).collect(),
+                ),
+                (None, None) => {
+                    errors.push("Please upload a spreadsheet or choose a preset".to_string());
+                    (Vec::new(), HashSet::new())
+                }
+            };
+
+            if files.is_empty() {
+                errors.push("No images uploaded".to_string());
+            }
+
+            let rescaling = upload_rescaling(data.target_pixel_size, &data.filter).unwrap_or_else(|message| {
+                errors.push(message);
+                configuration::rescaling()
+            });
+
+            check_bulk_upload(&sample, &rows, &listed, &files, &mut errors);
+
+            let bytes = files.iter().map(|(_, data)| data.len() as u64).sum();
+            let usage = storage_usage(sample.user_id, &owner_name)?;
+            if let Some(message) = usage.check_import(bytes, files.len(), 0) {
+                errors.push(message);
+            }
+
+            if !errors.is_empty() {
+                return render_load_images(&user_name, user_id, &allowed_programs, None, "Nothing was uploaded, please correct the following problems:", &errors)
+            }
+
+            let mut image_ids = Vec::new();
+            for row in rows.iter() {
+                if let Some((_, data)) = files.iter().find(|(file_name, _)| *file_name == row.file_name) {
+                    image_ids.push(store_grain_image(&sample, &owner_name, &row.file_name, data, &row.metadata, &rescaling)?);
+                }
+            }
+
+            if let Some(message) = undo_uploads_over_quota(&sample, &owner_name, &image_ids)? {
+                return render_load_images(&user_name, user_id, &allowed_programs, None, "Nothing was uploaded, please correct the following problems:", &[message])
+            }
+
+            render_load_images(&user_name, user_id, &allowed_programs, None, &format!("{} image(s) uploaded", rows.len()), &[])
+        } else {
+            Ok(Response::redirect_303(util::get_template_name(&allowed_programs[0])))
+        }
+    } else {
+        Ok(Response::redirect_303("/web_gui/"))
+    }
+}
+
+pub fn create_preset_post(session_id: &str, request: &Request) -> Result<Response, failure::Error> {
+    debug!("grain.rs, create_preset_post()");
+    if util::logged_in(session_id)? {
+        let (user_name, user_id) = util::login_id(session_id)?;
+        let allowed_programs = util::list_of_allowed_programs(user_id)?;
+
+        if allowed_programs.contains(&ProgramType::Grain3DHe) {
+            let (data, form) = post_input_with_metadata!(request, {
+                preset_name: String,
+            })?;
+
+            let name = data.preset_name.trim().to_string();
+
+            let (metadata, mut errors) = metadata_from_form(&form);
+
+            if name.is_empty() {
+                errors.push("Please enter a name for the preset".to_string());
+            } else if preset_name_in_use(user_id, &name) {
+                errors.push(format!("You already have a preset named {}", name));
+            }
+
+            if !errors.is_empty() {
+                return render_load_images(&user_name, user_id, &allowed_programs, None, "The preset was not saved, please correct the following problems:", &errors)
+            }
+
+            add_grain_preset(user_id, name, metadata)?;
+
+            Ok(Response::redirect_303("/web_gui/grain/load_images"))
+        } else {
+            Ok(Response::redirect_303(util::get_template_name(&allowed_programs[0])))
+        }
+    } else {
+        Ok(Response::redirect_303("/web_gui/"))
+    }
+}
+
+pub fn preset_from_image_post(session_id: &str, request: &Request) -> Result<Response, failure::Error> {
+    debug!("grain.rs, preset_from_image_post()");
+    if util::logged_in(session_id)? {
+        let (user_name, user_id) = util::login_id(session_id)?;
+        let allowed_programs = util::list_of_allowed_programs(user_id)?;
+
+        if allowed_programs.contains(&ProgramType::Grain3DHe) {
+            let data = post_input!(request, {
+                image_id: u32,
+                preset_name: String,
+            })?;
+
+            let name = data.preset_name.trim().to_string();
+
+            let error = if name.is_empty() {
+                Some("Please enter a name for the preset".to_string())
+            } else if preset_name_in_use(user_id, &name) {
+                Some(format!("You already have a preset named {}", name))
+            } else {
+                None
+            };
+
+            if let Some(error) = error {
+                return render_edit_image(&user_name, user_id, &allowed_programs, data.image_id, None, &[error])
+            }
+
+            let metadata = {
+                let grain_db = get_db_lock();
+                grain_db.grains.iter()
+                    .find(|grain| grain.id == data.image_id && grain.deleted_at.is_none() && image_permission(&grain_db, user_id, grain).is_some())
+                    .map(|grain| grain.metadata())
+                    .ok_or(WebGuiError::GrainImageNotFoundForUser)?
+            };
+
+            add_grain_preset(user_id, name, metadata)?;
+
+            Ok(Response::redirect_303("/web_gui/grain/load_images"))
+        } else {
+            Ok(Response::redirect_303(util::get_template_name(&allowed_programs[0])))
+        }
+    } else {
+        Ok(Response::redirect_303("/web_gui/"))
+    }
+}
+
+pub fn update_preset_post(session_id: &str, request: &Request) -> Result<Response, failure::Error> {
+    debug!("grain.rs, update_preset_post()");
+    if util::logged_in(session_id)? {
+        let (_, user_id) = util::login_id(session_id)?;
+        let allowed_programs = util::list_of_allowed_programs(user_id)?;
+
+        if allowed_programs.contains(&ProgramType::Grain3DHe) {
+            let data = post_input!(request, {
+                id: u32,
+                action: String,
+                project: Option<u32>,
+            })?;
+
+            match data.action.as_ref() {
+                "default" => toggle_default_preset(user_id, data.id)?,
+                "delete" => update_grain_preset(user_id, data.id, None, true)?,
+                _ => update_grain_preset(user_id, data.id, data.project, false)?,
+            }
+
+            Ok(Response::redirect_303("/web_gui/grain/load_images"))
+        } else {
+            Ok(Response::redirect_303(util::get_template_name(&allowed_programs[0])))
+        }
+    } else {
+        Ok(Response::redirect_303("/web_gui/"))
+    }
+}
+
+pub fn cancel_job_post(session_id: &str, request: &Request) -> Result<Response, failure::Error> {
+    debug!("grain.rs, cancel_job_post()");
+    if util::logged_in(session_id)? {
+        let (_user_name, user_id) = util::login_id(session_id)?;
+        let allowed_programs = util::list_of_allowed_programs(user_id)?;
+
+        if allowed_programs.contains(&ProgramType::Grain3DHe) {
+            let data = post_input!(request, {
+                job: u32,
+            })?;
+
+            cancel_job(user_id, data.job)?;
+
+            Ok(Response::redirect_303("/web_gui/grain/calculate"))
+        } else {
+            Ok(Response::redirect_303(util::get_template_name(&allowed_programs[0])))
+        }
+    } else {
+        Ok(Response::redirect_303("/web_gui/"))
+    }
+}
+
+pub fn remove_images_post(session_id: &str, request: &Request) -> Result<Response, failure::Error> {
+    debug!("grain.rs, remove_image_post()");
+    if util::logged_in(session_id)? {
+        let (_user_name, user_id) = util::login_id(session_id)?;
+        let allowed_programs = util::list_of_allowed_programs(user_id)?;
+
+        if allowed_programs.contains(&ProgramType::Grain3DHe) {
+            let data = post_input!(request, {
+                remove: Vec<u32>
+            })?;
+
+            trash_grain_images(user_id, data.remove)?;
+
+            Ok(Response::redirect_303("/web_gui/grain/load_images"))
+        } else {
+            Ok(Response::redirect_303(util::get_template_name(&allowed_programs[0])))
+        }
+    } else {
+        Ok(Response::redirect_303("/web_gui/"))
+    }
+}
+
+pub fn trash_post(session_id: &str, request: &Request) -> Result<Response, failure::Error> {
+    debug!("grain.rs, trash_post()");
+    if util::logged_in(session_id)? {
+        let (_user_name, user_id) = util::login_id(session_id)?;
+        let allowed_programs = util::list_of_allowed_programs(user_id)?;
+
+        if allowed_programs.contains(&ProgramType::Grain3DHe) {
+            let data = post_input!(request, {
+                action: String,
+                selected: Vec<u32>,
+            })?;
+
+            if data.action == "purge" {
+                let selected = editable_image_ids(&get_db_lock(), user_id, &data.selected);
+                purge_grain_images(|grain| grain.deleted_at.is_some() && selected.contains(&grain.id))?;
+            } else {
+                restore_grain_images(user_id, data.selected)?;
+            }
+
+            Ok(Response::redirect_303("/web_gui/grain/load_images"))
+        } else {
+            Ok(Response::redirect_303(util::get_template_name(&allowed_programs[0])))
+        }
+    } else {
+        Ok(Response::redirect_303("/web_gui/"))
+    }
+}
+
+pub fn outline_images_get(session_id: &str) -> Result<Response, failure::Error> {
+    debug!("grain.rs, outline_image_get()");
+    if util::logged_in(session_id)? {
+        let (user_name, user_id) = util::login_id(session_id)?;
+        let allowed_programs = util::list_of_allowed_programs(user_id)?;
+
+        if allowed_programs.contains(&ProgramType::Grain3DHe) {
+            let context = json!({
+                "login_id": user_name,
+                "programs": util::build_program_menu(&allowed_programs),
+                "usage": util::build_usage(user_id, &user_name)?,
+                "grain_samples": accessible_samples(user_id, Permission::Viewer)?
+            });
+
+            Ok(Response::html(util::render("grain_outline_images", &context)?))
+        } else {
+            Ok(Response::redirect_303(util::get_template_name(&allowed_programs[0])))
+        }
+    } else {
+        Ok(Response::redirect_303("/web_gui/"))
+    }
+}
+
+pub fn outline_images_post(session_id: &str, request: &Request) -> Result<Response, failure::Error> {
+    debug!("grain.rs, outline_image_post()");
+    if util::logged_in(session_id)? {
+        let (user_name, user_id) = util::login_id(session_id)?;
+        let allowed_programs = util::list_of_allowed_programs(user_id)?;
+
+        if allowed_programs.contains(&ProgramType::Grain3DHe) {
+            let data = post_input!(request, {
+                sample: u32
+            })?;
+
+            let sample = sample_for_user(user_id, data.sample, Permission::Viewer)?;
+            let owner_name = util::user_name(sample.user_id)?;
+            let mut sample_images = Vec::new();
+            for (imagename, image_id) in list_of_selected_grain_images(sample.id)? {
+                let path = format!("user_data/{}/{}/{}", owner_name, sample.folder, imagename);
+                // The page starts with the threshold of the stored outline, or the one from Otsu's method.
+                // The browser can only apply one threshold to the whole image.
+                let (bw_threshold, method) = match stored_threshold(image_id) {
+                    Some((value, method)) if method != ThresholdMethod::Adaptive => (value, method),
+                    _ => match image::open(&path) {
+                        Ok(image) => (outline::otsu_threshold(&image), ThresholdMethod::Otsu),
+                        Err(_) => (0.5, ThresholdMethod::Manual),
+                    },
+                };
+
+                sample_images.push(json!({
+                    "path": format!("{}/{}/{}", owner_name, sample.folder, imagename),
+                    "id": image_id,
+                    "bw_threshold": (bw_threshold * 100.0).round() / 100.0,
+                    "threshold_method": method.name(),
+                    "measurements": measurements_context(image_measurements(image_id)),
+                }));
+            }
+
+            let context = json!({
+                "login_id": user_name,
+                "programs": util::build_program_menu(&allowed_programs),
+                "usage": util::build_usage(user_id, &user_name)?,
+                "grain_samples": accessible_samples(user_id, Permission::Viewer)?,
+                "sample_images": sample_images
+            });
+
+            Ok(Response::html(util::render("grain_outline_images", &context)?))
+        } else {
+            Ok(Response::redirect_303(util::get_template_name(&allowed_programs[0])))
+        }
+    } else {
+        Ok(Response::redirect_303("/web_gui/"))
+    }
+}
+
+/// Serves the working image, or with size "thumb" or "medium" one of its previews.
+pub fn sample_image_get(session_id: &str, username: String, samplename: String, imagename: String, size: Option<String>) -> Result<Response, failure::Error> {
+    debug!("grain.rs, sample_image_get()");
+    if util::logged_in(session_id)? {
+        let (_user_name, user_id) = util::login_id(session_id)?;
+        let allowed_programs = util::list_of_allowed_programs(user_id)?;
+
+        if allowed_programs.contains(&ProgramType::Grain3DHe) {
+            let samplename = util::replace_characters(&samplename);
+            let imagename = util::replace_characters(&imagename);
+
+            if user_can_view_image(user_id, &username, &samplename, &imagename)? {
+                let user_path = format!("user_data/{}/{}", username, samplename);
+
+                let filename = match size.as_ref().and_then(|size| PreviewSize::from_name(size)) {
+                    Some(size) => preview_path(&user_path, &imagename, size)?,
+                    None => format!("{}/{}", user_path, imagename),
+                };
+
+                let content_type = if filename.ends_with(".png") {"image/png"} else {"image/jpeg"};
+                let file = File::open(filename)?;
+                Ok(Response::from_file(content_type, file))
+            } else {
+                Err(WebGuiError::GrainImageNotFoundForUser.into())
+            }
+        } else {
+            Err(WebGuiError::ProgramNotAllowedForUser.into())
+        }
+    } else {
+        Err(WebGuiError::UserNotLoggedIn.into())
+    }
+}
+
+/// Returns the outline of one image as JSON without storing it.
+/// The method is "polygon" (default) for an ordered polygon or "rows" for the left and right point of each row.
+pub fn detect_outline_post(session_id: &str, request: &Request) -> Result<Response, failure::Error> {
+    debug!("grain.rs, detect_outline_post()");
+    if util::logged_in(session_id)? {
+        let (_user_name, user_id) = util::login_id(session_id)?;
+        let allowed_programs = util::list_of_allowed_programs(user_id)?;
+
+        if allowed_programs.contains(&ProgramType::Grain3DHe) {
+            let data = post_input!(request, {
+                image_id: u32,
+                bw_threshold: Option<f64>,
+                threshold_method: Option<String>,
+                method: Option<String>,
+            })?;
+
+            let kind = OutlineKind::from_name(data.method.as_ref().map_or("", |method| method.as_str()))
+                .ok_or(WebGuiError::UnknownOutlineMethod)?;
+            let (threshold_method, bw_threshold) = requested_threshold(&data.threshold_method, data.bw_threshold)?;
+            let image = image::open(working_image_path(user_id, data.image_id)?)?;
+            let (coordinates, bw_threshold) = detect_outline(&image, threshold_method, bw_threshold, kind);
+
+            Ok(Response::json(&json!({
+                "image_id": data.image_id,
+                "bw_threshold": bw_threshold,
+                "threshold_method": threshold_method.name(),
+                "outline_kind": kind,
+                "coordinates": coordinates,
+            })))
+        } else {
+            Err(WebGuiError::ProgramNotAllowedForUser.into())
+        }
+    } else {
+        Err(WebGuiError::UserNotLoggedIn.into())
+    }
+}
+
+/// Detects and stores the outlines of all images of a sample with the same threshold.
+pub fn detect_outlines_post(session_id: &str, request: &Request) -> Result<Response, failure::Error> {
+    debug!("grain.rs, detect_outlines_post()");
+    if util::logged_in(session_id)? {
+        let (user_name, user_id) = util::login_id(session_id)?;
+        let allowed_programs = util::list_of_allowed_programs(user_id)?;
+
+        if allowed_programs.contains(&ProgramType::Grain3DHe) {
+            let data = post_input!(request, {
+                sample: u32,
+                bw_threshold: Option<f64>,
+                threshold_method: Option<String>,
+                method: Option<String>,
+            })?;
+
+            let kind = OutlineKind::from_name(data.method.as_ref().map_or("", |method| method.as_str()))
+                .ok_or(WebGuiError::UnknownOutlineMethod)?;
+            let (threshold_method, bw_threshold) = requested_threshold(&data.threshold_method, data.bw_threshold)?;
+            let sample = sample_for_user(user_id, data.sample, Permission::Editor)?;
+            let (detected, total) = detect_outlines_for_sample(user_id, &sample, threshold_method, bw_threshold, kind)?;
+
+            let context = json!({
+                "login_id": user_name,
+                "programs": util::build_program_menu(&allowed_programs),
+                "usage": util::build_usage(user_id, &user_name)?,
+                "grain_samples": accessible_samples(user_id, Permission::Viewer)?,
+                "message": format!("Outlines of {} of {} images in {} detected", detected, total, sample.name),
+            });
+
+            Ok(Response::html(util::render("grain_outline_images", &context)?))
+        } else {
+            Ok(Response::redirect_303(util::get_template_name(&allowed_programs[0])))
+        }
+    } else {
+        Ok(Response::redirect_303("/web_gui/"))
+    }
+}
+
+pub fn store_outline_post(session_id: &str, request: &Request) -> Result<Response, failure::Error> {
+    debug!("grain.rs, store_outline_post()");
+    if util::logged_in(session_id)? {
+        let (user_name, user_id) = util::login_id(session_id)?;
+        let allowed_programs = util::list_of_allowed_programs(user_id)?;
+
+        if allowed_programs.contains(&ProgramType::Grain3DHe) {
+            let data = post_input!(request, {
+                coordinates: Vec<String>,
+                axis: Vec<String>,
+                image_ids: Vec<u32>,
+                bw_thresholds: Vec<f64>,
+                threshold_methods: Vec<String>,
+                outline_kinds: Vec<String>,
+                edit_image: Option<u32>,
+            })?;
+
+            let mut outlines = Vec::new();
+            for i in 0..(data.coordinates.len()) {
+                let coordinates: Vec<Coordinates> = serde_json::from_str(&data.coordinates[i])?;
+                let axis: Axis = serde_json::from_str(&data.axis[i])?;
+                let threshold = data.bw_thresholds.get(i).and_then(|value| {
+                    data.threshold_methods.get(i).and_then(|name| ThresholdMethod::from_name(name)).map(|method| (*value, method))
+                });
+                // The threshold page sends no kind, its outlines are row pairs.
+                let kind = match data.outline_kinds.get(i) {
+                    Some(name) => OutlineKind::from_name(name).ok_or(WebGuiError::UnknownOutlineMethod)?,
+                    None => OutlineKind::RowPairs,
+                };
+
+                if !valid_outline(&coordinates, kind) {
+                    return Err(WebGuiError::InvalidOutline.into())
+                }
+
+                let image_id = *data.image_ids.get(i).ok_or(WebGuiError::GrainImageNotFoundForUser)?;
+                outlines.push(PostedOutline { image_id, coordinates, kind, axis, threshold });
+            }
+
+            save_outlines(user_id, outlines)?;
+
+            if let Some(image_id) = data.edit_image {
+                return Ok(Response::redirect_303(format!("/web_gui/grain/edit_outline?image={}", image_id)))
+            }
+
+            let context = json!({
+                "login_id": user_name,
+                "programs": util::build_program_menu(&allowed_programs),
+                "usage": util::build_usage(user_id, &user_name)?,
+                "grain_samples": accessible_samples(user_id, Permission::Viewer)?,
+                "message": "Outlines and axis saved!"
+            });
+
+            Ok(Response::html(util::render("grain_outline_images", &context)?))
+        } else {
+            Ok(Response::redirect_303(util::get_template_name(&allowed_programs[0])))
+        }
+    } else {
+        Ok(Response::redirect_303("/web_gui/"))
+    }
+}
+
+/// Shows the outline of one image as a polygon that can be edited, with its revisions.
+pub fn edit_outline_get(session_id: &str, request: &Request) -> Result<Response, failure::Error> {
+    debug!("grain.rs, edit_outline_get()");
+    if util::logged_in(session_id)? {
+        let (user_name, user_id) = util::login_id(session_id)?;
+        let allowed_programs = util::list_of_allowed_programs(user_id)?;
+
+        if allowed_programs.contains(&ProgramType::Grain3DHe) {
+            let image_id = request.get_param("image").and_then(|image_id| image_id.parse::<u32>().ok())
+                .ok_or(WebGuiError::GrainImageNotFoundForUser)?;
+
+            let context = json!({
+                "login_id": user_name,
+                "programs": util::build_program_menu(&allowed_programs),
+                "usage": util::build_usage(user_id, &user_name)?,
+                "image": outline_editor_context(user_id, image_id)?,
+            });
+
+            Ok(Response::html(util::render("grain_edit_outline", &context)?))
+        } else {
+            Ok(Response::redirect_303(util::get_template_name(&allowed_programs[0])))
+        }
+    } else {
+        Ok(Response::redirect_303("/web_gui/"))
+    }
+}
+
+pub fn revert_outline_post(session_id: &str, request: &Request) -> Result<Response, failure::Error> {
+    debug!("grain.rs, revert_outline_post()");
+    if util::logged_in(session_id)? {
+        let (_user_name, user_id) = util::login_id(session_id)?;
+        let allowed_programs = util::list_of_allowed_programs(user_id)?;
+
+        if allowed_programs.contains(&ProgramType::Grain3DHe) {
+            let data = post_input!(request, {
+                image_id: u32,
+                revision: u32,
+            })?;
+
+            revert_outline(user_id, data.image_id, data.revision)?;
+
+            Ok(Response::redirect_303(format!("/web_gui/grain/edit_outline?image={}", data.image_id)))
+        } else {
+            Ok(Response::redirect_303(util::get_template_name(&allowed_programs[0])))
+        }
+    } else {
+        Ok(Response::redirect_303("/web_gui/"))
+    }
+}
+
+/// PNG of the working image (or with size "thumb" or "medium" a smaller version) with the outline, axis and a scale bar.
+pub fn overlay_get(session_id: &str, request: &Request) -> Result<Response, failure::Error> {
+    debug!("grain.rs, overlay_get()");
+    if util::logged_in(session_id)? {
+        let (_user_name, user_id) = util::login_id(session_id)?;
+        let allowed_programs = util::list_of_allowed_programs(user_id)?;
+
+        if allowed_programs.contains(&ProgramType::Grain3DHe) {
+            let image_id = request.get_param("image").and_then(|image_id| image_id.parse::<u32>().ok())
+                .ok_or(WebGuiError::GrainImageNotFoundForUser)?;
+            let max_side = request.get_param("size").and_then(|size| PreviewSize::from_name(&size))
+                .map_or(u32::MAX, |size| size.max_side());
+
+            let images = overlay_images(user_id, Some(image_id), None)?;
+            let (grain, path) = images.first().ok_or(WebGuiError::GrainImageNotFoundForUser)?;
+
+            png_response(render_image_overlay(grain, path, max_side)?)
+        } else {
+            Err(WebGuiError::ProgramNotAllowedForUser.into())
+        }
+    } else {
+        Err(WebGuiError::UserNotLoggedIn.into())
+    }
+}
+
+/// The whole log of a job as a text file.
+pub fn job_log_get(session_id: &str, request: &Request) -> Result<Response, failure::Error> {
+    debug!("grain.rs, job_log_get()");
+    if util::logged_in(session_id)? {
+        let (_user_name, user_id) = util::login_id(session_id)?;
+        let allowed_programs = util::list_of_allowed_programs(user_id)?;
+
+        if allowed_programs.contains(&ProgramType::Grain3DHe) {
+            let job_id = request.get_param("job").and_then(|job_id| job_id.parse::<u32>().ok())
+                .ok_or(WebGuiError::JobNotFoundForUser)?;
+            let sample_id = {
+                let grain_db = get_db_lock();
+                grain_db.jobs.iter().find(|job| job.id == job_id).map(|job| job.sample_id).ok_or(WebGuiError::JobNotFoundForUser)?
+            };
+            let sample = sample_for_user(user_id, sample_id, Permission::Viewer)?;
+
+            let mut contents = Vec::new();
+            File::open(job_log_path(&sample, job_id)?).map_err(|_| WebGuiError::JobNotFoundForUser)?.read_to_end(&mut contents)?;
+
+            Ok(Response::from_data("text/plain; charset=utf-8", contents).with_content_disposition_attachment(&format!("job_{}.log", job_id)))
+        } else {
+            Err(WebGuiError::ProgramNotAllowedForUser.into())
+        }
+    } else {
+        Err(WebGuiError::UserNotLoggedIn.into())
+    }
+}
+
+/// PNG with the overlays of all images of a sample, labelled with the image ids.
+pub fn contact_sheet_get(session_id: &str, request: &Request) -> Result<Response, failure::Error> {
+    debug!("grain.rs, contact_sheet_get()");
+    if util::logged_in(session_id)? {
+        let (_user_name, user_id) = util::login_id(session_id)?;
+        let allowed_programs = util::list_of_allowed_programs(user_id)?;
+
+        if allowed_programs.contains(&ProgramType::Grain3DHe) {
+            let sample_id = request.get_param("sample").and_then(|sample_id| sample_id.parse::<u32>().ok())
+                .ok_or(WebGuiError::SampleNotFoundForUser)?;
+            let sample = sample_for_user(user_id, sample_id, Permission::Viewer)?;
+
+            let mut cells = Vec::new();
+            for (grain, path) in overlay_images(user_id, None, Some(sample.id))? {
+                match render_image_overlay(&grain, &path, CONTACT_SHEET_CELL) {
+                    Ok(image) => cells.push((format!("#{}", grain.id), image)),
+                    Err(e) => warn!("grain.rs, contact_sheet_get(): image {} could not be read: {}", path, e),
+                }
+            }
+
+            png_response(overlay::contact_sheet(&cells, CONTACT_SHEET_COLUMNS))
+        } else {
+            Err(WebGuiError::ProgramNotAllowedForUser.into())
+        }
+    } else {
+        Err(WebGuiError::UserNotLoggedIn.into())
+    }
+}
+
+pub fn calculate_get(session_id: &str) -> Result<Response, failure::Error> {
+    debug!("grain.rs, calculate_get()");
+    if util::logged_in(session_id)? {
+        let (user_name, user_id) = util::login_id(session_id)?;
+        let allowed_programs = util::list_of_allowed_programs(user_id)?;
+
+        if allowed_programs.contains(&ProgramType::Grain3DHe) {
+            let results = get_results(user_id)?;
+
+            debug!("results: {:?}", results);
+
+            let context = json!({
+                "login_id": user_name,
+                "programs": util::build_program_menu(&allowed_programs),
+                "usage": util::build_usage(user_id, &user_name)?,
+                "grain_samples": accessible_samples(user_id, Permission::Viewer)?,
+                "message": if results.len() == 0 {"No results yet"} else {""},
+                "results": results,
+                "jobs": jobs_context(user_id)?,
+                "preflight": preflight_context(user_id)?,
+                "measurements": sample_measurements_context(user_id)?,
+            });
+
+            Ok(Response::html(util::render("grain_calculate", &context)?))
+        } else {
+            Ok(Response::redirect_303(util::get_template_name(&allowed_programs[0])))
+        }
+    } else {
+        Ok(Response::redirect_303("/web_gui/"))
+    }
+}
+
+pub fn calculate_post(session_id: &str, request: &Request) -> Result<Response, failure::Error> {
+    debug!("grain.rs, calculate_post()");
+    if util::logged_in(session_id)? {
+        let (user_name, user_id) = util::login_id(session_id)?;
+        let allowed_programs = util::list_of_allowed_programs(user_id)?;
+
+        if allowed_programs.contains(&ProgramType::Grain3DHe) {
+            let data = post_input!(request, {
+                sample: u32,
+                engine: Option<String>,
+                seed: Option<u64>,
+            })?;
+
+            let engine = CalculationEngine::from_name(&data.engine.unwrap_or_default()).ok_or(WebGuiError::UnknownCalculationEngine)?;
+
+            let mut refused = Vec::new();
+
+            // Analytical results are shown right away, unless the config file sets another way to run them.
+            let queued = match engine {
+                CalculationEngine::Analytical => configuration::executor_configured(engine.name()),
+                _ => true,
+            };
+
+            let (message, analytical_results) = if queued {
+                let sample = sample_for_user(user_id, data.sample, Permission::Editor)?;
+                refused = preflight_problems(&sample);
+
+                // Without a seed every Monte Carlo run differs, the seed is part of the result to repeat it.
+                let seed = match engine {
+                    CalculationEngine::MonteCarlo => Some(data.seed.unwrap_or_else(util::timestamp_now)),
+                    _ => None,
+                };
+
+                if refused.is_empty() {
+                    match queue_job(user_id, &sample, engine, seed)? {
+                        Ok(id) => (format!("Calculation of sample {} queued as job {}!", sample.name, id), Vec::new()),
+                        Err(reason) => {
+                            refused.push(reason);
+                            (format!("Sample {} was not submitted:", sample.name), Vec::new())
+                        }
+                    }
+                } else {
+                    (format!("Sample {} was not submitted:", sample.name), Vec::new())
+                }
+            } else {
+                // Nothing is stored, viewers can calculate as well.
+                let sample = sample_for_user(user_id, data.sample, Permission::Viewer)?;
+                (format!("Ft of sample {} for the idealized grain shapes:", sample.name), analytical_results_context(&sample)?)
+            };
+
+            let context = json!({
+                "login_id": user_name,
+                "programs": util::build_program_menu(&allowed_programs),
+                "usage": util::build_usage(user_id, &user_name)?,
+                "grain_samples": accessible_samples(user_id, Permission::Viewer)?,
+                "message": message,
+                "refused": refused,
+                "analytical_results": analytical_results,
+                "jobs": jobs_context(user_id)?,
+                "preflight": preflight_context(user_id)?,
+                "measurements": sample_measurements_context(user_id)?,
+            });
+
+            Ok(Response::html(util::render("grain_calculate", &context)?))
+        } else {
+            Ok(Response::redirect_303(util::get_template_name(&allowed_programs[0])))
+        }
+    } else {
+        Ok(Response::redirect_303("/web_gui/"))
+    }
+}
+
+fn render_samples(user_name: &str, user_id: u16, allowed_programs: &[ProgramType], message: &str) -> Result<Response, failure::Error> {
+    let context = json!({
+        "login_id": user_name,
+        "programs": util::build_program_menu(allowed_programs),
+        "usage": util::build_usage(user_id, user_name)?,
+        "grain_samples": grain_samples_context(user_id)?,
+        "message": message,
+    });
+
+    Ok(Response::html(util::render("grain_samples", &context)?))
+}
+
+pub fn samples_get(session_id: &str) -> Result<Response, failure::Error> {
+    debug!("grain.rs, samples_get()");
+    if util::logged_in(session_id)? {
+        let (user_name, user_id) = util::login_id(session_id)?;
+        let allowed_programs = util::list_of_allowed_programs(user_id)?;
+
+        if allowed_programs.contains(&ProgramType::Grain3DHe) {
+            render_samples(&user_name, user_id, &allowed_programs, "")
+        } else {
+            Ok(Response::redirect_303(util::get_template_name(&allowed_programs[0])))
+        }
+    } else {
+        Ok(Response::redirect_303("/web_gui/"))
+    }
+}
+
+pub fn create_sample_post(session_id: &str, request: &Request) -> Result<Response, failure::Error> {
+    debug!("grain.rs, create_sample_post()");
+    if util::logged_in(session_id)? {
+        let (user_name, user_id) = util::login_id(session_id)?;
+        let allowed_programs = util::list_of_allowed_programs(user_id)?;
+
+        if allowed_programs.contains(&ProgramType::Grain3DHe) {
+            let data = post_input!(request, {
+                name: String,
+                description: String,
+                locality: String,
+                lithology: String,
+                collector: String,
+                notes: String,
+            })?;
+
+            let name = util::replace_characters(&data.name);
+
+            if name.is_empty() {
+                return render_samples(&user_name, user_id, &allowed_programs, "Please enter a sample name")
+            }
+
+            if sample_name_in_use(user_id, &name, 0)? {
+                return render_samples(&user_name, user_id, &allowed_programs, "Sample name already in use")
+            }
+
+            if let Some(message) = storage_usage(user_id, &user_name)?.check_new_sample() {
+                return render_samples(&user_name, user_id, &allowed_programs, &message)
+            }
+
+            add_grain_sample(GrainSample {
+                user_id,
+                name,
+                description: data.description,
+                locality: data.locality,
+                lithology: data.lithology,
+                collector: data.collector,
+                created: util::timestamp_now(),
+                notes: data.notes,
+                ..GrainSample::default()
+            })?;
+
+            Ok(Response::redirect_303("/web_gui/grain/samples"))
+        } else {
+            Ok(Response::redirect_303(util::get_template_name(&allowed_programs[0])))
+        }
+    } else {
+        Ok(Response::redirect_303("/web_gui/"))
+    }
+}
+
+pub fn update_sample_post(session_id: &str, request: &Request) -> Result<Response, failure::Error> {
+    debug!("grain.rs, update_sample_post()");
+    if util::logged_in(session_id)? {
+        let (user_name, user_id) = util::login_id(session_id)?;
+        let allowed_programs = util::list_of_allowed_programs(user_id)?;
+
+        if allowed_programs.contains(&ProgramType::Grain3DHe) {
+            let data = post_input!(request, {
+                id: u32,
+                action: String,
+                project: Option<u32>,
+                name: String,
+                description: String,
+                locality: String,
+                lithology: String,
+                collector: String,
+                notes: String,
+            })?;
+
+            let sample = sample_for_user(user_id, data.id, Permission::Editor)?;
+            let is_owner = sample.user_id == user_id;
+
+            if data.action == "delete" {
+                if !is_owner {
+                    return render_samples(&user_name, user_id, &allowed_programs, "Only the owner can delete a sample")
+                }
+
+                trash_grain_sample(user_id, sample.id)?;
+                return Ok(Response::redirect_303("/web_gui/grain/samples"))
+            }
+
+            let name = util::replace_characters(&data.name);
+
+            if name.is_empty() {
+                return render_samples(&user_name, user_id, &allowed_programs, "Please enter a sample name")
+            }
+
+            if sample_name_in_use(sample.user_id, &name, sample.id)? {
+                return render_samples(&user_name, user_id, &allowed_programs, "Sample name already in use")
+            }
+
+            // Only the owner decides with whom a sample is shared.
+            let project_id = if is_owner {
+                match data.project {
+                    Some(project_id) if !assignable_projects(user_id)?.iter().any(|project| project["id"] == project_id) => {
+                        return render_samples(&user_name, user_id, &allowed_programs, "You are not an editor of this project")
+                    }
+                    project_id => project_id,
+                }
+            } else {
+                sample.project_id
+            };
+
+            update_grain_sample(GrainSample {
+                project_id,
+                name,
+                description: data.description,
+                locality: data.locality,
+                lithology: data.lithology,
+                collector: data.collector,
+                notes: data.notes,
+                ..sample
+            })?;
+
+            Ok(Response::redirect_303("/web_gui/grain/samples"))
+        } else {
+            Ok(Response::redirect_303(util::get_template_name(&allowed_programs[0])))
+        }
+    } else {
+        Ok(Response::redirect_303("/web_gui/"))
+    }
+}
+
+fn render_projects(user_name: &str, user_id: u16, allowed_programs: &[ProgramType], message: &str) -> Result<Response, failure::Error> {
+    let context = json!({
+        "login_id": user_name,
+        "programs": util::build_program_menu(allowed_programs),
+        "usage": util::build_usage(user_id, user_name)?,
+        "grain_projects": grain_projects_context(user_id)?,
+        "message": message,
+    });
+
+    Ok(Response::html(util::render("grain_projects", &context)?))
+}
+
+pub fn projects_get(session_id: &str) -> Result<Response, failure::Error> {
+    debug!("grain.rs, projects_get()");
+    if util::logged_in(session_id)? {
+        let (user_name, user_id) = util::login_id(session_id)?;
+        let allowed_programs = util::list_of_allowed_programs(user_id)?;
+
+        if allowed_programs.contains(&ProgramType::Grain3DHe) {
+            render_projects(&user_name, user_id, &allowed_programs, "")
+        } else {
+            Ok(Response::redirect_303(util::get_template_name(&allowed_programs[0])))
+        }
+    } else {
+        Ok(Response::redirect_303("/web_gui/"))
+    }
+}
+
+pub fn create_project_post(session_id: &str, request: &Request) -> Result<Response, failure::Error> {
+    debug!("grain.rs, create_project_post()");
+    if util::logged_in(session_id)? {
+        let (user_name, user_id) = util::login_id(session_id)?;
+        let allowed_programs = util::list_of_allowed_programs(user_id)?;
+
+        if allowed_programs.contains(&ProgramType::Grain3DHe) {
+            let data = post_input!(request, {
+                name: String,
+            })?;
+
+            let name = data.name.trim().to_string();
+
+            if name.is_empty() {
+                return render_projects(&user_name, user_id, &allowed_programs, "Please enter a project name")
+            }
+
+            add_grain_project(name, user_id)?;
+
+            Ok(Response::redirect_303("/web_gui/grain/projects"))
+        } else {
+            Ok(Response::redirect_303(util::get_template_name(&allowed_programs[0])))
+        }
+    } else {
+        Ok(Response::redirect_303("/web_gui/"))
+    }
+}
+
+pub fn update_project_post(session_id: &str, request: &Request) -> Result<Response, failure::Error> {
+    debug!("grain.rs, update_project_post()");
+    if util::logged_in(session_id)? {
+        let (user_name, user_id) = util::login_id(session_id)?;
+        let allowed_programs = util::list_of_allowed_programs(user_id)?;
+
+        if allowed_programs.contains(&ProgramType::Grain3DHe) {
+            let data = post_input!(request, {
+                id: u32,
+                action: String,
+                login_id: String,
+                permission: String,
+            })?;
+
+            let project = project_for_owner(user_id, data.id)?;
+
+            if data.action == "delete" {
+                delete_grain_project(project.id)?;
+                return Ok(Response::redirect_303("/web_gui/grain/projects"))
+            }
+
+            let member_id = match util::user_id(data.login_id.trim())? {
+                Some(member_id) if member_id != project.owner_id => member_id,
+                Some(_) => return render_projects(&user_name, user_id, &allowed_programs, "The owner is always a member of the project"),
+                None => return render_projects(&user_name, user_id, &allowed_programs, "User name not found"),
+            };
+
+            let permission = match (data.action.as_ref(), data.permission.as_ref()) {
+                ("remove", _) => None,
+                (_, "Editor") => Some(Permission::Editor),
+                _ => Some(Permission::Viewer),
+            };
+
+            set_project_member(project.id, member_id, permission)?;
+
+            Ok(Response::redirect_303("/web_gui/grain/projects"))
+        } else {
+            Ok(Response::redirect_303(util::get_template_name(&allowed_programs[0])))
+        }
+    } else {
+        Ok(Response::redirect_303("/web_gui/"))
+    }
+}
+
+pub fn export_get(session_id: &str, request: &Request) -> Result<Response, failure::Error> {
+    debug!("grain.rs, export_get()");
+    if util::logged_in(session_id)? {
+        let (user_name, user_id) = util::login_id(session_id)?;
+        let allowed_programs = util::list_of_allowed_programs(user_id)?;
+
+        if allowed_programs.contains(&ProgramType::Grain3DHe) {
+            let project_id = request.get_param("project").and_then(|project_id| project_id.parse::<u32>().ok());
+            let data = export_archive(user_id, project_id)?;
+
+            let file_name = match project_id {
+                Some(project_id) => format!("grain_project_{}.zip", project_id),
+                None => format!("grain_{}.zip", user_name),
+            };
+
+            Ok(Response::from_data("application/zip", data).with_content_disposition_attachment(&file_name))
+        } else {
+            Ok(Response::redirect_303(util::get_template_name(&allowed_programs[0])))
+        }
+    } else {
+        Ok(Response::redirect_303("/web_gui/"))
+    }
+}
+
+pub fn import_post(session_id: &str, request: &Request) -> Result<Response, failure::Error> {
+    debug!("grain.rs, import_post()");
+    if util::logged_in(session_id)? {
+        let (user_name, user_id) = util::login_id(session_id)?;
+        let allowed_programs = util::list_of_allowed_programs(user_id)?;
+
+        if allowed_programs.contains(&ProgramType::Grain3DHe) {
+            let data = post_input!(request, {
+                archive: input::post::BufferedFile,
+            })?;
+
+            let mut archive = match ZipArchive::new(Cursor::new(&data.archive.data[..])) {
+                Ok(archive) => archive,
+                Err(_) => return render_samples(&user_name, user_id, &allowed_programs, "The uploaded file is not a zip archive"),
+            };
+
+            let (manifest, bytes) = match read_archive_manifest(&mut archive) {
+                Ok(result) => result,
+                Err(_) => return render_samples(&user_name, user_id, &allowed_programs, "The archive does not contain a valid manifest.json"),
+            };
+
+            let images = manifest.samples.iter().map(|sample| sample.images.len()).sum();
+            let usage = storage_usage(user_id, &user_name)?;
+            if let Some(message) = usage.check_import(bytes, images, manifest.samples.len()) {
+                return render_samples(&user_name, user_id, &allowed_programs, &message)
+            }
+
+            let num_of_samples = import_archive(user_id, &mut archive, manifest)?;
+
+            render_samples(&user_name, user_id, &allowed_programs, &format!("{} sample(s) imported", num_of_samples))
+        } else {
+            Ok(Response::redirect_303(util::get_template_name(&allowed_programs[0])))
+        }
+    } else {
+        Ok(Response::redirect_303("/web_gui/"))
+    }
+}
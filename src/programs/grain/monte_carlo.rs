@@ -0,0 +1,432 @@
+//! Alpha ejection correction (Ft) by Monte Carlo simulation on a voxel model of the grain.
+//! The model is built from the outline of an image with the c-axis parallel to the field of view
+//! (the side view) and the outline of an image with the c-axis perpendicular to it (the cross section).
+//! Lengths are in micrometer. The same seed gives the same result for any number of threads.
+
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+
+use super::analytical::{self, Isotope};
+use super::measurements::axis_direction;
+use super::parameters::Mineral;
+use super::{Axis, Coordinates};
+
+/// Particles simulated with one random stream, the work is split into chunks of this size.
+const CHUNK_SIZE: usize = 10_000;
+
+/// Squared distance used for voxels inside the grain before the distance transform.
+const FAR: f64 = 1e20;
+
+/// Distribution of the parent nuclides in zoned grains.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Zoning {
+    /// Width (micrometer) of the rim below the surface.
+    pub rim_width: f64,
+    /// Concentration in the rim relative to the core.
+    pub ratio_rim_core: f64,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Settings {
+    /// Simulated alpha particles per isotope.
+    pub particles: usize,
+    pub seed: u64,
+    pub threads: usize,
+}
+
+/// Grain on a regular grid with the c-axis along x, surrounded by at least one voxel outside the grain.
+#[derive(Clone, Debug)]
+pub struct VoxelGrain {
+    origin: [f64; 3],
+    voxel_size: f64,
+    dims: [usize; 3],
+    inside: Vec<bool>,
+    /// Relative concentration of the parent nuclides, 0 outside the grain.
+    concentration: Vec<f64>,
+    /// Position of the two ends of the grain along the c-axis.
+    ends: (f64, f64),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MonteCarloFt {
+    pub ft: Vec<(Isotope, f64)>,
+    /// Mean of the isotopes weighted by their He production.
+    pub mean_ft: f64,
+}
+
+impl VoxelGrain {
+    pub fn voxel_size(&self) -> f64 {
+        self.voxel_size
+    }
+
+    pub fn volume(&self) -> f64 {
+        self.inside.iter().filter(|inside| **inside).count() as f64 * self.voxel_size.powi(3)
+    }
+
+    fn index(&self, i: usize, j: usize, k: usize) -> usize {
+        (i * self.dims[1] + j) * self.dims[2] + k
+    }
+
+    fn contains(&self, point: [f64; 3]) -> bool {
+        let mut indices = [0; 3];
+
+        for axis in 0..3 {
+            let position = ((point[axis] - self.origin[axis]) / self.voxel_size).floor();
+            if position < 0.0 || position >= self.dims[axis] as f64 {
+                return false
+            }
+            indices[axis] = position as usize;
+        }
+
+        self.inside[self.index(indices[0], indices[1], indices[2])]
+    }
+}
+
+/// Builds the voxel model: the side view is rotated so that its c-axis is along x, each slice across the
+/// c-axis is the cross section scaled to the width of the side view at that position.
+/// Pixel sizes are in micrometer, the longest dimension along the c-axis is split into voxels_along_axis voxels.
+pub fn reconstruct(parallel: &[Coordinates], axis: &Axis, parallel_pixel_size: f64,
+    perpendicular: &[Coordinates], perpendicular_pixel_size: f64, zoning: Option<Zoning>, voxels_along_axis: usize) -> Result<VoxelGrain, String> {
+    if parallel.len() < 3 || perpendicular.len() < 3 {
+        return Err("both outlines need at least three points".to_string())
+    }
+
+    let (dx, dy) = axis_direction(axis).ok_or("no c-axis stored for the parallel image")?;
+
+    let side: Vec<_> = parallel.iter()
+        .map(|point| {
+            let (x, y) = (point.x * parallel_pixel_size, point.y * parallel_pixel_size);
+            (x * dx + y * dy, y * dx - x * dy)
+        })
+        .collect();
+    let (x0, x1, y0, y1) = bounds(&side);
+
+    let (u0, u1, v0, v1) = bounds(&perpendicular.iter()
+        .map(|point| (point.x * perpendicular_pixel_size, point.y * perpendicular_pixel_size))
+        .collect::<Vec<_>>());
+    let section: Vec<_> = perpendicular.iter()
+        .map(|point| (point.x * perpendicular_pixel_size - (u0 + u1) / 2.0, point.y * perpendicular_pixel_size - (v0 + v1) / 2.0))
+        .collect();
+    let (half_width, half_height) = ((u1 - u0) / 2.0, (v1 - v0) / 2.0);
+
+    if !(x1 > x0 && y1 > y0 && half_width > 0.0 && half_height > 0.0 && voxels_along_axis > 0) {
+        return Err("the outlines have no area".to_string())
+    }
+
+    let voxel_size = (x1 - x0) / voxels_along_axis as f64;
+    let z_extent = (y1 - y0) / (2.0 * half_width) * half_height;
+    let origin = [x0 - voxel_size, y0 - voxel_size, -z_extent - voxel_size];
+    let dims = [voxels_along_axis + 2, ((y1 - y0) / voxel_size).ceil() as usize + 2, (2.0 * z_extent / voxel_size).ceil() as usize + 2];
+    let center = |index: usize, axis: usize| origin[axis] + (index as f64 + 0.5) * voxel_size;
+
+    let mut inside = vec![false; dims[0] * dims[1] * dims[2]];
+
+    for i in 0..dims[0] {
+        let silhouette = crossings(&side, center(i, 0));
+        if silhouette.len() < 2 {
+            continue
+        }
+
+        let (bottom, top) = (silhouette[0], silhouette[silhouette.len() - 1]);
+        let scale = (top - bottom) / (2.0 * half_width);
+
+        for j in (0..dims[1]).filter(|j| within(&silhouette, center(*j, 1))) {
+            let across = crossings(&section, (center(j, 1) - (bottom + top) / 2.0) / scale);
+
+            for k in (0..dims[2]).filter(|k| within(&across, center(*k, 2) / scale)) {
+                inside[(i * dims[1] + j) * dims[2] + k] = true;
+            }
+        }
+    }
+
+    let concentration: Vec<_> = match zoning {
+        Some(zoning) => inside.iter().zip(surface_distance(&inside, dims))
+            .map(|(inside, distance)| match (inside, (distance - 0.5) * voxel_size < zoning.rim_width) {
+                (false, _) => 0.0,
+                (true, true) => zoning.ratio_rim_core,
+                (true, false) => 1.0,
+            })
+            .collect(),
+        None => inside.iter().map(|inside| if *inside {1.0} else {0.0}).collect(),
+    };
+
+    if !concentration.iter().any(|value| *value > 0.0) {
+        return Err("the reconstructed grain contains no parent nuclides".to_string())
+    }
+
+    Ok(VoxelGrain { origin, voxel_size, dims, inside, concentration, ends: (x0, x1) })
+}
+
+/// Ft of each isotope and their mean, weighted by the He production of the ratios (mol) of Th and Sm to U.
+/// With broken tips the ends of the grain are fractures, particles leaving through them are counted as retained.
+/// stop is asked before each chunk of particles, None if it returned true.
+pub fn ft_correction(grain: &VoxelGrain, mineral: Mineral, broken_tips: bool, ratio_232_238: f64, ratio_147_238: f64,
+    settings: &Settings, stop: &(dyn Fn() -> bool + Sync)) -> Option<MonteCarloFt> {
+    let mut ft = Vec::new();
+
+    for (stream, isotope) in Isotope::ALL.iter().enumerate() {
+        let seed = stream_seed(settings.seed, stream as u64);
+        ft.push((*isotope, retained_fraction(grain, isotope.stopping_distance(mineral), broken_tips, settings.particles, seed, settings.threads, stop)?));
+    }
+
+    let mean_ft = analytical::weighted_mean_ft(&ft, ratio_232_238, ratio_147_238);
+
+    Some(MonteCarloFt { ft, mean_ft })
+}
+
+/// Fraction of the particles that stop inside the grain. They start in a voxel chosen by its concentration
+/// and travel the stopping distance in a random direction.
+fn retained_fraction(grain: &VoxelGrain, stopping_distance: f64, broken_tips: bool, particles: usize, seed: u64, threads: usize,
+    stop: &(dyn Fn() -> bool + Sync)) -> Option<f64> {
+    let mut sources = Vec::new();
+    let mut cumulative = Vec::new();
+    let mut total = 0.0;
+
+    for (index, concentration) in grain.concentration.iter().enumerate().filter(|(_, concentration)| **concentration > 0.0) {
+        total += concentration;
+        sources.push(index);
+        cumulative.push(total);
+    }
+
+    let chunks = particles.div_ceil(CHUNK_SIZE);
+    let next_chunk = AtomicUsize::new(0);
+    let retained = AtomicUsize::new(0);
+    let stopped = AtomicBool::new(false);
+
+    thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            scope.spawn(|| loop {
+                let chunk = next_chunk.fetch_add(1, Ordering::Relaxed);
+                if chunk >= chunks || stopped.load(Ordering::Relaxed) {
+                    break
+                }
+                if stop() {
+                    stopped.store(true, Ordering::Relaxed);
+                    break
+                }
+
+                let mut rng = SplitMix64(stream_seed(seed, chunk as u64));
+                let count = CHUNK_SIZE.min(particles - chunk * CHUNK_SIZE);
+                let mut chunk_retained = 0;
+
+                for _ in 0..count {
+                    let target = rng.next_f64() * total;
+                    let choice = cumulative.partition_point(|value| *value <= target).min(sources.len() - 1);
+                    let index = sources[choice];
+                    let voxel = [index / (grain.dims[1] * grain.dims[2]), (index / grain.dims[2]) % grain.dims[1], index % grain.dims[2]];
+
+                    let mut end = [0.0; 3];
+                    let cos_theta = 2.0 * rng.next_f64() - 1.0;
+                    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                    let phi = 2.0 * PI * rng.next_f64();
+                    let direction = [sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta];
+
+                    for axis in 0..3 {
+                        let start = grain.origin[axis] + (voxel[axis] as f64 + rng.next_f64()) * grain.voxel_size;
+                        end[axis] = start + stopping_distance * direction[axis];
+                    }
+
+                    if grain.contains(end) || (broken_tips && (end[0] < grain.ends.0 || end[0] > grain.ends.1)) {
+                        chunk_retained += 1;
+                    }
+                }
+
+                retained.fetch_add(chunk_retained, Ordering::Relaxed);
+            });
+        }
+    });
+
+    if stopped.into_inner() {
+        return None
+    }
+
+    if particles == 0 {
+        return Some(0.0)
+    }
+
+    Some(retained.into_inner() as f64 / particles as f64)
+}
+
+/// Minimum and maximum of x and y.
+fn bounds(points: &[(f64, f64)]) -> (f64, f64, f64, f64) {
+    points.iter().fold((f64::INFINITY, f64::NEG_INFINITY, f64::INFINITY, f64::NEG_INFINITY),
+        |(x0, x1, y0, y1), (x, y)| (x0.min(*x), x1.max(*x), y0.min(*y), y1.max(*y)))
+}
+
+/// Sorted y values where the edges of the closed polygon cross the vertical line at x.
+fn crossings(polygon: &[(f64, f64)], x: f64) -> Vec<f64> {
+    let mut values: Vec<_> = polygon.iter().enumerate()
+        .filter_map(|(i, a)| {
+            let b = polygon[(i + 1) % polygon.len()];
+            if (a.0 <= x) != (b.0 <= x) {
+                Some(a.1 + (x - a.0) * (b.1 - a.1) / (b.0 - a.0))
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    values
+}
+
+/// Inside the polygon if an odd number of crossings lies below the value.
+fn within(crossings: &[f64], value: f64) -> bool {
+    !crossings.iter().filter(|crossing| **crossing < value).count().is_multiple_of(2)
+}
+
+/// Euclidean distance (voxels) of each voxel to the nearest voxel outside the grain, one axis after the other.
+fn surface_distance(inside: &[bool], dims: [usize; 3]) -> Vec<f64> {
+    let mut squared: Vec<_> = inside.iter().map(|inside| if *inside {FAR} else {0.0}).collect();
+    let strides = [dims[1] * dims[2], dims[2], 1];
+
+    for axis in 0..3 {
+        for start in (0..squared.len()).filter(|index| (index / strides[axis]).is_multiple_of(dims[axis])) {
+            let line: Vec<_> = (0..dims[axis]).map(|n| squared[start + n * strides[axis]]).collect();
+
+            for (n, value) in squared_distance_1d(&line).into_iter().enumerate() {
+                squared[start + n * strides[axis]] = value;
+            }
+        }
+    }
+
+    squared.into_iter().map(f64::sqrt).collect()
+}
+
+/// Lower envelope of parabolas (Felzenszwalb and Huttenlocher 2012) over the squared distances of a line.
+fn squared_distance_1d(f: &[f64]) -> Vec<f64> {
+    let n = f.len();
+    let mut vertices = vec![0; n];
+    let mut boundaries = vec![0.0; n + 1];
+    let mut k = 0;
+    boundaries[0] = f64::NEG_INFINITY;
+    boundaries[1] = f64::INFINITY;
+
+    let intersection = |q: usize, v: usize| ((f[q] + (q * q) as f64) - (f[v] + (v * v) as f64)) / (2.0 * (q as f64 - v as f64));
+
+    for q in 1..n {
+        let mut s = intersection(q, vertices[k]);
+        while s <= boundaries[k] {
+            k -= 1;
+            s = intersection(q, vertices[k]);
+        }
+        k += 1;
+        vertices[k] = q;
+        boundaries[k] = s;
+        boundaries[k + 1] = f64::INFINITY;
+    }
+
+    k = 0;
+    (0..n).map(|q| {
+        while boundaries[k + 1] < q as f64 {
+            k += 1;
+        }
+        let offset = q as f64 - vertices[k] as f64;
+        offset * offset + f[vertices[k]]
+    }).collect()
+}
+
+/// Seed of an independent random stream, ex. of an isotope or of a chunk.
+fn stream_seed(seed: u64, stream: u64) -> u64 {
+    SplitMix64(seed ^ stream.wrapping_mul(0x9E37_79B9_7F4A_7C15)).next_u64()
+}
+
+/// SplitMix64 (Steele et al. 2014), small and fast and gives the same numbers on every platform.
+pub(super) struct SplitMix64(pub(super) u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in [0, 1).
+    pub(super) fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(x: f64, y: f64) -> Coordinates {
+        Coordinates { x, y }
+    }
+
+    fn circle(radius: f64) -> Vec<Coordinates> {
+        (0..64).map(|i| {
+            let angle = 2.0 * PI * f64::from(i) / 64.0;
+            point(radius * angle.cos(), radius * angle.sin())
+        }).collect()
+    }
+
+    fn rectangle(length: f64, width: f64) -> Vec<Coordinates> {
+        vec![point(0.0, 0.0), point(length, 0.0), point(length, width), point(0.0, width)]
+    }
+
+    fn along_x() -> Axis {
+        Axis { x1: 0, y1: 0, x2: 10, y2: 0 }
+    }
+
+    fn settings(particles: usize, seed: u64, threads: usize) -> Settings {
+        Settings { particles, seed, threads }
+    }
+
+    #[test]
+    fn distance_along_a_line() {
+        assert_eq!(squared_distance_1d(&[0.0, FAR, FAR, FAR, 0.0]), vec![0.0, 1.0, 4.0, 1.0, 0.0]);
+        assert_eq!(squared_distance_1d(&[0.0, FAR, FAR]), vec![0.0, 1.0, 4.0]);
+    }
+
+    #[test]
+    fn cylinder_volume() {
+        // Side view of 100 x 40 pixels of 0.5 micrometer, cross section with a radius of 10 micrometer.
+        let grain = reconstruct(&rectangle(200.0, 80.0), &along_x(), 0.5, &circle(20.0), 0.5, None, 100).unwrap();
+
+        assert_eq!(grain.voxel_size(), 1.0);
+        assert!((grain.volume() / (PI * 400.0 * 100.0) - 1.0).abs() < 0.03);
+        assert!(reconstruct(&rectangle(200.0, 80.0), &Axis::default(), 0.5, &circle(20.0), 0.5, None, 100).is_err());
+    }
+
+    #[test]
+    fn sphere_matches_analytical() {
+        let grain = reconstruct(&circle(50.0), &along_x(), 1.0, &circle(50.0), 1.0, None, 100).unwrap();
+        let result = ft_correction(&grain, Mineral::Apatite, false, 0.0, 0.0, &settings(200_000, 7, 4), &|| false).unwrap();
+
+        assert!((result.ft[0].1 - analytical::sphere_ft(18.81, 50.0)).abs() < 0.01);
+    }
+
+    #[test]
+    fn same_seed_for_any_number_of_threads() {
+        let grain = reconstruct(&rectangle(80.0, 30.0), &along_x(), 1.0, &circle(15.0), 1.0, None, 40).unwrap();
+        let run = |seed: u64, threads: usize| ft_correction(&grain, Mineral::Zircon, false, 1.0, 0.5, &settings(35_000, seed, threads), &|| false);
+        let single = run(42, 1);
+
+        assert!(single.is_some());
+        assert_eq!(single, run(42, 3));
+        assert_ne!(single, run(43, 3));
+        assert_eq!(ft_correction(&grain, Mineral::Zircon, false, 1.0, 0.5, &settings(35_000, 42, 3), &|| true), None);
+    }
+
+    #[test]
+    fn broken_tips_and_zoning() {
+        let side = rectangle(60.0, 40.0);
+        let section = circle(20.0);
+        let run = |zoning: Option<Zoning>, broken_tips: bool| {
+            let grain = reconstruct(&side, &along_x(), 1.0, &section, 1.0, zoning, 60).unwrap();
+            ft_correction(&grain, Mineral::Apatite, broken_tips, 0.0, 0.0, &settings(50_000, 1, 2), &|| false).unwrap().mean_ft
+        };
+
+        let homogeneous = run(None, false);
+        assert!(run(None, true) > homogeneous + 0.02);
+
+        // Fewer particles start close to the surface of a depleted rim.
+        assert!(run(Some(Zoning { rim_width: 8.0, ratio_rim_core: 0.2 }), false) > homogeneous + 0.02);
+        assert!(run(Some(Zoning { rim_width: 8.0, ratio_rim_core: 5.0 }), false) < homogeneous - 0.02);
+    }
+}
@@ -0,0 +1,801 @@
+//! Grain outline detection on the working image, so the coordinates can be stored as they are.
+//!
+//! trace_outline() follows the boundary of the grain with marching squares and returns a closed polygon.
+//! detect_outline() is the pipeline of js/grain_outline.js (gauss blur, black and white threshold,
+//! laplace filter and fill_inside) that returns the first and last edge pixel of each row.
+//!
+//! Both take the black and white threshold either as a number or chosen from the image with
+//! otsu_threshold() or adaptive_threshold().
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, VecDeque};
+use std::ops::Range;
+
+use image::{DynamicImage, RgbaImage};
+
+use super::Coordinates;
+
+/// Pixels closer than this to the border of the image are never part of the outline.
+const BORDER: u32 = 10;
+
+const GAUSS_SIDE: u32 = 7;
+
+const GAUSS_WEIGHTS: [f64; 49] = [
+    0.0, 0.0, 0.0, 0.0046816479400749065, 0.0, 0.0, 0.0,
+    0.0, 0.0046816479400749065, 0.016853932584269662, 0.0299625468164794, 0.016853932584269662, 0.0046816479400749065, 0.0,
+    0.0, 0.016853932584269662, 0.0599250936329588, 0.09363295880149813, 0.0599250936329588, 0.016853932584269662, 0.0,
+    0.0046816479400749065, 0.0299625468164794, 0.09363295880149813, 0.09363295880149813, 0.09363295880149813, 0.0299625468164794, 0.0046816479400749065,
+    0.0, 0.016853932584269662, 0.0599250936329588, 0.09363295880149813, 0.0599250936329588, 0.016853932584269662, 0.0,
+    0.0, 0.0046816479400749065, 0.016853932584269662, 0.0299625468164794, 0.016853932584269662, 0.0046816479400749065, 0.0,
+    0.0, 0.0, 0.0, 0.0046816479400749065, 0.0, 0.0, 0.0,
+];
+
+const LAPLACE_WEIGHTS: [f64; 9] = [
+    -1.0, -1.0, -1.0,
+    -1.0, 8.0, -1.0,
+    -1.0, -1.0, -1.0,
+];
+
+/// The adaptive threshold is chosen separately in ADAPTIVE_TILES x ADAPTIVE_TILES parts of the image.
+const ADAPTIVE_TILES: usize = 4;
+
+/// Parts of the image where the two classes differ less than this in brightness have no threshold of their own.
+const MIN_TILE_CONTRAST: f64 = 0.1;
+
+/// Nor do parts where one class has less than this fraction of the pixels.
+const MIN_TILE_CLASS: f64 = 0.05;
+
+/// Black and white threshold between 0.0 (everything white) and 1.0 (everything black).
+#[derive(Clone, Debug, PartialEq)]
+pub enum Threshold {
+    /// The same for the whole image.
+    Global(f64),
+    /// One for each pixel, row by row.
+    Local(Vec<f64>),
+}
+
+impl Threshold {
+    fn at(&self, index: usize) -> f64 {
+        match self {
+            Threshold::Global(value) => *value,
+            Threshold::Local(values) => values.get(index).cloned().unwrap_or(0.5),
+        }
+    }
+}
+
+/// Returns the outline of the grain as pairs of points (left and right edge) for each row.
+pub fn detect_outline(image: &DynamicImage, threshold: &Threshold) -> Vec<Coordinates> {
+    debug!("outline.rs, detect_outline()");
+    let mut pixels = image.to_rgba8();
+
+    filter_image(&mut pixels, &GAUSS_WEIGHTS);
+    bw_image(&mut pixels, threshold);
+    filter_image(&mut pixels, &LAPLACE_WEIGHTS);
+
+    fill_inside(&pixels)
+}
+
+/// Values are stored like in a canvas (Uint8ClampedArray): clamped and rounded half to even.
+fn clamp_u8(value: f64) -> u8 {
+    value.clamp(0.0, 255.0).round_ties_even() as u8
+}
+
+/// Convolution of the color channels, pixels outside of the image are skipped. Alpha is set to 255.
+fn filter_image(pixels: &mut RgbaImage, weights: &[f64]) {
+    let side = (weights.len() as f64).sqrt().round() as i64;
+    let half_side = side / 2;
+    let width = pixels.width() as i64;
+    let height = pixels.height() as i64;
+    let src = pixels.clone();
+
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = [0.0; 3];
+
+            for cy in 0..side {
+                for cx in 0..side {
+                    let scy = y + cy - half_side;
+                    let scx = x + cx - half_side;
+
+                    if scy >= 0 && scy < height && scx >= 0 && scx < width {
+                        let src_pixel = src.get_pixel(scx as u32, scy as u32);
+                        let weight = weights[(cy * side + cx) as usize];
+
+                        for (channel, value) in sum.iter_mut().enumerate() {
+                            *value += f64::from(src_pixel[channel]) * weight;
+                        }
+                    }
+                }
+            }
+
+            let dst_pixel = pixels.get_pixel_mut(x as u32, y as u32);
+            for (channel, value) in sum.iter().enumerate() {
+                dst_pixel[channel] = clamp_u8(*value);
+            }
+            dst_pixel[3] = 255;
+        }
+    }
+}
+
+/// Brightness between 0.0 and 1.0, the mean of the color channels.
+fn gray_value(pixel: &image::Rgba<u8>) -> f64 {
+    (f64::from(pixel[0]) + f64::from(pixel[1]) + f64::from(pixel[2])) / (255.0 * 3.0)
+}
+
+/// Pixels darker than the threshold become black, all others white.
+fn bw_image(pixels: &mut RgbaImage, threshold: &Threshold) {
+    for (index, pixel) in pixels.pixels_mut().enumerate() {
+        if gray_value(pixel) < threshold.at(index) {
+            pixel.0 = [0, 0, 0, 255];
+        } else {
+            pixel.0 = [255, 255, 255, 0];
+        }
+    }
+}
+
+/// The first and the last edge pixel of each row.
+fn fill_inside(pixels: &RgbaImage) -> Vec<Coordinates> {
+    let width = pixels.width();
+    let height = pixels.height();
+    let mut result = Vec::new();
+
+    for y in BORDER..height.saturating_sub(BORDER) {
+        let mut x1 = None;
+        let mut x2 = 0;
+
+        for x in BORDER..width.saturating_sub(BORDER) {
+            if pixels.get_pixel(x, y)[0] == 255 {
+                match x1 {
+                    None => x1 = Some(x),
+                    Some(_) => x2 = x,
+                }
+            }
+        }
+
+        if let Some(x1) = x1 {
+            if x2 > x1 {
+                result.push(Coordinates { x: f64::from(x1), y: f64::from(y) });
+                result.push(Coordinates { x: f64::from(x2), y: f64::from(y) });
+            }
+        }
+    }
+
+    result
+}
+
+/// Returns the outer boundary of the grain as a closed polygon (the first point is not repeated)
+/// with sub-pixel accuracy, pixel centers are at whole numbers.
+/// The grain is the largest connected region on the side of the threshold that does not cover
+/// most of the image border, so bright grains on dark background work as well as the reverse.
+pub fn trace_outline(image: &DynamicImage, threshold: &Threshold) -> Vec<Coordinates> {
+    debug!("outline.rs, trace_outline()");
+    let mut pixels = image.to_rgba8();
+    filter_image(&mut pixels, &GAUSS_WEIGHTS);
+
+    let width = pixels.width() as usize;
+    let height = pixels.height() as usize;
+
+    if width == 0 || height == 0 {
+        return Vec::new()
+    }
+
+    let mut field: Vec<f64> = pixels.pixels().enumerate()
+        .map(|(index, pixel)| gray_value(pixel) - threshold.at(index))
+        .collect();
+
+    let border = (0..width).flat_map(|x| vec![x, (height - 1) * width + x])
+        .chain((0..height).flat_map(|y| vec![y * width, y * width + width - 1]))
+        .collect::<Vec<_>>();
+
+    if border.iter().filter(|index| field[**index] > 0.0).count() * 2 > border.len() {
+        for value in field.iter_mut() {
+            *value = -*value;
+        }
+    }
+
+    let grain = largest_region(&field, width, height);
+
+    if grain.is_empty() {
+        return Vec::new()
+    }
+
+    // Only the grain is inside, padded with one row / column of background on each side so the outline is always closed.
+    let padded_width = width + 2;
+    let padded_height = height + 2;
+    let mut padded = vec![-1.0; padded_width * padded_height];
+
+    for y in 0..height {
+        for x in 0..width {
+            let index = y * width + x;
+            padded[(y + 1) * padded_width + x + 1] = if grain[index] {
+                field[index].max(1e-6)
+            } else {
+                field[index].min(-1e-6)
+            };
+        }
+    }
+
+    let mut outline = marching_squares(&padded, padded_width, padded_height);
+
+    for point in outline.iter_mut() {
+        point.x -= 1.0;
+        point.y -= 1.0;
+    }
+
+    outline
+}
+
+/// Histogram of the brightness in 256 levels of the gauss filtered pixels in the given part of the image.
+/// The filter darkens the pixels near the border of the image, they are left out if the image is large enough.
+fn histogram(pixels: &RgbaImage, xs: Range<u32>, ys: Range<u32>) -> [u64; 256] {
+    let margin = GAUSS_SIDE / 2;
+    let clip = |range: Range<u32>, size: u32| {
+        if size > 2 * margin {
+            range.start.max(margin)..range.end.min(size - margin)
+        } else {
+            range
+        }
+    };
+
+    let mut histogram = [0; 256];
+
+    for y in clip(ys, pixels.height()) {
+        for x in clip(xs.clone(), pixels.width()) {
+            histogram[(gray_value(pixels.get_pixel(x, y)) * 255.0).round() as usize] += 1;
+        }
+    }
+
+    histogram
+}
+
+/// Otsu's method: the level that splits the histogram into the two classes with the largest variance between them.
+/// Returns the threshold between 0.0 and 1.0 and the fraction of pixels and mean brightness of both classes,
+/// or None if all pixels have the same brightness.
+fn otsu_split(histogram: &[u64; 256]) -> Option<(f64, [f64; 2], [f64; 2])> {
+    let total = histogram.iter().sum::<u64>() as f64;
+    let total_sum = histogram.iter().enumerate().map(|(level, count)| level as f64 * *count as f64).sum::<f64>();
+
+    let mut best_variance = 0.0;
+    let mut best_levels = Vec::new();
+    let mut count_below = 0.0;
+    let mut sum_below = 0.0;
+
+    for (level, count) in histogram.iter().enumerate().take(255) {
+        count_below += *count as f64;
+        sum_below += level as f64 * *count as f64;
+        let count_above = total - count_below;
+
+        if count_below == 0.0 || count_above == 0.0 {
+            continue
+        }
+
+        let difference = sum_below / count_below - (total_sum - sum_below) / count_above;
+        let variance = count_below * count_above * difference * difference;
+
+        if variance > best_variance * (1.0 + 1e-9) {
+            best_variance = variance;
+            best_levels = vec![level];
+        } else if variance >= best_variance * (1.0 - 1e-9) {
+            best_levels.push(level);
+        }
+    }
+
+    if best_levels.is_empty() {
+        return None
+    }
+
+    // Several levels can split equally well if no pixel lies between them, take the middle one.
+    let level = best_levels.iter().sum::<usize>() as f64 / best_levels.len() as f64;
+    let split = level.floor() as usize;
+    let count_below = histogram[..=split].iter().sum::<u64>() as f64;
+    let sum_below = histogram[..=split].iter().enumerate().map(|(level, count)| level as f64 * *count as f64).sum::<f64>();
+    let count_above = total - count_below;
+
+    Some((
+        (level + 0.5) / 255.0,
+        [count_below / total, count_above / total],
+        [sum_below / count_below / 255.0, (total_sum - sum_below) / count_above / 255.0],
+    ))
+}
+
+/// Threshold for the whole image with Otsu's method, computed like the outline on the gauss filtered image.
+/// 0.5 if the image has only one brightness.
+pub fn otsu_threshold(image: &DynamicImage) -> f64 {
+    debug!("outline.rs, otsu_threshold()");
+    let mut pixels = image.to_rgba8();
+    filter_image(&mut pixels, &GAUSS_WEIGHTS);
+
+    otsu_split(&histogram(&pixels, 0..pixels.width(), 0..pixels.height())).map_or(0.5, |(threshold, _, _)| threshold)
+}
+
+/// Threshold for each pixel, for images with uneven illumination.
+/// Otsu's method is applied to each tile of the image that contains both grain and background.
+/// The other tiles take the threshold of the nearest such tiles, and between the tile centers
+/// the threshold is interpolated linearly.
+pub fn adaptive_threshold(image: &DynamicImage) -> Threshold {
+    debug!("outline.rs, adaptive_threshold()");
+    let mut pixels = image.to_rgba8();
+    filter_image(&mut pixels, &GAUSS_WEIGHTS);
+
+    let width = pixels.width() as usize;
+    let height = pixels.height() as usize;
+    let global = otsu_split(&histogram(&pixels, 0..pixels.width(), 0..pixels.height())).map_or(0.5, |(threshold, _, _)| threshold);
+
+    if width < ADAPTIVE_TILES || height < ADAPTIVE_TILES {
+        return Threshold::Global(global)
+    }
+
+    let tile_width = width.div_ceil(ADAPTIVE_TILES);
+    let tile_height = height.div_ceil(ADAPTIVE_TILES);
+    let mut tiles = vec![None; ADAPTIVE_TILES * ADAPTIVE_TILES];
+
+    for ty in 0..ADAPTIVE_TILES {
+        for tx in 0..ADAPTIVE_TILES {
+            let xs = (tx * tile_width) as u32..((tx + 1) * tile_width).min(width) as u32;
+            let ys = (ty * tile_height) as u32..((ty + 1) * tile_height).min(height) as u32;
+
+            if let Some((threshold, fractions, means)) = otsu_split(&histogram(&pixels, xs, ys)) {
+                if means[1] - means[0] >= MIN_TILE_CONTRAST && fractions.iter().all(|fraction| *fraction >= MIN_TILE_CLASS) {
+                    tiles[ty * ADAPTIVE_TILES + tx] = Some(threshold);
+                }
+            }
+        }
+    }
+
+    if tiles.iter().all(|tile| tile.is_none()) {
+        return Threshold::Global(global)
+    }
+
+    let distance = |a: usize, b: usize| {
+        let (ax, ay, bx, by) = ((a % ADAPTIVE_TILES) as i64, (a / ADAPTIVE_TILES) as i64, (b % ADAPTIVE_TILES) as i64, (b / ADAPTIVE_TILES) as i64);
+        (ax - bx) * (ax - bx) + (ay - by) * (ay - by)
+    };
+
+    let filled: Vec<f64> = (0..tiles.len()).map(|index| match tiles[index] {
+        Some(threshold) => threshold,
+        None => {
+            let nearest = (0..tiles.len()).filter(|other| tiles[*other].is_some())
+                .map(|other| distance(index, other)).min().unwrap_or(0);
+            let values: Vec<f64> = (0..tiles.len())
+                .filter(|other| distance(index, *other) == nearest)
+                .filter_map(|other| tiles[other])
+                .collect();
+
+            values.iter().sum::<f64>() / values.len() as f64
+        }
+    }).collect();
+
+    // Position between the tile centers: index of the tile before and weight of the tile after.
+    let position = |coordinate: usize, tile_size: usize| {
+        let tile = ((coordinate as f64 + 0.5) / tile_size as f64 - 0.5).clamp(0.0, (ADAPTIVE_TILES - 1) as f64);
+        let before = (tile.floor() as usize).min(ADAPTIVE_TILES - 2);
+        (before, tile - before as f64)
+    };
+
+    let mut values = Vec::with_capacity(width * height);
+
+    for y in 0..height {
+        let (ty, fy) = position(y, tile_height);
+
+        for x in 0..width {
+            let (tx, fx) = position(x, tile_width);
+            let tile = |dx: usize, dy: usize| filled[(ty + dy) * ADAPTIVE_TILES + tx + dx];
+
+            values.push(
+                (tile(0, 0) * (1.0 - fx) + tile(1, 0) * fx) * (1.0 - fy)
+                + (tile(0, 1) * (1.0 - fx) + tile(1, 1) * fx) * fy
+            );
+        }
+    }
+
+    Threshold::Local(values)
+}
+
+/// The largest 4-connected region of positive values.
+fn largest_region(field: &[f64], width: usize, height: usize) -> Vec<bool> {
+    let mut label = vec![0; field.len()];
+    let mut best = (0, 0);
+    let mut next_label = 1;
+
+    for start in 0..field.len() {
+        if field[start] <= 0.0 || label[start] != 0 {
+            continue
+        }
+
+        let mut size = 0;
+        let mut queue = VecDeque::new();
+        label[start] = next_label;
+        queue.push_back(start);
+
+        while let Some(index) = queue.pop_front() {
+            size += 1;
+            let (x, y) = (index % width, index / width);
+            let mut neighbours = Vec::with_capacity(4);
+            if x > 0 { neighbours.push(index - 1) }
+            if x + 1 < width { neighbours.push(index + 1) }
+            if y > 0 { neighbours.push(index - width) }
+            if y + 1 < height { neighbours.push(index + width) }
+
+            for neighbour in neighbours {
+                if field[neighbour] > 0.0 && label[neighbour] == 0 {
+                    label[neighbour] = next_label;
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+
+        if size > best.1 {
+            best = (next_label, size);
+        }
+
+        next_label += 1;
+    }
+
+    label.iter().map(|value| best.0 != 0 && *value == best.0).collect()
+}
+
+/// Edges of the grid between two neighbouring values: horizontal from (x, y) to (x + 1, y) or vertical from (x, y) to (x, y + 1).
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+enum GridEdge {
+    Horizontal(usize, usize),
+    Vertical(usize, usize),
+}
+
+/// Traces all boundaries between positive (inside) and negative values and returns the one enclosing the largest area.
+/// Crossing points are interpolated linearly between the values at both ends of a grid edge.
+fn marching_squares(field: &[f64], width: usize, height: usize) -> Vec<Coordinates> {
+    let value = |x: usize, y: usize| field[y * width + x];
+
+    let crossing = |edge: GridEdge| -> (f64, f64) {
+        let ((x1, y1), (x2, y2)) = match edge {
+            GridEdge::Horizontal(x, y) => ((x, y), (x + 1, y)),
+            GridEdge::Vertical(x, y) => ((x, y), (x, y + 1)),
+        };
+        let (v1, v2) = (value(x1, y1), value(x2, y2));
+        let t = v1 / (v1 - v2);
+
+        (x1 as f64 + t * (x2 as f64 - x1 as f64), y1 as f64 + t * (y2 as f64 - y1 as f64))
+    };
+
+    // Segments are oriented so that the inside is always on the same side, each boundary edge starts exactly one segment.
+    let mut segments = HashMap::new();
+
+    for y in 0..height - 1 {
+        for x in 0..width - 1 {
+            // Corners clockwise from top left, each with the two cell edges that touch it.
+            let corners = [(x, y), (x + 1, y), (x + 1, y + 1), (x, y + 1)];
+            let edges = [
+                GridEdge::Horizontal(x, y),
+                GridEdge::Vertical(x + 1, y),
+                GridEdge::Horizontal(x, y + 1),
+                GridEdge::Vertical(x, y),
+            ];
+            let touching = [(edges[3], edges[0]), (edges[0], edges[1]), (edges[1], edges[2]), (edges[2], edges[3])];
+            let inside = [
+                value(x, y) > 0.0, value(x + 1, y) > 0.0, value(x + 1, y + 1) > 0.0, value(x, y + 1) > 0.0,
+            ];
+            let num_inside = inside.iter().filter(|corner| **corner).count();
+
+            if num_inside == 0 || num_inside == 4 {
+                continue
+            }
+
+            // Corners that are cut off from the other three by a segment.
+            let mut isolated = Vec::new();
+
+            match num_inside {
+                1 => isolated.push(inside.iter().position(|corner| *corner).unwrap_or(0)),
+                3 => isolated.push(inside.iter().position(|corner| !*corner).unwrap_or(0)),
+                _ if inside[0] == inside[2] => {
+                    // Saddle, decided by the value in the center of the cell.
+                    let center = corners.iter().map(|&(cx, cy)| value(cx, cy)).sum::<f64>() / 4.0;
+                    isolated.extend((0..4).filter(|corner| inside[*corner] != (center > 0.0)));
+                }
+                _ => {
+                    // Two neighbouring corners inside: one segment between the edges that cross the boundary.
+                    let first = (0..4).find(|corner| inside[*corner] && !inside[(*corner + 3) % 4]).unwrap_or(0);
+                    let second = (first + 1) % 4;
+                    segments.insert(touching[first].0, touching[second].1);
+                    continue
+                }
+            }
+
+            for corner in isolated {
+                let (before, after) = touching[corner];
+                // Going clockwise around an inside corner keeps the inside on the right.
+                if inside[corner] {
+                    segments.insert(before, after);
+                } else {
+                    segments.insert(after, before);
+                }
+            }
+        }
+    }
+
+    let mut best: Vec<Coordinates> = Vec::new();
+    let mut best_area = 0.0;
+
+    while let Some(&start) = segments.keys().next() {
+        let mut polygon = Vec::new();
+        let mut edge = start;
+
+        while let Some(next) = segments.remove(&edge) {
+            let (px, py) = crossing(edge);
+            polygon.push(Coordinates { x: px, y: py });
+            edge = next;
+        }
+
+        let area = polygon_area(&polygon).abs();
+        if area > best_area {
+            best_area = area;
+            best = polygon;
+        }
+    }
+
+    best
+}
+
+/// Signed area (shoelace formula).
+pub fn polygon_area(polygon: &[Coordinates]) -> f64 {
+    let mut area = 0.0;
+
+    for (i, point) in polygon.iter().enumerate() {
+        let next = &polygon[(i + 1) % polygon.len()];
+        area += point.x * next.y - next.x * point.y;
+    }
+
+    area / 2.0
+}
+
+/// Converts a polygon to the first and last pixel inside of it for each row.
+/// This is the format of outlines stored before polygons and of the coordinate files read by matlab.
+pub fn polygon_to_row_pairs(polygon: &[Coordinates]) -> Vec<Coordinates> {
+    if polygon.len() < 3 {
+        return Vec::new()
+    }
+
+    let min_y = polygon.iter().map(|point| point.y).fold(f64::INFINITY, f64::min).ceil() as i64;
+    let max_y = polygon.iter().map(|point| point.y).fold(f64::NEG_INFINITY, f64::max).floor() as i64;
+    let mut result = Vec::new();
+
+    for row in min_y.max(0)..=max_y {
+        let y = row as f64;
+        // Edges crossing the row and vertices on it.
+        let mut crossings = Vec::new();
+
+        for (i, p1) in polygon.iter().enumerate() {
+            let p2 = &polygon[(i + 1) % polygon.len()];
+
+            if (p1.y < y && p2.y > y) || (p2.y < y && p1.y > y) {
+                crossings.push(p1.x + (y - p1.y) * (p2.x - p1.x) / (p2.y - p1.y));
+            } else if p1.y == y {
+                crossings.push(p1.x);
+            }
+        }
+
+        if !crossings.is_empty() {
+            let left = crossings.iter().cloned().fold(f64::INFINITY, f64::min).ceil().max(0.0);
+            let right = crossings.iter().cloned().fold(f64::NEG_INFINITY, f64::max).floor().max(0.0);
+
+            if right > left {
+                result.push(Coordinates { x: left, y });
+                result.push(Coordinates { x: right, y });
+            }
+        }
+    }
+
+    result
+}
+
+/// Converts the left and right point of each row to a polygon: down the left side and up the right side.
+pub fn row_pairs_to_polygon(rows: &[Coordinates]) -> Vec<Coordinates> {
+    let mut pairs: Vec<_> = rows.chunks(2).filter(|pair| pair.len() == 2).collect();
+    pairs.sort_by(|a, b| a[0].y.partial_cmp(&b[0].y).unwrap_or(Ordering::Equal));
+
+    pairs.iter().map(|pair| pair[0].clone())
+        .chain(pairs.iter().rev().map(|pair| pair[1].clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{self, DynamicImage, Rgb, RgbImage};
+
+    use super::*;
+
+    fn grain_photo() -> DynamicImage {
+        image::open("images/grain20_photo.jpg").unwrap()
+    }
+
+    /// White square from 20 to 39 on a black background.
+    fn white_square() -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(60, 60, |x, y| {
+            if (20..40).contains(&x) && (20..40).contains(&y) {
+                Rgb([255, 255, 255])
+            } else {
+                Rgb([0, 0, 0])
+            }
+        }))
+    }
+
+    #[test]
+    fn square_edges_are_found() {
+        let outline = detect_outline(&white_square(), &Threshold::Global(0.5));
+
+        let row: Vec<_> = outline.iter().filter(|point| point.y == 30.0).map(|point| point.x).collect();
+        assert_eq!(row, vec![20.0, 39.0]);
+        assert!(outline.iter().all(|point| point.y >= 20.0 && point.y < 40.0));
+    }
+
+    #[test]
+    fn uniform_images_have_no_outline() {
+        let black = DynamicImage::ImageRgb8(RgbImage::new(60, 60));
+
+        assert!(detect_outline(&white_square(), &Threshold::Global(0.0)).is_empty());
+        assert!(detect_outline(&black, &Threshold::Global(0.5)).is_empty());
+        assert!(detect_outline(&black, &Threshold::Global(1.0)).is_empty());
+    }
+
+    #[test]
+    fn grain_photo_has_outline() {
+        let image = grain_photo();
+        let outline = detect_outline(&image, &Threshold::Global(0.5));
+
+        assert!(!outline.is_empty());
+        assert_eq!(outline.len() % 2, 0);
+
+        for pair in outline.chunks(2) {
+            assert_eq!(pair[0].y, pair[1].y);
+            assert!(pair[0].x < pair[1].x);
+            assert!(pair[0].x >= f64::from(BORDER) && pair[1].x < f64::from(image.width() - BORDER));
+            assert!(pair[0].y >= f64::from(BORDER) && pair[0].y < f64::from(image.height() - BORDER));
+        }
+    }
+
+    #[test]
+    fn grain_photo_rows_are_ordered_and_unique() {
+        let outline = detect_outline(&grain_photo(), &Threshold::Global(0.5));
+        let rows: Vec<_> = outline.chunks(2).map(|pair| pair[0].y).collect();
+
+        assert!(rows.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn tiny_images_have_no_outline() {
+        let image = DynamicImage::ImageRgb8(RgbImage::new(5, 5));
+        assert!(detect_outline(&image, &Threshold::Global(0.5)).is_empty());
+        assert!(trace_outline(&image, &Threshold::Global(0.5)).is_empty());
+    }
+
+    /// Bright rectangle from (20, 10) to (139, 69), the right half of the image is twice as bright as the left.
+    fn unevenly_lit_grain() -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(160, 80, |x, y| {
+            let value = if (20..140).contains(&x) && (10..70).contains(&y) {220} else {50};
+            let value = if x < 80 {value / 2} else {value};
+            Rgb([value, value, value])
+        }))
+    }
+
+    #[test]
+    fn otsu_threshold_between_two_levels() {
+        let threshold = otsu_threshold(&white_square());
+        assert!(threshold > 0.3 && threshold < 0.7, "threshold: {}", threshold);
+
+        let gray = DynamicImage::ImageRgb8(RgbImage::from_pixel(20, 20, Rgb([100, 100, 100])));
+        assert_eq!(otsu_threshold(&gray), 0.5);
+
+        let outline = detect_outline(&white_square(), &Threshold::Global(otsu_threshold(&white_square())));
+        let row: Vec<_> = outline.iter().filter(|point| point.y == 30.0).map(|point| point.x).collect();
+        assert_eq!(row, vec![20.0, 39.0]);
+    }
+
+    #[test]
+    fn adaptive_threshold_with_uneven_illumination() {
+        let image = unevenly_lit_grain();
+        let area = 120.0 * 60.0;
+
+        // The global threshold lies between the bright grain and everything else, the dim half of the grain is lost.
+        let global = polygon_area(&trace_outline(&image, &Threshold::Global(otsu_threshold(&image)))).abs();
+        assert!(global < area * 0.6, "global: {}", global);
+
+        let threshold = adaptive_threshold(&image);
+        match threshold {
+            Threshold::Local(ref values) => assert_eq!(values.len(), 160 * 80),
+            Threshold::Global(_) => panic!("no local threshold"),
+        }
+
+        let adaptive = polygon_area(&trace_outline(&image, &threshold)).abs();
+        assert!((adaptive - area).abs() < area * 0.05, "adaptive: {}", adaptive);
+    }
+
+    #[test]
+    fn adaptive_threshold_of_uniform_image_is_global() {
+        let gray = DynamicImage::ImageRgb8(RgbImage::from_pixel(20, 20, Rgb([100, 100, 100])));
+        assert_eq!(adaptive_threshold(&gray), Threshold::Global(0.5));
+    }
+
+    /// Disc with a notch from the top down to its center.
+    fn notched_disc(background: u8, grain: u8) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(80, 80, |x, y| {
+            let (dx, dy) = (x as f64 - 40.0, y as f64 - 40.0);
+            let in_disc = dx * dx + dy * dy <= 25.0 * 25.0;
+            let in_notch = y <= 40 && (35..45).contains(&x);
+
+            if in_disc && !in_notch {
+                Rgb([grain, grain, grain])
+            } else {
+                Rgb([background, background, background])
+            }
+        }))
+    }
+
+    #[test]
+    fn traced_square_is_closed_and_sub_pixel() {
+        let outline = trace_outline(&white_square(), &Threshold::Global(0.5));
+
+        assert!(outline.len() > 40);
+        // The boundary lies half way between the last white and the first black pixel.
+        assert!(outline.iter().all(|point| point.x > 18.5 && point.x < 40.5 && point.y > 18.5 && point.y < 40.5));
+        assert!(outline.iter().any(|point| point.x.fract() != 0.0 || point.y.fract() != 0.0));
+
+        let area = polygon_area(&outline).abs();
+        assert!(area > 18.0 * 18.0 && area < 21.0 * 21.0, "area: {}", area);
+
+        // Ordered: neighbouring points are never more than one cell apart, including the last and the first.
+        for (i, point) in outline.iter().enumerate() {
+            let next = &outline[(i + 1) % outline.len()];
+            assert!((point.x - next.x).abs() <= 1.0 && (point.y - next.y).abs() <= 1.0);
+        }
+    }
+
+    #[test]
+    fn traced_outline_follows_concave_notch() {
+        let outline = trace_outline(&notched_disc(0, 255), &Threshold::Global(0.5));
+
+        // The polygon goes down into the notch to the center of the disc.
+        assert!(outline.iter().any(|point| (point.x - 40.0).abs() < 1.0 && point.y > 38.0 && point.y < 42.0));
+        // The row pairs of the old format cannot represent that, they span the notch.
+        let rows = detect_outline(&notched_disc(0, 255), &Threshold::Global(0.5));
+        let row: Vec<_> = rows.iter().filter(|point| point.y == 30.0).map(|point| point.x).collect();
+        assert!(row.len() == 2 && row[0] < 20.0 && row[1] > 60.0);
+    }
+
+    #[test]
+    fn dark_grain_on_bright_background() {
+        let bright = polygon_area(&trace_outline(&notched_disc(0, 255), &Threshold::Global(0.5))).abs();
+        let dark = polygon_area(&trace_outline(&notched_disc(255, 0), &Threshold::Global(0.5))).abs();
+
+        assert!((bright - dark).abs() < bright * 0.05, "bright: {}, dark: {}", bright, dark);
+    }
+
+    #[test]
+    fn row_pairs_of_traced_square() {
+        let rows = polygon_to_row_pairs(&trace_outline(&white_square(), &Threshold::Global(0.5)));
+        let row: Vec<_> = rows.iter().filter(|point| point.y == 30.0).map(|point| point.x).collect();
+
+        assert_eq!(row, vec![20.0, 39.0]);
+        assert!(rows.iter().all(|point| point.x.fract() == 0.0 && point.y.fract() == 0.0));
+    }
+
+    #[test]
+    fn row_pairs_and_polygon() {
+        let rows = detect_outline(&white_square(), &Threshold::Global(0.5));
+        let polygon = row_pairs_to_polygon(&rows);
+
+        assert_eq!(polygon.len(), rows.len());
+        assert_eq!(polygon[0], rows[0]);
+        assert_eq!(polygon[polygon.len() - 1], rows[1]);
+        assert_eq!(polygon_to_row_pairs(&polygon), rows);
+    }
+
+    #[test]
+    fn grain_photo_traced_outline() {
+        let image = grain_photo();
+        let outline = trace_outline(&image, &Threshold::Global(0.5));
+
+        assert!(outline.len() > 50);
+        assert!(outline.iter().all(|point| point.x >= -0.5 && point.y >= -0.5
+            && point.x <= image.width() as f64 - 0.5 && point.y <= image.height() as f64 - 0.5));
+        assert!(polygon_area(&outline).abs() > 100.0);
+    }
+}
@@ -0,0 +1,243 @@
+//! Review images: the stored outline and axis drawn onto the working image with a scale bar,
+//! and contact sheets with the images of a whole sample.
+
+use image::{DynamicImage, Rgb, RgbImage};
+use image::imageops::{self, FilterType};
+
+use super::{Axis, Coordinates};
+
+const OUTLINE_COLOR: Rgb<u8> = Rgb([255, 0, 0]);
+const AXIS_COLOR: Rgb<u8> = Rgb([0, 255, 0]);
+const BAR_COLOR: Rgb<u8> = Rgb([255, 255, 255]);
+const SHADOW_COLOR: Rgb<u8> = Rgb([0, 0, 0]);
+const SHEET_COLOR: Rgb<u8> = Rgb([255, 255, 255]);
+
+/// Glyphs 3 pixels wide and 5 high, one row per entry with the leftmost pixel in the highest bit.
+const GLYPHS: [(char, [u8; 5]); 15] = [
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b111, 0b001, 0b111, 0b100, 0b111]),
+    ('3', [0b111, 0b001, 0b111, 0b001, 0b111]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b111, 0b001, 0b111]),
+    ('6', [0b111, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b001, 0b001, 0b001]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b111]),
+    ('#', [0b101, 0b111, 0b101, 0b111, 0b101]),
+    ('.', [0b000, 0b000, 0b000, 0b000, 0b010]),
+    ('µ', [0b101, 0b101, 0b101, 0b111, 0b100]),
+    ('m', [0b000, 0b111, 0b111, 0b101, 0b101]),
+    (' ', [0b000, 0b000, 0b000, 0b000, 0b000]),
+];
+
+/// Size of a text pixel in image pixels.
+const TEXT_SCALE: u32 = 2;
+
+/// Space around and between the images of a contact sheet.
+const SHEET_PADDING: u32 = 10;
+
+/// Draws the outline (a closed polygon in pixels of the working image), the axis and a scale bar onto the image.
+/// The image is reduced to fit into max_side first, pixel_size is the size (micrometer) of a pixel of the working image.
+pub fn render_overlay(image: &DynamicImage, polygon: &[Coordinates], axis: &Axis, pixel_size: f64, max_side: u32) -> RgbImage {
+    debug!("overlay.rs, render_overlay()");
+    let mut canvas = if image.width() > max_side || image.height() > max_side {
+        image.resize(max_side, max_side, FilterType::Triangle).to_rgb8()
+    } else {
+        image.to_rgb8()
+    };
+
+    let scale = if image.width() > 0 { f64::from(canvas.width()) / f64::from(image.width()) } else { 1.0 };
+    let line_width = if canvas.width().max(canvas.height()) > 400 {2.0} else {1.0};
+
+    for (i, point) in polygon.iter().enumerate() {
+        let next = &polygon[(i + 1) % polygon.len()];
+        draw_line(&mut canvas, (point.x * scale, point.y * scale), (next.x * scale, next.y * scale), line_width, OUTLINE_COLOR);
+    }
+
+    if (axis.x1, axis.y1) != (axis.x2, axis.y2) {
+        draw_line(&mut canvas,
+            (f64::from(axis.x1) * scale, f64::from(axis.y1) * scale),
+            (f64::from(axis.x2) * scale, f64::from(axis.y2) * scale),
+            line_width, AXIS_COLOR);
+    }
+
+    if pixel_size > 0.0 {
+        draw_scale_bar(&mut canvas, pixel_size / scale);
+    }
+
+    canvas
+}
+
+/// Arranges the images in rows of the given number of columns, each with its label above it.
+pub fn contact_sheet(cells: &[(String, RgbImage)], columns: u32) -> RgbImage {
+    debug!("overlay.rs, contact_sheet()");
+    let columns = columns.max(1);
+    let rows = (cells.len() as u32).div_ceil(columns).max(1);
+    let label_height = 5 * TEXT_SCALE + SHEET_PADDING / 2;
+    let cell_width = cells.iter().map(|(_, image)| image.width()).max().unwrap_or(0);
+    let cell_height = cells.iter().map(|(_, image)| image.height()).max().unwrap_or(0) + label_height;
+
+    let mut sheet = RgbImage::from_pixel(
+        columns * (cell_width + SHEET_PADDING) + SHEET_PADDING,
+        rows * (cell_height + SHEET_PADDING) + SHEET_PADDING,
+        SHEET_COLOR);
+
+    for (index, (label, image)) in cells.iter().enumerate() {
+        let x = SHEET_PADDING + (index as u32 % columns) * (cell_width + SHEET_PADDING);
+        let y = SHEET_PADDING + (index as u32 / columns) * (cell_height + SHEET_PADDING);
+
+        draw_text(&mut sheet, x, y, label, SHADOW_COLOR);
+        imageops::replace(&mut sheet, image, i64::from(x), i64::from(y + label_height));
+    }
+
+    sheet
+}
+
+/// A round length (1, 2 or 5 times a power of ten) close to the given one.
+fn round_length(length: f64) -> f64 {
+    let magnitude = 10f64.powf(length.log10().floor());
+
+    [5.0, 2.0, 1.0].iter().map(|factor| factor * magnitude)
+        .find(|candidate| *candidate <= length)
+        .unwrap_or(magnitude)
+}
+
+/// Scale bar of about a quarter of the image width in the lower left corner, labelled in micrometer.
+fn draw_scale_bar(canvas: &mut RgbImage, pixel_size: f64) {
+    let length = round_length(f64::from(canvas.width()) * pixel_size / 4.0);
+    let bar_width = (length / pixel_size).round() as u32;
+    let bar_height = TEXT_SCALE * 2;
+    let margin = 5 * TEXT_SCALE;
+
+    if bar_width == 0 || canvas.height() < 3 * margin + bar_height || canvas.width() < 2 * margin + bar_width {
+        return
+    }
+
+    let x = margin;
+    let y = canvas.height() - margin - bar_height;
+    fill_rect(canvas, x - 1, y - 1, bar_width + 2, bar_height + 2, SHADOW_COLOR);
+    fill_rect(canvas, x, y, bar_width, bar_height, BAR_COLOR);
+
+    let label = format!("{} µm", length);
+    let text_y = y - TEXT_SCALE * 7;
+    fill_rect(canvas, x - 1, text_y - 1, text_width(&label) + 2, 5 * TEXT_SCALE + 2, SHADOW_COLOR);
+    draw_text(canvas, x, text_y, &label, BAR_COLOR);
+}
+
+fn text_width(text: &str) -> u32 {
+    text.chars().count() as u32 * 4 * TEXT_SCALE
+}
+
+/// Characters without a glyph are left out.
+fn draw_text(canvas: &mut RgbImage, x: u32, y: u32, text: &str, color: Rgb<u8>) {
+    let mut left = x;
+
+    for character in text.chars() {
+        if let Some((_, rows)) = GLYPHS.iter().find(|(glyph, _)| *glyph == character) {
+            for (row, bits) in rows.iter().enumerate() {
+                for column in 0..3 {
+                    if bits & (0b100 >> column) != 0 {
+                        fill_rect(canvas, left + column * TEXT_SCALE, y + row as u32 * TEXT_SCALE, TEXT_SCALE, TEXT_SCALE, color);
+                    }
+                }
+            }
+
+            left += 4 * TEXT_SCALE;
+        }
+    }
+}
+
+/// Parts outside of the image are skipped.
+fn fill_rect(canvas: &mut RgbImage, x: u32, y: u32, width: u32, height: u32, color: Rgb<u8>) {
+    for py in y..(y + height).min(canvas.height()) {
+        for px in x..(x + width).min(canvas.width()) {
+            canvas.put_pixel(px, py, color);
+        }
+    }
+}
+
+/// Line with square ends, pixel centers are at whole numbers.
+fn draw_line(canvas: &mut RgbImage, from: (f64, f64), to: (f64, f64), width: f64, color: Rgb<u8>) {
+    let steps = (to.0 - from.0).abs().max((to.1 - from.1).abs()).ceil().max(1.0) as u32;
+    let half = width / 2.0;
+
+    for step in 0..=steps {
+        let t = f64::from(step) / f64::from(steps);
+        let x = from.0 + t * (to.0 - from.0);
+        let y = from.1 + t * (to.1 - from.1);
+
+        let left = (x - half + 0.5).round().max(0.0) as u32;
+        let top = (y - half + 0.5).round().max(0.0) as u32;
+        let right = (x + half - 0.5).round();
+        let bottom = (y + half - 0.5).round();
+
+        if right >= 0.0 && bottom >= 0.0 {
+            fill_rect(canvas, left, top, (right as u32 + 1).saturating_sub(left), (bottom as u32 + 1).saturating_sub(top), color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray_image(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(width, height, Rgb([128, 128, 128])))
+    }
+
+    fn square() -> Vec<Coordinates> {
+        vec![
+            Coordinates { x: 20.0, y: 20.0 },
+            Coordinates { x: 60.0, y: 20.0 },
+            Coordinates { x: 60.0, y: 60.0 },
+            Coordinates { x: 20.0, y: 60.0 },
+        ]
+    }
+
+    #[test]
+    fn round_lengths() {
+        assert_eq!(round_length(7.0), 5.0);
+        assert_eq!(round_length(23.0), 20.0);
+        assert_eq!(round_length(150.0), 100.0);
+        assert_eq!(round_length(0.3), 0.2);
+    }
+
+    #[test]
+    fn outline_axis_and_scale_bar_are_drawn() {
+        let axis = Axis { x1: 30, y1: 40, x2: 50, y2: 40 };
+        let overlay = render_overlay(&gray_image(200, 100), &square(), &axis, 2.0, 800);
+
+        assert_eq!(overlay.dimensions(), (200, 100));
+        assert_eq!(*overlay.get_pixel(40, 20), OUTLINE_COLOR);
+        assert_eq!(*overlay.get_pixel(20, 40), OUTLINE_COLOR);
+        assert_eq!(*overlay.get_pixel(40, 40), AXIS_COLOR);
+        assert_eq!(*overlay.get_pixel(40, 30), Rgb([128, 128, 128]));
+
+        // 200 pixels of 2 micrometer: a bar of 100 micrometer is 50 pixels wide.
+        let bar_y = 100 - 5 * TEXT_SCALE - 1;
+        let bar: Vec<_> = (0..200).filter(|x| *overlay.get_pixel(*x, bar_y) == BAR_COLOR).collect();
+        assert_eq!(bar.len(), 50);
+    }
+
+    #[test]
+    fn overlay_is_reduced_with_the_outline() {
+        let overlay = render_overlay(&gray_image(400, 200), &square(), &Axis::default(), 1.0, 100);
+
+        assert_eq!(overlay.dimensions(), (100, 50));
+        assert_eq!(*overlay.get_pixel(10, 5), OUTLINE_COLOR);
+        assert_eq!(*overlay.get_pixel(12, 12), Rgb([128, 128, 128]));
+    }
+
+    #[test]
+    fn contact_sheet_layout() {
+        let cells: Vec<_> = (0..5).map(|i| (format!("#{}", i), RgbImage::from_pixel(50, 40, Rgb([0, 0, 255])))).collect();
+        let sheet = contact_sheet(&cells, 2);
+        let label_height = 5 * TEXT_SCALE + SHEET_PADDING / 2;
+
+        assert_eq!(sheet.width(), 2 * (50 + SHEET_PADDING) + SHEET_PADDING);
+        assert_eq!(sheet.height(), 3 * (40 + label_height + SHEET_PADDING) + SHEET_PADDING);
+        assert_eq!(*sheet.get_pixel(SHEET_PADDING, SHEET_PADDING + label_height), Rgb([0, 0, 255]));
+        assert_eq!(*sheet.get_pixel(sheet.width() - 1, sheet.height() - 1), SHEET_COLOR);
+    }
+}
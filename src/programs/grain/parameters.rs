@@ -0,0 +1,313 @@
+//! Grain parameters with a fixed set of values. The database stores them as their position in the
+//! list of names, like the numbers stored before they had their own types. The matlab input gets the names.
+
+use std::convert::TryFrom;
+
+use toml::value::{Table, Value};
+
+/// A parameter that is one of a fixed list of values.
+pub trait Choice: Sized + Copy + PartialEq + 'static {
+    /// All values, in the order of their numbers.
+    const ALL: &'static [Self];
+    /// Field name used in messages.
+    const FIELD: &'static str;
+
+    fn name(&self) -> &'static str;
+
+    fn code(&self) -> i32 {
+        Self::ALL.iter().position(|value| value == self).unwrap_or(0) as i32
+    }
+
+    fn from_code(code: i32) -> Option<Self> {
+        usize::try_from(code).ok().and_then(|index| Self::ALL.get(index)).cloned()
+    }
+
+    /// Accepts either the number or the name, ex. "1" or "zr" for the mineral.
+    fn from_name(value: &str) -> Option<Self> {
+        match value.parse::<i32>() {
+            Ok(code) => Self::from_code(code),
+            Err(_) => Self::ALL.iter().find(|choice| choice.name().eq_ignore_ascii_case(value)).cloned(),
+        }
+    }
+
+    /// Message for a value that is not in the list.
+    fn invalid(value: &str) -> String {
+        let names: Vec<_> = Self::ALL.iter().map(|choice| choice.name()).collect();
+        format!("{} must be one of {}, found '{}'", Self::FIELD, names.join(", "), value)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "i32", into = "i32")]
+pub enum Mode {
+    /// Whole or broken grains.
+    Normal,
+    /// Grains that were ground and polished.
+    Cut,
+}
+
+impl Choice for Mode {
+    const ALL: &'static [Mode] = &[Mode::Normal, Mode::Cut];
+    const FIELD: &'static str = "mode";
+
+    fn name(&self) -> &'static str {
+        match self {
+            Mode::Normal => "normal",
+            Mode::Cut => "cut",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "i32", into = "i32")]
+pub enum Mineral {
+    Apatite,
+    Zircon,
+}
+
+impl Choice for Mineral {
+    const ALL: &'static [Mineral] = &[Mineral::Apatite, Mineral::Zircon];
+    const FIELD: &'static str = "mineral";
+
+    fn name(&self) -> &'static str {
+        match self {
+            Mineral::Apatite => "ap",
+            Mineral::Zircon => "zr",
+        }
+    }
+}
+
+/// Orientation of the c-axis to the field of view.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "i32", into = "i32")]
+pub enum Orientation {
+    Parallel,
+    Perpendicular,
+}
+
+impl Choice for Orientation {
+    const ALL: &'static [Orientation] = &[Orientation::Parallel, Orientation::Perpendicular];
+    const FIELD: &'static str = "orientation";
+
+    fn name(&self) -> &'static str {
+        match self {
+            Orientation::Parallel => "parallel",
+            Orientation::Perpendicular => "perpendicular",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "i32", into = "i32")]
+pub enum Shape {
+    Hexagonal,
+    Ellipsoid,
+    Cylinder,
+    Block,
+}
+
+impl Choice for Shape {
+    const ALL: &'static [Shape] = &[Shape::Hexagonal, Shape::Ellipsoid, Shape::Cylinder, Shape::Block];
+    const FIELD: &'static str = "shape";
+
+    fn name(&self) -> &'static str {
+        match self {
+            Shape::Hexagonal => "hexagonal",
+            Shape::Ellipsoid => "ellipsoid",
+            Shape::Cylinder => "cylinder",
+            Shape::Block => "block",
+        }
+    }
+}
+
+/// Before the values were checked any number could be stored. Mode, mineral and orientation were read
+/// as "0 or not 0" and are loaded that way, an unknown shape is loaded as hexagonal.
+/// Changes the codes of one image record of the database and returns a message for each of them.
+pub fn repair_legacy_codes(record: &mut Table) -> Vec<String> {
+    let mut repaired = Vec::new();
+
+    repaired.extend(repair_code(record, Mode::Cut));
+    repaired.extend(repair_code(record, Mineral::Zircon));
+    repaired.extend(repair_code(record, Orientation::Perpendicular));
+    repaired.extend(repair_code(record, Shape::Hexagonal));
+
+    repaired
+}
+
+fn repair_code<T: Choice>(record: &mut Table, replacement: T) -> Option<String> {
+    let code = match record.get(T::FIELD) {
+        Some(Value::Integer(code)) => *code,
+        _ => return None,
+    };
+
+    if i32::try_from(code).ok().and_then(T::from_code).is_some() {
+        return None
+    }
+
+    record.insert(T::FIELD.to_string(), Value::Integer(replacement.code() as i64));
+    Some(format!("{} {} is not one of the codes, loaded as {}", T::FIELD, code, replacement.name()))
+}
+
+/// Conversions used by serde to keep the numbers in the database.
+fn choice_from_code<T: Choice>(code: i32) -> Result<T, String> {
+    T::from_code(code).ok_or_else(|| T::invalid(&code.to_string()))
+}
+
+impl TryFrom<i32> for Mode {
+    type Error = String;
+
+    fn try_from(code: i32) -> Result<Mode, String> {
+        choice_from_code(code)
+    }
+}
+
+impl From<Mode> for i32 {
+    fn from(value: Mode) -> i32 {
+        value.code()
+    }
+}
+
+impl TryFrom<i32> for Mineral {
+    type Error = String;
+
+    fn try_from(code: i32) -> Result<Mineral, String> {
+        choice_from_code(code)
+    }
+}
+
+impl From<Mineral> for i32 {
+    fn from(value: Mineral) -> i32 {
+        value.code()
+    }
+}
+
+impl TryFrom<i32> for Orientation {
+    type Error = String;
+
+    fn try_from(code: i32) -> Result<Orientation, String> {
+        choice_from_code(code)
+    }
+}
+
+impl From<Orientation> for i32 {
+    fn from(value: Orientation) -> i32 {
+        value.code()
+    }
+}
+
+impl TryFrom<i32> for Shape {
+    type Error = String;
+
+    fn try_from(code: i32) -> Result<Shape, String> {
+        choice_from_code(code)
+    }
+}
+
+impl From<Shape> for i32 {
+    fn from(value: Shape) -> i32 {
+        value.code()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{GrainList, ImageMetadata};
+    use toml;
+
+    /// An image as stored before the parameters had their own types.
+    const LEGACY_GRAIN: &str = r#"
+[[grains]]
+broken_tips = false
+file_name = "Test-1-perC.jpg"
+id = 2
+mineral = 1
+mode = 0
+orientation = 1
+pyramids = 2
+ratio_147_238 = 0.0615
+ratio_232_238 = 0.4444
+ratio_rim_core = 1.0
+rim_width = 0.0
+sample_name = "test1"
+shape = 3
+size = 0.3998
+user_id = 1
+zoned = false
+coordinate_file_name = "Test-1-perC.txt"
+
+[[grains.coordinates]]
+x = 229
+y = 122
+
+[grains.axis]
+x1 = 169
+x2 = 278
+y1 = 172
+y2 = 184
+"#;
+
+    #[test]
+    fn codes_and_names() {
+        assert_eq!(Shape::Block.code(), 3);
+        assert_eq!(Shape::from_code(3), Some(Shape::Block));
+        assert_eq!(Shape::from_code(4), None);
+        assert_eq!(Mineral::from_name("1"), Some(Mineral::Zircon));
+        assert_eq!(Mineral::from_name("ZR"), Some(Mineral::Zircon));
+        assert_eq!(Orientation::from_name("-1"), None);
+        assert_eq!(Mode::invalid("2"), "mode must be one of normal, cut, found '2'");
+    }
+
+    #[test]
+    fn legacy_numbers_are_read_and_written_back() {
+        let grain_list: GrainList = toml::from_str(LEGACY_GRAIN).unwrap();
+        let metadata = grain_list.grains[0].metadata();
+
+        assert_eq!((metadata.mode, metadata.mineral, metadata.orientation, metadata.shape),
+            (Mode::Normal, Mineral::Zircon, Orientation::Perpendicular, Shape::Block));
+
+        let stored = toml::Value::try_from(&grain_list).unwrap();
+        let grain = &stored["grains"][0];
+        assert_eq!((grain["mode"].as_integer(), grain["mineral"].as_integer(), grain["orientation"].as_integer(), grain["shape"].as_integer()),
+            (Some(0), Some(1), Some(1), Some(3)));
+
+        assert!(toml::from_str::<GrainList>(&LEGACY_GRAIN.replace("shape = 3", "shape = 7")).is_err());
+    }
+
+    #[test]
+    fn out_of_range_codes_are_repaired_per_record() {
+        let mut database: Value = LEGACY_GRAIN.replace("mode = 0", "mode = 2").replace("shape = 3", "shape = 7").parse().unwrap();
+        let record = database["grains"][0].as_table().cloned().unwrap();
+
+        let mut repaired = record.clone();
+        assert_eq!(repair_legacy_codes(&mut repaired), vec![
+            "mode 2 is not one of the codes, loaded as cut",
+            "shape 7 is not one of the codes, loaded as hexagonal",
+        ]);
+
+        let mut valid = LEGACY_GRAIN.parse::<Value>().unwrap()["grains"][0].as_table().cloned().unwrap();
+        assert!(repair_legacy_codes(&mut valid).is_empty());
+
+        if let Value::Array(grains) = &mut database["grains"] {
+            grains[0] = Value::Table(repaired);
+        }
+        let metadata = database.try_into::<GrainList>().unwrap().grains[0].metadata();
+        assert_eq!((metadata.mode, metadata.shape), (Mode::Cut, Shape::Hexagonal));
+    }
+
+    #[test]
+    fn validate_reports_each_field() {
+        let metadata = ImageMetadata { pyramids: 3, rim_width: 20.0, ..ImageMetadata::default() };
+        assert_eq!(metadata.validate(), vec![
+            "pyramids must be 0, 1 or 2, found 3",
+            "rim_width must be 0 for a grain that is not zoned, found 20",
+        ]);
+
+        let zoned = ImageMetadata { zoned: true, size: 0.0, ..ImageMetadata::default() };
+        assert_eq!(zoned.validate(), vec![
+            "size must be larger than 0, found 0",
+            "rim_width must be larger than 0 for a zoned grain, found 0",
+        ]);
+        assert!(ImageMetadata::default().validate().is_empty());
+    }
+}
@@ -0,0 +1,118 @@
+//! Checks of a sample before it is calculated, so that missing outlines or axes and contradicting
+//! parameters are reported to the user instead of being written to the input of the engines.
+//! A sample is one grain, seen with the c-axis parallel and perpendicular to the field of view.
+
+use super::measurements::axis_direction;
+use super::parameters::{Choice, Orientation, Shape};
+use super::{Axis, Coordinates, ImageMetadata};
+
+/// Problems of one image, empty if it can be calculated. The outline is the closed polygon in pixels of the working image.
+pub fn image_problems(metadata: &ImageMetadata, outline: &[Coordinates], axis: &Axis, working_pixel_size: f64) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if outline.len() < 3 {
+        problems.push("no outline stored".to_string());
+    }
+
+    if axis_direction(axis).is_none() {
+        problems.push("no c-axis stored".to_string());
+    }
+
+    if !(working_pixel_size.is_finite() && working_pixel_size > 0.0) {
+        problems.push(format!("the pixel size of the working image is unknown, found {}", working_pixel_size));
+    }
+
+    if matches!(metadata.shape, Shape::Ellipsoid | Shape::Cylinder) && metadata.pyramids != 0 {
+        problems.push(format!("a grain of shape {} has no pyramids, found {}", metadata.shape.name(), metadata.pyramids));
+    }
+
+    problems.extend(metadata.validate());
+    problems
+}
+
+/// Problems of the sample as a whole: both views must be present and all images must describe the same grain.
+pub fn sample_problems(images: &[ImageMetadata]) -> Vec<String> {
+    if images.is_empty() {
+        return vec!["the sample has no images".to_string()]
+    }
+
+    let mut problems = Vec::new();
+
+    for orientation in Orientation::ALL {
+        if !images.iter().any(|image| image.orientation == *orientation) {
+            problems.push(format!("no image with {} orientation of the c-axis", orientation.name()));
+        }
+    }
+
+    let parameters: Vec<_> = images.iter().map(grain_parameters).collect();
+
+    for (index, (field, _)) in parameters[0].iter().enumerate() {
+        let mut values: Vec<_> = parameters.iter().map(|image| image[index].1.as_str()).collect();
+        values.sort();
+        values.dedup();
+
+        if values.len() > 1 {
+            problems.push(format!("the images differ in {}: {}", field, values.join(", ")));
+        }
+    }
+
+    problems
+}
+
+/// Parameters that describe the grain rather than the image, with their field names.
+fn grain_parameters(image: &ImageMetadata) -> Vec<(&'static str, String)> {
+    vec![
+        ("mode", image.mode.name().to_string()),
+        ("mineral", image.mineral.name().to_string()),
+        ("ratio_232_238", image.ratio_232_238.to_string()),
+        ("ratio_147_238", image.ratio_147_238.to_string()),
+        ("shape", image.shape.name().to_string()),
+        ("pyramids", image.pyramids.to_string()),
+        ("broken_tips", image.broken_tips.to_string()),
+        ("zoned", image.zoned.to_string()),
+        ("rim_width", image.rim_width.to_string()),
+        ("ratio_rim_core", image.ratio_rim_core.to_string()),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::parameters::Mineral;
+
+    fn square() -> Vec<Coordinates> {
+        vec![Coordinates { x: 0.0, y: 0.0 }, Coordinates { x: 10.0, y: 0.0 }, Coordinates { x: 10.0, y: 10.0 }, Coordinates { x: 0.0, y: 10.0 }]
+    }
+
+    fn view(orientation: Orientation) -> ImageMetadata {
+        ImageMetadata { orientation, ..ImageMetadata::default() }
+    }
+
+    #[test]
+    fn image_without_outline_or_axis() {
+        let axis = Axis { x1: 0, y1: 0, x2: 10, y2: 0 };
+        assert!(image_problems(&ImageMetadata::default(), &square(), &axis, 1.0).is_empty());
+
+        let problems = image_problems(&ImageMetadata::default(), &[], &Axis::default(), 1.0);
+        assert_eq!(problems, vec!["no outline stored", "no c-axis stored"]);
+
+        let cylinder = ImageMetadata { shape: Shape::Cylinder, pyramids: 2, ..ImageMetadata::default() };
+        assert_eq!(image_problems(&cylinder, &square(), &axis, 0.0).len(), 2);
+    }
+
+    #[test]
+    fn sample_needs_both_views() {
+        assert_eq!(sample_problems(&[]), vec!["the sample has no images"]);
+        assert!(sample_problems(&[view(Orientation::Parallel), view(Orientation::Perpendicular)]).is_empty());
+        assert_eq!(sample_problems(&[view(Orientation::Parallel), view(Orientation::Parallel)]),
+            vec!["no image with perpendicular orientation of the c-axis"]);
+    }
+
+    #[test]
+    fn parameters_of_the_views_must_agree() {
+        let zircon = ImageMetadata { mineral: Mineral::Zircon, ratio_232_238: 1.5, ..view(Orientation::Perpendicular) };
+        let problems = sample_problems(&[view(Orientation::Parallel), zircon]);
+
+        assert_eq!(problems, vec!["the images differ in mineral: ap, zr", "the images differ in ratio_232_238: 0.4444, 1.5"]);
+    }
+}
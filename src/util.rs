@@ -2,6 +2,7 @@ use std::sync::{Mutex, MutexGuard};
 use std::fs::File;
 use std::io::{Read, BufReader};
 use std::{thread, time};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use serde::{Serialize};
 use handlebars::{Handlebars};
@@ -9,8 +10,10 @@ use failure;
 use rouille::{Response};
 use argon2;
 use toml;
+use chrono::{Local, TimeZone};
 
 use program_types::{ProgramType};
+use programs::grain;
 use error::{WebGuiError};
 use configuration;
 
@@ -30,8 +33,27 @@ struct User {
     email: String,
     passwd: String,
     allowed_programs: Vec<ProgramType>,
+    #[serde(default)]
+    role: UserRole,
 }
 
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub enum UserRole {
+    Admin,
+    #[default]
+    User,
+}
+
+impl UserRole {
+    pub fn name(&self) -> &str {
+        match self {
+            UserRole::Admin => "Admin",
+            UserRole::User => "User",
+        }
+    }
+}
+
+
 lazy_static! {
     static ref TEMPLATE : Handlebars = {
         let mut hb = Handlebars::new();
@@ -40,12 +62,17 @@ lazy_static! {
         hb.register_template_file("footer", "html/footer.hbs").unwrap();
         hb.register_template_file("pecube", "html/pecube.hbs").unwrap();
         hb.register_template_file("grain", "html/grain.hbs").unwrap();
+        hb.register_template_file("grain_samples", "html/grain_samples.hbs").unwrap();
+        hb.register_template_file("grain_projects", "html/grain_projects.hbs").unwrap();
         hb.register_template_file("grain_load_images", "html/grain_load_images.hbs").unwrap();
         hb.register_template_file("grain_outline_images", "html/grain_outline_images.hbs").unwrap();
+        hb.register_template_file("grain_edit_outline", "html/grain_edit_outline.hbs").unwrap();
+        hb.register_template_file("grain_edit_image", "html/grain_edit_image.hbs").unwrap();
         hb.register_template_file("grain_calculate", "html/grain_calculate.hbs").unwrap();
         hb.register_template_file("landlab", "html/landlab.hbs").unwrap();
         hb.register_template_file("icecascade", "html/icecascade.hbs").unwrap();
         hb.register_template_file("coupled", "html/coupled.hbs").unwrap();
+        hb.register_template_file("admin_usage", "html/admin_usage.hbs").unwrap();
         hb
     };
 
@@ -188,6 +215,59 @@ pub fn login_id(session_id: &str) -> Result<(String, u16), failure::Error> {
     }
 }
 
+pub fn user_name(user_id: u16) -> Result<String, failure::Error> {
+    debug!("utils.rs, user_name()");
+    let user_db = get_db_lock();
+
+    let user_names = user_db.iter()
+        .filter(|user| user.id == user_id)
+        .map(|user| user.login_id.clone()).collect::<Vec<_>>();
+
+    match user_names.len() {
+        0 => Err(WebGuiError::UserNotFound.into()),
+        1 => Ok(user_names[0].clone()),
+        _ => Err(WebGuiError::MultipleUsers.into()),
+    }
+}
+
+pub fn user_id(login_id: &str) -> Result<Option<u16>, failure::Error> {
+    debug!("utils.rs, user_id()");
+    let user_db = get_db_lock();
+
+    let user_ids = user_db.iter()
+        .filter(|user| user.login_id == login_id)
+        .map(|user| user.id).collect::<Vec<_>>();
+
+    match user_ids.len() {
+        0 => Ok(None),
+        1 => Ok(Some(user_ids[0])),
+        _ => Err(WebGuiError::MultipleUsers.into()),
+    }
+}
+
+pub fn user_role(user_id: u16) -> Result<UserRole, failure::Error> {
+    debug!("utils.rs, user_role()");
+    let user_db = get_db_lock();
+
+    let roles = user_db.iter()
+        .filter(|user| user.id == user_id)
+        .map(|user| user.role).collect::<Vec<_>>();
+
+    match roles.len() {
+        0 => Err(WebGuiError::UserNotFound.into()),
+        1 => Ok(roles[0]),
+        _ => Err(WebGuiError::MultipleUsers.into()),
+    }
+}
+
+pub fn list_of_users() -> Result<Vec<(u16, String, UserRole)>, failure::Error> {
+    debug!("utils.rs, list_of_users()");
+    let user_db = get_db_lock();
+
+    Ok(user_db.iter()
+        .map(|user| (user.id, user.login_id.clone(), user.role)).collect())
+}
+
 pub fn list_of_allowed_programs(user_id: u16) -> Result<Vec<ProgramType>, failure::Error> {
     debug!("utils.rs, login_id()");
     let user_db = get_db_lock();
@@ -234,10 +314,25 @@ pub fn get_menu_name<'a>(program: &ProgramType) -> &'a str {
     }
 }
 
-pub fn build_program_menu(allowed_programs: &Vec<ProgramType>) -> Vec<(&str, &str)> {
+pub fn build_program_menu(allowed_programs: &[ProgramType]) -> Vec<(&str, &str)> {
     allowed_programs.iter().map(|p| (get_template_name(p), get_menu_name(p))).collect::<Vec<_>>()
 }
 
+/// Storage usage as shown in the page header.
+pub fn build_usage(user_id: u16, user_name: &str) -> Result<serde_json::Value, failure::Error> {
+    let usage = grain::storage_usage(user_id, user_name)?;
+
+    Ok(json!({
+        "bytes": format_bytes(usage.bytes),
+        "max_bytes": usage.quota.max_bytes.map(format_bytes),
+        "images": usage.images,
+        "max_images": usage.quota.max_images,
+        "samples": usage.samples,
+        "max_samples": usage.quota.max_samples,
+        "show_overview": user_role(user_id)? == UserRole::Admin,
+    }))
+}
+
 pub fn show_program(session_id: &str, program: &ProgramType) -> Result<Response, failure::Error> {
     debug!("util.rs, show_program()");
 
@@ -246,7 +341,7 @@ pub fn show_program(session_id: &str, program: &ProgramType) -> Result<Response,
         let allowed_programs = list_of_allowed_programs(db_id)?;
 
         if allowed_programs.contains(&program) {
-            let user_menu = json!({"login_id": user_name, "programs": build_program_menu(&allowed_programs)});
+            let user_menu = json!({"login_id": user_name, "programs": build_program_menu(&allowed_programs), "usage": build_usage(db_id, &user_name)?});
             debug!("user_menu: {}", user_menu);
             Ok(Response::html(render(get_template_name(program), &user_menu)?))
         } else {
@@ -262,3 +357,31 @@ pub fn replace_characters(input: &str) -> String {
         c.is_ascii_alphanumeric() || *c == '_' || *c == '.' || *c == '-'
     ).collect()
 }
+
+/// Seconds since the unix epoch, used for all time stamps stored in the databases.
+pub fn timestamp_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+pub fn format_timestamp(timestamp: u64) -> String {
+    Local.timestamp_opt(timestamp as i64, 0).single()
+        .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_default()
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, units[0])
+    } else {
+        format!("{:.1} {}", value, units[unit])
+    }
+}